@@ -0,0 +1,608 @@
+use crate::{
+    items::{Inventory, ItemId, ItemRegistry},
+    map::{
+        ChunkManager, Generated, Ground, GroundManager, STRUCTURE_LAYER_LEVEL, Structure,
+        StructureKind, StructureManager, WorldSeed, cursor_world_pos, get_neighbors,
+        place_structure, rounded_tile_pos_to_world, spawn_structure, world_pos_to_rounded_tile,
+    },
+    pathfinding::PathfindingAgent,
+    units::{
+        Unit,
+        jobs::{AssignedJob, JobBoard, assign_jobs_system, release_finished_jobs_system},
+        states::Available,
+        tasks::{
+            Action, ActionQueue, CurrentAction, CurrentTask, FailureReason, Reservations, Task,
+            TaskKind, process_current_action_system, reset_actions_system,
+        },
+    },
+};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+pub const GHOST_VALID_COLOR: Color = Color::srgba(1.0, 1.0, 1.0, 0.5);
+pub const GHOST_INVALID_COLOR: Color = Color::srgba(1.0, 0.2, 0.2, 0.5);
+pub const BLUEPRINT_COLOR: Color = Color::srgba(0.5, 0.7, 1.0, 0.6);
+const GHOST_LAYER_LEVEL: f32 = STRUCTURE_LAYER_LEVEL + 5.0;
+pub const CONSTRUCTION_PRIORITY: i32 = 1;
+
+pub struct BuildPlugin;
+
+impl Plugin for BuildPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BuildMode>()
+            .init_resource::<BlueprintManager>()
+            .add_event::<BuildCommand>()
+            .add_systems(
+                Update,
+                (
+                    select_build_mode_system,
+                    update_build_ghost_system.after(select_build_mode_system),
+                    mouse_build_system.after(select_build_mode_system),
+                    apply_build_commands_system.after(mouse_build_system),
+                ),
+            )
+            .add_systems(
+                FixedUpdate,
+                (
+                    complete_blueprints_system.after(process_current_action_system),
+                    construction_jobs_system
+                        .after(release_finished_jobs_system)
+                        .after(assign_jobs_system),
+                ),
+            );
+    }
+}
+
+/// what a left click does ; B cycles between the structures, X deconstructs, Escape stops
+/// the number keys are used by the control groups
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BuildMode {
+    #[default]
+    None,
+    Place(StructureKind),
+    Deconstruct,
+}
+
+/// sent by the mouse ; can also be sent by the code to build without the UI
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BuildCommand {
+    Place {
+        kind: StructureKind,
+        rounded_tile_pos: IVec2,
+    },
+    Deconstruct {
+        rounded_tile_pos: IVec2,
+    },
+}
+
+/// sprite showing where the structure will be placed
+#[derive(Component)]
+pub struct BuildGhost;
+
+/// structure waiting for a unit to build it ; units can walk through it until it is complete
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Blueprint {
+    pub kind: StructureKind,
+    pub rounded_tile_pos: IVec2,
+    /// a builder consumed the materials of `StructureKind::build_cost`
+    pub materials_delivered: bool,
+    pub remaining_ticks: u32,
+}
+
+impl Blueprint {
+    pub fn new(kind: StructureKind, rounded_tile_pos: IVec2) -> Self {
+        Self {
+            kind,
+            rounded_tile_pos,
+            materials_delivered: false,
+            remaining_ticks: kind.build_ticks(),
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.materials_delivered && self.remaining_ticks == 0
+    }
+}
+
+/// blueprints by tile ; they aren't in the StructureManager so they don't block the units
+#[derive(Resource, Default)]
+pub struct BlueprintManager {
+    pub blueprints: HashMap<IVec2, Entity>, // rounded_tile_pos -> blueprint
+}
+
+/// the tile must be empty and without unit on it
+pub fn can_place_structure(
+    rounded_tile_pos: IVec2,
+    structure_manager: &StructureManager,
+    blueprint_manager: &BlueprintManager,
+    mut unit_tile_positions: impl Iterator<Item = IVec2>,
+) -> bool {
+    !structure_manager.structures.contains_key(&rounded_tile_pos)
+        && !blueprint_manager.blueprints.contains_key(&rounded_tile_pos)
+        && !unit_tile_positions.any(|unit_tile_pos| unit_tile_pos == rounded_tile_pos)
+}
+
+pub fn spawn_blueprint(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    blueprint_manager: &mut BlueprintManager,
+    blueprint: Blueprint,
+) -> Entity {
+    let world_pos = rounded_tile_pos_to_world(blueprint.rounded_tile_pos);
+    let rounded_tile_pos = blueprint.rounded_tile_pos;
+    let blueprint_ent = commands
+        .spawn((
+            Sprite {
+                image: asset_server.load(blueprint.kind.sprite_path()),
+                color: BLUEPRINT_COLOR,
+                ..default()
+            },
+            Transform::from_translation(world_pos.extend(STRUCTURE_LAYER_LEVEL)),
+            blueprint,
+        ))
+        .id();
+    blueprint_manager
+        .blueprints
+        .insert(rounded_tile_pos, blueprint_ent);
+    blueprint_ent
+}
+
+/// tile next to the blueprint where the builder stands ; None if no tile around it can be walked on
+pub fn build_position(
+    rounded_tile_pos: IVec2,
+    structure_manager: &StructureManager,
+    blueprint_manager: &BlueprintManager,
+    ground: &Ground,
+) -> Option<IVec2> {
+    get_neighbors(rounded_tile_pos).find(|neighbor| {
+        !structure_manager.structures.contains_key(neighbor)
+            && !blueprint_manager.blueprints.contains_key(neighbor)
+            && ground.movement_cost(*neighbor).is_some()
+    })
+}
+
+/// fetches the materials then builds next to the blueprint ; PathUnreachable if there is no build_position
+pub fn construction_task(
+    blueprint_ent: Entity,
+    blueprint: &Blueprint,
+    structure_manager: &StructureManager,
+    blueprint_manager: &BlueprintManager,
+    ground: &Ground,
+) -> Result<Task, FailureReason> {
+    // a previous builder may have brought them already
+    let materials = if blueprint.materials_delivered {
+        Vec::new()
+    } else {
+        blueprint.kind.build_cost()
+    };
+    let mut sub_tasks: Vec<Task> = materials
+        .into_iter()
+        .map(|(kind, quantity)| Task::new(TaskKind::GetItems { kind, quantity }, Vec::new()))
+        .collect();
+    // the builder must not stand on the tile when the structure appears
+    let build_pos = build_position(
+        blueprint.rounded_tile_pos,
+        structure_manager,
+        blueprint_manager,
+        ground,
+    )
+    .ok_or(FailureReason::PathUnreachable)?;
+    sub_tasks.push(Task::new(
+        TaskKind::Action(Action::MoveTo(build_pos)),
+        Vec::new(),
+    ));
+    sub_tasks.push(Task::new(
+        TaskKind::Action(Action::Build {
+            blueprint: blueprint_ent,
+        }),
+        Vec::new(),
+    ));
+    Ok(Task::sequence(sub_tasks))
+}
+
+fn cursor_rounded_tile_pos(
+    windows: &Query<&Window>,
+    cameras: &Query<(&Camera, &GlobalTransform)>,
+) -> Option<IVec2> {
+    cursor_world_pos(windows, cameras).map(world_pos_to_rounded_tile)
+}
+
+fn select_build_mode_system(input: Res<ButtonInput<KeyCode>>, mut build_mode: ResMut<BuildMode>) {
+    let new_build_mode = if input.just_pressed(KeyCode::KeyB) {
+        match *build_mode {
+            BuildMode::Place(StructureKind::Wall) => BuildMode::Place(StructureKind::Chest),
+            BuildMode::Place(StructureKind::Chest) => BuildMode::Place(StructureKind::Crafter),
+            BuildMode::Place(StructureKind::Crafter) => BuildMode::None,
+            BuildMode::None | BuildMode::Deconstruct => BuildMode::Place(StructureKind::Wall),
+        }
+    } else if input.just_pressed(KeyCode::KeyX) {
+        BuildMode::Deconstruct
+    } else if input.just_pressed(KeyCode::Escape) {
+        BuildMode::None
+    } else {
+        return;
+    };
+    if *build_mode != new_build_mode {
+        info!("Build mode: {:?}", new_build_mode);
+        *build_mode = new_build_mode;
+    }
+}
+
+/// moves the ghost under the cursor, red if the structure can't be placed there
+#[allow(clippy::too_many_arguments)]
+fn update_build_ghost_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    build_mode: Res<BuildMode>,
+    structure_manager: Res<StructureManager>,
+    blueprint_manager: Res<BlueprintManager>,
+    windows: Query<&Window>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    unit_query: Query<&Transform, (With<Unit>, Without<BuildGhost>)>,
+    mut ghost_query: Query<(Entity, &mut Transform, &mut Sprite), With<BuildGhost>>,
+) {
+    let (BuildMode::Place(kind), Some(rounded_tile_pos)) =
+        (*build_mode, cursor_rounded_tile_pos(&windows, &cameras))
+    else {
+        for (ghost_ent, _, _) in ghost_query.iter() {
+            commands.entity(ghost_ent).despawn();
+        }
+        return;
+    };
+
+    let valid = can_place_structure(
+        rounded_tile_pos,
+        &structure_manager,
+        &blueprint_manager,
+        unit_query
+            .iter()
+            .map(|transform| world_pos_to_rounded_tile(transform.translation.xy())),
+    );
+    let translation = rounded_tile_pos_to_world(rounded_tile_pos).extend(GHOST_LAYER_LEVEL);
+    let color = if valid {
+        GHOST_VALID_COLOR
+    } else {
+        GHOST_INVALID_COLOR
+    };
+    let image = asset_server.load(kind.sprite_path());
+
+    match ghost_query.single_mut() {
+        Ok((_, mut transform, mut sprite)) => {
+            transform.translation = translation;
+            sprite.color = color;
+            // the kind may have changed
+            sprite.image = image;
+        }
+        Err(_) => {
+            commands.spawn((
+                BuildGhost,
+                Sprite {
+                    image,
+                    color,
+                    ..default()
+                },
+                Transform::from_translation(translation),
+            ));
+        }
+    }
+}
+
+/// left click sends a BuildCommand for the tile under the cursor
+fn mouse_build_system(
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    build_mode: Res<BuildMode>,
+    windows: Query<&Window>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    mut build_command_events: EventWriter<BuildCommand>,
+) {
+    if !mouse_input.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let Some(rounded_tile_pos) = cursor_rounded_tile_pos(&windows, &cameras) else {
+        return;
+    };
+    match *build_mode {
+        BuildMode::None => {}
+        BuildMode::Place(kind) => {
+            build_command_events.write(BuildCommand::Place {
+                kind,
+                rounded_tile_pos,
+            });
+        }
+        BuildMode::Deconstruct => {
+            build_command_events.write(BuildCommand::Deconstruct { rounded_tile_pos });
+        }
+    }
+}
+
+/// the structures to deconstruct and the chests getting back the materials of removed blueprints
+type StructureQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        Has<Generated>,
+        &'static GlobalTransform,
+        Option<&'static mut Inventory>,
+    ),
+    With<Structure>,
+>;
+
+/// checks the BuildCommands again (the tile may have changed since the click) and applies them
+/// placing a structure only spawns its blueprint and posts the construction job
+#[allow(clippy::too_many_arguments)]
+pub fn apply_build_commands_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut build_command_events: EventReader<BuildCommand>,
+    mut structure_manager: ResMut<StructureManager>,
+    mut blueprint_manager: ResMut<BlueprintManager>,
+    ground_manager: Res<GroundManager>,
+    item_registry: Res<ItemRegistry>,
+    mut reservations: ResMut<Reservations>,
+    mut job_board: ResMut<JobBoard>,
+    unit_query: Query<&Transform, With<Unit>>,
+    mut structure_query: StructureQuery,
+    blueprint_query: Query<&Blueprint>,
+) {
+    for build_command in build_command_events.read() {
+        match *build_command {
+            BuildCommand::Place {
+                kind,
+                rounded_tile_pos,
+            } => {
+                if !can_place_structure(
+                    rounded_tile_pos,
+                    &structure_manager,
+                    &blueprint_manager,
+                    unit_query
+                        .iter()
+                        .map(|transform| world_pos_to_rounded_tile(transform.translation.xy())),
+                ) {
+                    warn!("Can't build {:?} at {}", kind, rounded_tile_pos);
+                    continue;
+                }
+                if build_position(
+                    rounded_tile_pos,
+                    &structure_manager,
+                    &blueprint_manager,
+                    &ground_manager.ground,
+                )
+                .is_none()
+                {
+                    warn!(
+                        "Can't build {:?} at {}: no tile to build it from",
+                        kind, rounded_tile_pos
+                    );
+                    continue;
+                }
+                let blueprint = Blueprint::new(kind, rounded_tile_pos);
+                let blueprint_ent = spawn_blueprint(
+                    &mut commands,
+                    &asset_server,
+                    &mut blueprint_manager,
+                    blueprint.clone(),
+                );
+                if let Ok(task) = construction_task(
+                    blueprint_ent,
+                    &blueprint,
+                    &structure_manager,
+                    &blueprint_manager,
+                    &ground_manager.ground,
+                ) {
+                    job_board.post(task, CONSTRUCTION_PRIORITY, Some(rounded_tile_pos));
+                }
+            }
+            BuildCommand::Deconstruct { rounded_tile_pos } => {
+                // its job is cancelled by construction_jobs_system
+                if let Some(blueprint_ent) = blueprint_manager.blueprints.remove(&rounded_tile_pos)
+                {
+                    if let Ok(blueprint) = blueprint_query.get(blueprint_ent)
+                        && blueprint.materials_delivered
+                    {
+                        let lost = refund_to_closest_chests(
+                            rounded_tile_pos,
+                            blueprint.kind.build_cost(),
+                            &mut structure_query,
+                            &item_registry,
+                        );
+                        for (kind, quantity) in lost {
+                            warn!(
+                                "{} {} of the blueprint at {} don't fit in any chest",
+                                quantity,
+                                item_registry.display_name(&kind),
+                                rounded_tile_pos
+                            );
+                        }
+                    }
+                    commands.entity(blueprint_ent).despawn();
+                    continue;
+                }
+                let Some(&structure_ent) = structure_manager.structures.get(&rounded_tile_pos)
+                else {
+                    continue;
+                };
+                let Ok((_, is_generated, _, inventory)) = structure_query.get(structure_ent) else {
+                    continue;
+                };
+                // the items would be lost with the chest
+                if inventory.is_some_and(|inventory| !inventory.is_empty()) {
+                    warn!(
+                        "Can't deconstruct the chest at {}: it isn't empty",
+                        rounded_tile_pos
+                    );
+                    continue;
+                }
+                reservations.release_all_for_chest(structure_ent);
+                if is_generated {
+                    structure_manager.remove_generated(rounded_tile_pos);
+                } else {
+                    structure_manager.remove(rounded_tile_pos);
+                }
+                commands.entity(structure_ent).despawn();
+            }
+        }
+    }
+}
+
+/// adds the items to the chests, the closest first ; returns what doesn't fit anywhere
+fn refund_to_closest_chests(
+    rounded_tile_pos: IVec2,
+    items: Vec<(ItemId, u32)>,
+    structure_query: &mut StructureQuery,
+    item_registry: &ItemRegistry,
+) -> Vec<(ItemId, u32)> {
+    let mut chests: Vec<(Entity, i32)> = structure_query
+        .iter()
+        .filter(|(_, _, _, inventory)| inventory.is_some())
+        .map(|(chest_ent, _, global_transform, _)| {
+            let chest_tile_pos = world_pos_to_rounded_tile(global_transform.translation().xy());
+            (
+                chest_ent,
+                (chest_tile_pos - rounded_tile_pos).length_squared(),
+            )
+        })
+        .collect();
+    chests.sort_by_key(|(_, distance)| *distance);
+
+    let mut remaining = items;
+    for (chest_ent, _) in chests {
+        let Ok((_, _, _, Some(mut inventory))) = structure_query.get_mut(chest_ent) else {
+            continue;
+        };
+        for (kind, quantity) in remaining.iter_mut() {
+            *quantity = inventory.add(*kind, *quantity, item_registry);
+        }
+        remaining.retain(|(_, quantity)| *quantity > 0);
+        if remaining.is_empty() {
+            break;
+        }
+    }
+    remaining
+}
+
+/// cancels the construction jobs of the removed blueprints, and posts a new job for the blueprints without one
+/// (their job was dropped after failing, or there was nowhere to build them from)
+#[allow(clippy::too_many_arguments)]
+pub fn construction_jobs_system(
+    mut commands: Commands,
+    mut reservations: ResMut<Reservations>,
+    mut job_board: ResMut<JobBoard>,
+    structure_manager: Res<StructureManager>,
+    blueprint_manager: Res<BlueprintManager>,
+    ground_manager: Res<GroundManager>,
+    blueprint_query: Query<(Entity, &Blueprint)>,
+    mut unit_query: Query<
+        (
+            Entity,
+            &mut CurrentTask,
+            &mut CurrentAction,
+            &mut ActionQueue,
+            &mut PathfindingAgent,
+        ),
+        With<Unit>,
+    >,
+) {
+    let built_blueprint = |task: &Task| match task.kind {
+        TaskKind::Action(Action::Build { blueprint }) => Some(blueprint),
+        _ => None,
+    };
+
+    job_board.jobs.retain(|job| {
+        job.task
+            .iter()
+            .filter_map(built_blueprint)
+            .all(|blueprint_ent| blueprint_query.contains(blueprint_ent))
+    });
+    for (unit_ent, mut current_task, mut current_action, mut action_queue, mut pathfinding_agent) in
+        unit_query.iter_mut()
+    {
+        let Some(task) = &current_task.task else {
+            continue;
+        };
+        if task
+            .iter()
+            .filter_map(built_blueprint)
+            .all(|blueprint_ent| blueprint_query.contains(blueprint_ent))
+        {
+            continue;
+        }
+        reservations.release_all_for_owner(unit_ent);
+        reset_actions_system(
+            &mut action_queue,
+            &mut current_action,
+            &mut pathfinding_agent,
+        );
+        current_task.reset();
+        commands
+            .entity(unit_ent)
+            .try_remove::<AssignedJob>()
+            .try_insert(Available);
+    }
+
+    let mut active_blueprints: HashSet<Entity> =
+        job_board.tasks().filter_map(built_blueprint).collect();
+    for (_, current_task, _, _, _) in unit_query.iter() {
+        if let Some(task) = &current_task.task {
+            active_blueprints.extend(task.iter().filter_map(built_blueprint));
+        }
+    }
+    for (blueprint_ent, blueprint) in blueprint_query.iter() {
+        if blueprint.is_complete() || active_blueprints.contains(&blueprint_ent) {
+            continue;
+        }
+        if let Ok(task) = construction_task(
+            blueprint_ent,
+            blueprint,
+            &structure_manager,
+            &blueprint_manager,
+            &ground_manager.ground,
+        ) {
+            job_board.post(
+                task,
+                CONSTRUCTION_PRIORITY,
+                Some(blueprint.rounded_tile_pos),
+            );
+        }
+    }
+}
+
+/// replaces the complete blueprints by their structure once no unit stands on the tile
+#[allow(clippy::too_many_arguments)]
+pub fn complete_blueprints_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut structure_manager: ResMut<StructureManager>,
+    mut chunk_manager: ResMut<ChunkManager>,
+    mut blueprint_manager: ResMut<BlueprintManager>,
+    world_seed: Res<WorldSeed>,
+    blueprint_query: Query<(Entity, &Blueprint)>,
+    unit_query: Query<&Transform, With<Unit>>,
+) {
+    for (blueprint_ent, blueprint) in blueprint_query.iter() {
+        if !blueprint.is_complete() {
+            continue;
+        }
+        let rounded_tile_pos = blueprint.rounded_tile_pos;
+        // waits for the units to leave the tile
+        if unit_query.iter().any(|transform| {
+            world_pos_to_rounded_tile(transform.translation.xy()) == rounded_tile_pos
+        }) {
+            continue;
+        }
+
+        blueprint_manager.blueprints.remove(&rounded_tile_pos);
+        commands.entity(blueprint_ent).despawn();
+        let structure_ent = spawn_structure(&mut commands, &asset_server, blueprint.kind);
+        place_structure(
+            &mut commands,
+            &asset_server,
+            &structure_ent,
+            &mut structure_manager,
+            &mut chunk_manager,
+            &world_seed,
+            rounded_tile_pos,
+        );
+    }
+}
@@ -0,0 +1,198 @@
+// ========= coordinates =========
+// world_pos = (5.5 * TILE_SIZE.X, 0.5 * TILE_SIZE.y) | tile_pos = (5, 0) | chunk_pos = (0, 0) | local_tile_pos = (5, 0)
+// every conversion to a coarser grid rounds toward -infinity (floor / div_euclid), so (-1, -1) is in chunk (-1, -1) and not (0, 0)
+
+use crate::map::{CHUNK_SIZE, TILE_SIZE};
+use bevy::prelude::*;
+
+/// position in pixels
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WorldPos(pub Vec2);
+
+/// tile containing a position ; the same as the old `rounded_tile_pos`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TilePos(pub IVec2);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ChunkPos(pub IVec2);
+
+/// tile position inside its chunk, in [0, CHUNK_SIZE)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct LocalTilePos(pub UVec2);
+
+impl WorldPos {
+    pub fn to_tile(self) -> TilePos {
+        TilePos(IVec2::new(
+            (self.0.x / TILE_SIZE.x).floor() as i32,
+            (self.0.y / TILE_SIZE.y).floor() as i32,
+        ))
+    }
+
+    // goes through the tile so a position is always in the chunk of its tile
+    pub fn to_chunk(self) -> ChunkPos {
+        self.to_tile().to_chunk()
+    }
+}
+
+impl TilePos {
+    /// center of the tile ; where entities standing on it are drawn
+    pub fn to_world(self) -> WorldPos {
+        WorldPos(Vec2::new(
+            self.0.x as f32 * TILE_SIZE.x + 0.5 * TILE_SIZE.x,
+            self.0.y as f32 * TILE_SIZE.y + 0.5 * TILE_SIZE.y,
+        ))
+    }
+
+    pub fn to_chunk(self) -> ChunkPos {
+        ChunkPos(IVec2::new(
+            self.0.x.div_euclid(CHUNK_SIZE.x as i32),
+            self.0.y.div_euclid(CHUNK_SIZE.y as i32),
+        ))
+    }
+
+    pub fn to_local(self) -> LocalTilePos {
+        LocalTilePos(UVec2::new(
+            self.0.x.rem_euclid(CHUNK_SIZE.x as i32) as u32,
+            self.0.y.rem_euclid(CHUNK_SIZE.y as i32) as u32,
+        ))
+    }
+}
+
+impl ChunkPos {
+    /// tile at the bottom left corner of the chunk
+    pub fn origin_tile(self) -> TilePos {
+        TilePos(IVec2::new(
+            self.0.x * CHUNK_SIZE.x as i32,
+            self.0.y * CHUNK_SIZE.y as i32,
+        ))
+    }
+
+    pub fn tile(self, local_tile_pos: LocalTilePos) -> TilePos {
+        TilePos(self.origin_tile().0 + local_tile_pos.0.as_ivec2())
+    }
+
+    pub fn contains(self, tile_pos: TilePos) -> bool {
+        tile_pos.to_chunk() == self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{
+        camera_pos_to_rounded_chunk_pos, local_tile_pos_to_rounded_tile,
+        rounded_chunk_pos_to_rounded_tile, rounded_tile_pos_to_rounded_chunk,
+        rounded_tile_pos_to_world, tile_pos_to_rounded_chunk, tile_pos_to_rounded_tile,
+        tile_pos_to_world, world_pos_to_rounded_chunk, world_pos_to_rounded_tile,
+        world_pos_to_tile,
+    };
+    use proptest::prelude::*;
+
+    // keeps the f32 conversions exact
+    const MAX_TILE: i32 = 1 << 16;
+
+    fn tile_coordinate() -> impl Strategy<Value = i32> {
+        -MAX_TILE..MAX_TILE
+    }
+
+    fn world_coordinate() -> impl Strategy<Value = f32> {
+        -(MAX_TILE as f32 * TILE_SIZE.x)..(MAX_TILE as f32 * TILE_SIZE.x)
+    }
+
+    #[test]
+    fn negative_tiles_are_in_negative_chunks() {
+        let chunk_size = CHUNK_SIZE.x as i32;
+        assert_eq!(
+            TilePos(IVec2::new(-1, -1)).to_chunk(),
+            ChunkPos(IVec2::new(-1, -1))
+        );
+        assert_eq!(
+            TilePos(IVec2::new(-chunk_size, 0)).to_chunk(),
+            ChunkPos(IVec2::new(-1, 0))
+        );
+        assert_eq!(
+            TilePos(IVec2::new(-chunk_size - 1, chunk_size)).to_chunk(),
+            ChunkPos(IVec2::new(-2, 1))
+        );
+        assert_eq!(
+            TilePos(IVec2::new(-1, -1)).to_local(),
+            LocalTilePos(UVec2::new(CHUNK_SIZE.x - 1, CHUNK_SIZE.y - 1))
+        );
+        assert_eq!(
+            rounded_tile_pos_to_rounded_chunk(IVec2::new(-1, 5)),
+            IVec2::new(-1, 0)
+        );
+        assert_eq!(
+            camera_pos_to_rounded_chunk_pos(&Vec2::new(-1.0, -1.0)),
+            IVec2::new(-1, -1)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn tile_is_inside_its_chunk(x in tile_coordinate(), y in tile_coordinate()) {
+            let tile_pos = TilePos(IVec2::new(x, y));
+            let chunk_pos = tile_pos.to_chunk();
+            let origin = chunk_pos.origin_tile().0;
+            prop_assert!(origin.x <= x && x < origin.x + CHUNK_SIZE.x as i32);
+            prop_assert!(origin.y <= y && y < origin.y + CHUNK_SIZE.y as i32);
+            prop_assert!(chunk_pos.contains(tile_pos));
+        }
+
+        #[test]
+        fn chunk_and_local_give_back_the_tile(x in tile_coordinate(), y in tile_coordinate()) {
+            let tile_pos = TilePos(IVec2::new(x, y));
+            let local_tile_pos = tile_pos.to_local();
+            prop_assert!(local_tile_pos.0.x < CHUNK_SIZE.x && local_tile_pos.0.y < CHUNK_SIZE.y);
+            prop_assert_eq!(tile_pos.to_chunk().tile(local_tile_pos), tile_pos);
+            prop_assert_eq!(
+                local_tile_pos_to_rounded_tile(local_tile_pos.0.as_ivec2(), tile_pos.to_chunk().0),
+                tile_pos.0
+            );
+        }
+
+        #[test]
+        fn chunk_origin_roundtrip(x in -(1 << 10)..(1 << 10), y in -(1 << 10)..(1 << 10)) {
+            let chunk_pos = ChunkPos(IVec2::new(x, y));
+            prop_assert_eq!(chunk_pos.origin_tile().to_chunk(), chunk_pos);
+            prop_assert_eq!(chunk_pos.origin_tile().to_local(), LocalTilePos(UVec2::ZERO));
+            prop_assert_eq!(rounded_chunk_pos_to_rounded_tile(&chunk_pos.0), chunk_pos.origin_tile().0);
+        }
+
+        #[test]
+        fn tile_center_roundtrip(x in tile_coordinate(), y in tile_coordinate()) {
+            let tile_pos = TilePos(IVec2::new(x, y));
+            prop_assert_eq!(tile_pos.to_world().to_tile(), tile_pos);
+            prop_assert_eq!(rounded_tile_pos_to_world(tile_pos.0), tile_pos.to_world().0);
+            prop_assert_eq!(world_pos_to_rounded_tile(rounded_tile_pos_to_world(tile_pos.0)), tile_pos.0);
+        }
+
+        #[test]
+        fn world_pos_is_inside_its_tile(x in world_coordinate(), y in world_coordinate()) {
+            let world_pos = WorldPos(Vec2::new(x, y));
+            let tile_pos = world_pos.to_tile();
+            let corner = tile_pos.to_world().0 - 0.5 * Vec2::new(TILE_SIZE.x, TILE_SIZE.y);
+            prop_assert!(corner.x <= x && x < corner.x + TILE_SIZE.x);
+            prop_assert!(corner.y <= y && y < corner.y + TILE_SIZE.y);
+            prop_assert_eq!(world_pos_to_rounded_tile(world_pos.0), tile_pos.0);
+        }
+
+        #[test]
+        fn world_to_chunk_conversions_agree(x in world_coordinate(), y in world_coordinate()) {
+            let world_pos = Vec2::new(x, y);
+            let expected = WorldPos(world_pos).to_tile().to_chunk().0;
+            prop_assert_eq!(world_pos_to_rounded_chunk(&world_pos), expected);
+            prop_assert_eq!(camera_pos_to_rounded_chunk_pos(&world_pos), expected);
+            prop_assert_eq!(rounded_tile_pos_to_rounded_chunk(world_pos_to_rounded_tile(world_pos)), expected);
+            prop_assert_eq!(tile_pos_to_rounded_chunk(world_pos_to_tile(world_pos)), expected);
+        }
+
+        #[test]
+        fn fractional_tile_conversions_agree(x in world_coordinate(), y in world_coordinate()) {
+            let world_pos = Vec2::new(x, y);
+            let tile_pos = world_pos_to_tile(world_pos);
+            prop_assert_eq!(tile_pos_to_rounded_tile(tile_pos), world_pos_to_rounded_tile(world_pos));
+            prop_assert!((tile_pos_to_world(tile_pos) - world_pos).abs().max_element() < 1e-3 * TILE_SIZE.x.max(x.abs()));
+        }
+    }
+}
@@ -1,54 +1,390 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    path::Path,
+    sync::{LazyLock, Mutex},
+};
 
 use bevy::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+use crate::UPS_TARGET;
+
+pub const ITEMS_PATH: &str = "assets/items.ron";
+
+pub struct ItemsPlugin;
+
+impl Plugin for ItemsPlugin {
+    fn build(&self, app: &mut App) {
+        let item_registry = ItemRegistry::load(Path::new(ITEMS_PATH))
+            .unwrap_or_else(|error| panic!("Can't load {}: {}", ITEMS_PATH, error));
+        app.insert_resource(item_registry)
+            .add_event::<ItemsTransferred>();
+    }
+}
+
+/// marker of the unique item entities ; they have no Transform and live in an Inventory
 #[derive(Component)]
 pub struct Item;
 
-/// for stackable items
-#[derive(Component, Clone, Copy, Debug, Hash, PartialEq, Eq)]
-pub enum ItemKind {
-    Rock,
+/// id of a stackable item in the ItemRegistry, written as its name in the data files ("rock")
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct ItemId(&'static str);
+
+impl ItemId {
+    // items used by the code ; they must be in the registry
+    pub const ROCK: ItemId = ItemId("rock");
+    pub const CHEST: ItemId = ItemId("chest");
+    pub const CORE: [ItemId; 2] = [ItemId::ROCK, ItemId::CHEST];
+
+    /// the same name always gives the same id
+    pub fn new(name: &str) -> Self {
+        if let Some(core_id) = Self::CORE.iter().find(|core_id| core_id.0 == name) {
+            return *core_id;
+        }
+        // ids are never freed but there is only one per item definition
+        static NAMES: LazyLock<Mutex<HashSet<&'static str>>> =
+            LazyLock::new(|| Mutex::new(HashSet::new()));
+        let mut names = NAMES.lock().unwrap();
+        match names.get(name) {
+            Some(interned) => ItemId(interned),
+            None => {
+                let interned: &'static str = Box::leak(name.to_string().into_boxed_str());
+                names.insert(interned);
+                ItemId(interned)
+            }
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.0
+    }
+}
+
+impl fmt::Display for ItemId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Serialize for ItemId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for ItemId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(ItemId::new(&name))
+    }
+}
+
+/// what the game knows about a stackable item
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ItemDefinition {
+    pub display_name: String,
+    pub icon: String, // path in the assets
+    pub stack_size: u32,
+    pub weight: f32,
+    pub volume: f32,
+}
+
+#[derive(Debug)]
+pub enum ItemRegistryError {
+    Io(std::io::Error),
+    Deserialize(ron::error::SpannedError),
+    MissingCoreItem(ItemId),
+}
+
+impl fmt::Display for ItemRegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ItemRegistryError::Io(error) => write!(f, "io error: {}", error),
+            ItemRegistryError::Deserialize(error) => write!(f, "can't parse the items: {}", error),
+            ItemRegistryError::MissingCoreItem(item_id) => {
+                write!(f, "core item {} isn't defined", item_id)
+            }
+        }
+    }
+}
+
+/// every stackable item, loaded from ITEMS_PATH
+#[derive(Resource, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ItemRegistry {
+    pub items: HashMap<ItemId, ItemDefinition>,
+}
+
+impl ItemRegistry {
+    pub fn load(path: &Path) -> Result<Self, ItemRegistryError> {
+        let text = fs::read_to_string(path).map_err(ItemRegistryError::Io)?;
+        Self::from_ron(&text)
+    }
+
+    pub fn from_ron(text: &str) -> Result<Self, ItemRegistryError> {
+        let item_registry: Self = ron::from_str(text).map_err(ItemRegistryError::Deserialize)?;
+        if let Some(missing) = ItemId::CORE
+            .iter()
+            .find(|core_id| !item_registry.items.contains_key(core_id))
+        {
+            return Err(ItemRegistryError::MissingCoreItem(*missing));
+        }
+        Ok(item_registry)
+    }
+
+    pub fn get(&self, item_id: &ItemId) -> Option<&ItemDefinition> {
+        self.items.get(item_id)
+    }
+
+    /// the id itself if the item isn't in the registry
+    pub fn display_name(&self, item_id: &ItemId) -> String {
+        self.get(item_id).map_or(item_id.to_string(), |definition| {
+            definition.display_name.clone()
+        })
+    }
+
+    /// 1 if the item isn't in the registry
+    pub fn stack_size(&self, item_id: &ItemId) -> u32 {
+        self.get(item_id)
+            .map_or(1, |definition| definition.stack_size.max(1))
+    }
+
+    /// 0 if the item isn't in the registry
+    pub fn weight(&self, item_id: &ItemId) -> f32 {
+        self.get(item_id)
+            .map_or(0.0, |definition| definition.weight)
+    }
+}
+
+/// how much an inventory can hold
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum InventoryCapacity {
+    #[default]
+    Unlimited,
+    /// every slot holds one stack of one item (see `ItemDefinition::stack_size`)
+    Slots(u32),
+    /// max total weight
+    Weight(f32),
 }
 
 /// can't be stacked in the code but can be showed as stacked in the game UI
-#[derive(Component, Clone, Copy, Debug)]
+#[derive(Component, Clone, Copy, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub enum UniqueItemKind {
     IronSword,
+    Hammer,
+}
+
+impl UniqueItemKind {
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            UniqueItemKind::IronSword => "Iron sword",
+            UniqueItemKind::Hammer => "Hammer",
+        }
+    }
+
+    /// number of uses before the item breaks
+    pub fn max_durability(&self) -> u16 {
+        match self {
+            UniqueItemKind::IronSword => 200,
+            UniqueItemKind::Hammer => 50,
+        }
+    }
+}
+
+/// spawns a new unique item with full durability ; it still has to be put in an Inventory
+pub fn spawn_unique_item(commands: &mut Commands, kind: UniqueItemKind) -> Entity {
+    commands
+        .spawn((Item, kind, Durability::new(kind.max_durability())))
+        .id()
+}
+
+/// uses the item `uses` times ; when it breaks it is removed from the inventory and despawned
+/// returns true if the item broke
+pub fn wear_unique_item(
+    commands: &mut Commands,
+    inventory: &mut Inventory,
+    item_ent: Entity,
+    durability: &mut Durability,
+    uses: u16,
+) -> bool {
+    if !durability.wear(uses) {
+        return false;
+    }
+    inventory.remove_unique_item(item_ent);
+    // nothing to do if it is already gone
+    commands.entity(item_ent).try_despawn();
+    true
 }
 
-#[derive(Component, Default, Debug)]
+#[derive(Component, Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Inventory {
-    pub stackable_items: HashMap<ItemKind, u32>,
+    pub stackable_items: HashMap<ItemId, u32>,
     pub unique_items: Vec<Entity>,
     // pub unique_items: HashMap<UniqueItemKind, Vec<Entity>>,
+    #[serde(default)]
+    pub capacity: InventoryCapacity,
 }
 
-#[derive(Component)]
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Durability {
     pub current: u16,
     pub max: u16,
 }
 
-#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+impl Durability {
+    pub fn new(max: u16) -> Self {
+        Self { current: max, max }
+    }
+
+    /// returns true if the item is broken
+    pub fn wear(&mut self, uses: u16) -> bool {
+        self.current = self.current.saturating_sub(uses);
+        self.current == 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CraftRecipeId {
     Chest,
+    Brick,
+}
+
+/// what a recipe consumes and produces for one craft
+#[derive(Clone, Debug)]
+pub struct CraftRecipe {
+    pub inputs: Vec<(ItemId, u32)>,
+    pub outputs: Vec<(ItemId, u32)>,
+    pub duration_ticks: u32, // for one craft
+    /// unique item the unit must carry ; it loses one durability per craft
+    pub tool: Option<UniqueItemKind>,
+}
+
+impl CraftRecipe {
+    /// checks if the inventory contains the inputs to craft `quantity` times
+    pub fn has_inputs(&self, inventory: &Inventory, quantity: u32) -> bool {
+        self.inputs
+            .iter()
+            .all(|(kind, amount)| inventory.count(kind) >= amount * quantity)
+    }
+}
+
+/// registry of every recipe, indexed by CraftRecipeId
+#[derive(Resource, Debug)]
+pub struct CraftRecipes {
+    pub recipes: HashMap<CraftRecipeId, CraftRecipe>,
+}
+
+impl Default for CraftRecipes {
+    fn default() -> Self {
+        let mut recipes = HashMap::new();
+        recipes.insert(
+            CraftRecipeId::Chest,
+            CraftRecipe {
+                inputs: vec![(ItemId::ROCK, 10)],
+                outputs: vec![(ItemId::CHEST, 1)],
+                duration_ticks: UPS_TARGET as u32 * 3,
+                tool: None,
+            },
+        );
+        recipes.insert(
+            CraftRecipeId::Brick,
+            CraftRecipe {
+                inputs: vec![(ItemId::ROCK, 2)],
+                outputs: vec![(ItemId::new("brick"), 1)],
+                duration_ticks: UPS_TARGET as u32,
+                tool: Some(UniqueItemKind::Hammer),
+            },
+        );
+        Self { recipes }
+    }
+}
+
+impl CraftRecipes {
+    pub fn get(&self, recipe_id: &CraftRecipeId) -> Option<&CraftRecipe> {
+        self.recipes.get(recipe_id)
+    }
 }
 
 impl Inventory {
+    /// unlimited capacity
     pub fn new() -> Self {
         Self {
             stackable_items: HashMap::new(),
             unique_items: Vec::new(),
+            capacity: InventoryCapacity::Unlimited,
+        }
+    }
+
+    pub fn with_capacity(capacity: InventoryCapacity) -> Self {
+        Self {
+            capacity,
+            ..Self::new()
+        }
+    }
+
+    /// adds what fits and returns the overflow
+    pub fn add(&mut self, kind: ItemId, quantity: u32, item_registry: &ItemRegistry) -> u32 {
+        let added = quantity.min(self.space_for(&kind, item_registry));
+        if added > 0 {
+            *self.stackable_items.entry(kind).or_insert(0) += added;
+        }
+        quantity - added
+    }
+
+    /// how many more `kind` fit in the inventory
+    pub fn space_for(&self, kind: &ItemId, item_registry: &ItemRegistry) -> u32 {
+        match self.capacity {
+            InventoryCapacity::Unlimited => u32::MAX,
+            InventoryCapacity::Slots(slots) => {
+                let used_slots = self.used_slots(item_registry);
+                let stack_size = item_registry.stack_size(kind);
+                // the last stack of this item may not be full
+                let in_last_stack = self.count(kind) % stack_size;
+                let room_in_last_stack = if in_last_stack == 0 {
+                    0
+                } else {
+                    stack_size - in_last_stack
+                };
+                slots.saturating_sub(used_slots) * stack_size + room_in_last_stack
+            }
+            InventoryCapacity::Weight(max_weight) => {
+                let weight = item_registry.weight(kind);
+                if weight <= 0.0 {
+                    return u32::MAX;
+                }
+                let free_weight = max_weight - self.weight(item_registry);
+                (free_weight / weight).max(0.0).floor() as u32
+            }
+        }
+    }
+
+    /// every unique item takes a slot of its own
+    pub fn used_slots(&self, item_registry: &ItemRegistry) -> u32 {
+        let stack_slots: u32 = self
+            .stackable_items
+            .iter()
+            .map(|(item_id, quantity)| quantity.div_ceil(item_registry.stack_size(item_id)))
+            .sum();
+        stack_slots + self.unique_items.len() as u32
+    }
+
+    /// unique items have no weight, only the slot limit applies to them
+    pub fn has_space_for_unique_item(&self, item_registry: &ItemRegistry) -> bool {
+        match self.capacity {
+            InventoryCapacity::Slots(slots) => self.used_slots(item_registry) < slots,
+            InventoryCapacity::Unlimited | InventoryCapacity::Weight(_) => true,
         }
     }
 
-    pub fn add(&mut self, kind: ItemKind, quantity: u32) {
-        *self.stackable_items.entry(kind).or_insert(0) += quantity;
+    pub fn weight(&self, item_registry: &ItemRegistry) -> f32 {
+        self.stackable_items
+            .iter()
+            .map(|(item_id, quantity)| item_registry.weight(item_id) * *quantity as f32)
+            .sum()
     }
 
     /// remove up to quantity, returns true if fully removed, false if not enough
-    pub fn remove(&mut self, kind: &ItemKind, quantity: u32) -> bool {
+    pub fn remove(&mut self, kind: &ItemId, quantity: u32) -> bool {
         if let Some(current_quantity) = self.stackable_items.get_mut(kind) {
             if *current_quantity >= quantity {
                 *current_quantity -= quantity;
@@ -61,10 +397,22 @@ impl Inventory {
         false
     }
 
-    pub fn count(&self, kind: &ItemKind) -> u32 {
+    pub fn count(&self, kind: &ItemId) -> u32 {
         *self.stackable_items.get(kind).unwrap_or(&0)
     }
 
+    /// true if all the items fit together
+    pub fn has_space_for_all(&self, items: &[(ItemId, u32)], item_registry: &ItemRegistry) -> bool {
+        let mut after = self.clone();
+        items
+            .iter()
+            .all(|(kind, quantity)| after.add(*kind, *quantity, item_registry) == 0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.unique_items.is_empty() && self.stackable_items.values().all(|&quantity| quantity == 0)
+    }
+
     pub fn add_unique_item(&mut self, item_entity: Entity) {
         self.unique_items.push(item_entity);
     }
@@ -79,13 +427,227 @@ impl Inventory {
     }
 }
 
+/// what moves from an inventory to another
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransferItem {
+    Stackable { kind: ItemId, quantity: u32 },
+    Unique(Entity),
+}
+
+/// sent for every transfer that moved something ; `item` is what was really moved
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ItemsTransferred {
+    pub from: Entity,
+    pub to: Entity,
+    pub item: TransferItem,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferError {
+    NotEnoughItems, // the source doesn't have the items
+    NoSpace,        // the destination is full
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferOutcome {
+    /// everything was moved
+    Complete(u32),
+    /// the source didn't have enough or the destination was too small ; the rest didn't move
+    Partial { transferred: u32, requested: u32 },
+    /// nothing was moved and neither inventory changed
+    Failed(TransferError),
+}
+
+impl TransferOutcome {
+    pub fn transferred(&self) -> u32 {
+        match self {
+            TransferOutcome::Complete(transferred)
+            | TransferOutcome::Partial { transferred, .. } => *transferred,
+            TransferOutcome::Failed(_) => 0,
+        }
+    }
+}
+
+/// moves what fits from `from_inventory` to `to_inventory` ; the quantity is computed before
+/// touching the inventories so items are never lost nor duplicated
+pub fn transfer_items(
+    from: Entity,
+    from_inventory: &mut Inventory,
+    to: Entity,
+    to_inventory: &mut Inventory,
+    item: TransferItem,
+    item_registry: &ItemRegistry,
+    items_transferred_events: &mut EventWriter<ItemsTransferred>,
+) -> TransferOutcome {
+    let (requested, transferred) = match item {
+        TransferItem::Stackable { kind, quantity } => {
+            let available = from_inventory.count(&kind);
+            if available == 0 {
+                return TransferOutcome::Failed(TransferError::NotEnoughItems);
+            }
+            let space = to_inventory.space_for(&kind, item_registry);
+            if space == 0 {
+                return TransferOutcome::Failed(TransferError::NoSpace);
+            }
+            let transferred = quantity.min(available).min(space);
+            from_inventory.remove(&kind, transferred);
+            to_inventory.add(kind, transferred, item_registry);
+            (quantity, transferred)
+        }
+        TransferItem::Unique(item_ent) => {
+            if !from_inventory.unique_items.contains(&item_ent) {
+                return TransferOutcome::Failed(TransferError::NotEnoughItems);
+            }
+            if !to_inventory.has_space_for_unique_item(item_registry) {
+                return TransferOutcome::Failed(TransferError::NoSpace);
+            }
+            from_inventory.remove_unique_item(item_ent);
+            to_inventory.add_unique_item(item_ent);
+            (1, 1)
+        }
+    };
+    if transferred == 0 {
+        return TransferOutcome::Failed(TransferError::NotEnoughItems);
+    }
+
+    let item = match item {
+        TransferItem::Stackable { kind, .. } => TransferItem::Stackable {
+            kind,
+            quantity: transferred,
+        },
+        TransferItem::Unique(_) => item,
+    };
+    items_transferred_events.write(ItemsTransferred { from, to, item });
+    if transferred == requested {
+        TransferOutcome::Complete(transferred)
+    } else {
+        TransferOutcome::Partial {
+            transferred,
+            requested,
+        }
+    }
+}
+
 // Système d'affichage de l'inventaire
-pub fn display_inventories(inventories: Query<&Inventory>) {
+pub fn display_inventories(
+    item_registry: Res<ItemRegistry>,
+    inventories: Query<&Inventory>,
+    unique_item_query: Query<(&UniqueItemKind, &Durability), With<Item>>,
+) {
     if let Ok(inventory) = inventories.single() {
         println!("=== INVENTORY ===");
-        for (item_kind, quantity) in &inventory.stackable_items {
-            println!("{:?}: {}", item_kind, quantity);
+        for (item_id, quantity) in &inventory.stackable_items {
+            println!("{}: {}", item_registry.display_name(item_id), quantity);
+        }
+        // unique items are showed as stacks of their kind
+        let mut unique_items: HashMap<UniqueItemKind, Vec<&Durability>> = HashMap::new();
+        for (kind, durability) in unique_item_query.iter_many(&inventory.unique_items) {
+            unique_items.entry(*kind).or_default().push(durability);
+        }
+        for (kind, durabilities) in unique_items {
+            let durabilities: Vec<String> = durabilities
+                .iter()
+                .map(|durability| format!("{}/{}", durability.current, durability.max))
+                .collect();
+            println!(
+                "{}: {} ({})",
+                kind.display_name(),
+                durabilities.len(),
+                durabilities.join(", ")
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_name_gives_same_id() {
+        assert_eq!(ItemId::new("rock"), ItemId::ROCK);
+        assert_eq!(ItemId::new("iron plate"), ItemId::new("iron plate"));
+        assert_ne!(ItemId::new("iron plate"), ItemId::ROCK);
+    }
+
+    #[test]
+    fn ids_are_written_as_names() {
+        let text = ron::to_string(&ItemId::CHEST).unwrap();
+        assert_eq!(text, "\"chest\"");
+        let item_id: ItemId = ron::from_str("\"copper ore\"").unwrap();
+        assert_eq!(item_id.name(), "copper ore");
+    }
+
+    fn registry() -> ItemRegistry {
+        ItemRegistry::from_ron(
+            r#"(items: {
+                "rock": (display_name: "Rock", icon: "", stack_size: 50, weight: 2.0, volume: 1.0),
+                "chest": (display_name: "Chest", icon: "", stack_size: 10, weight: 15.0, volume: 8.0),
+            })"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn slots_respect_stack_sizes() {
+        let item_registry = registry();
+        let mut inventory = Inventory::with_capacity(InventoryCapacity::Slots(2));
+        assert_eq!(inventory.add(ItemId::ROCK, 30, &item_registry), 0);
+        // 20 more rocks fill the first slot, the chests get the second one
+        assert_eq!(inventory.space_for(&ItemId::ROCK, &item_registry), 70);
+        assert_eq!(inventory.add(ItemId::CHEST, 12, &item_registry), 2);
+        assert_eq!(inventory.space_for(&ItemId::ROCK, &item_registry), 20);
+        assert_eq!(inventory.add(ItemId::ROCK, 25, &item_registry), 5);
+        assert_eq!(inventory.count(&ItemId::ROCK), 50);
+        assert_eq!(inventory.count(&ItemId::CHEST), 10);
+    }
+
+    #[test]
+    fn weight_limit_returns_the_overflow() {
+        let item_registry = registry();
+        let mut inventory = Inventory::with_capacity(InventoryCapacity::Weight(40.0));
+        assert_eq!(inventory.add(ItemId::CHEST, 2, &item_registry), 0);
+        assert_eq!(inventory.space_for(&ItemId::ROCK, &item_registry), 5);
+        assert_eq!(inventory.add(ItemId::ROCK, 8, &item_registry), 3);
+        assert_eq!(inventory.space_for(&ItemId::CHEST, &item_registry), 0);
+
+        let mut unlimited = Inventory::new();
+        assert_eq!(unlimited.add(ItemId::ROCK, 1000, &item_registry), 0);
+    }
+
+    #[test]
+    fn unique_items_take_a_slot_each() {
+        let item_registry = registry();
+        let mut inventory = Inventory::with_capacity(InventoryCapacity::Slots(2));
+        inventory.add(ItemId::ROCK, 10, &item_registry);
+        inventory.add_unique_item(Entity::from_raw(1));
+        assert!(!inventory.has_space_for_unique_item(&item_registry));
+        // only the room left in the rock stack
+        assert_eq!(inventory.space_for(&ItemId::ROCK, &item_registry), 40);
+        assert!(inventory.remove_unique_item(Entity::from_raw(1)));
+        assert!(inventory.has_space_for_unique_item(&item_registry));
+    }
+
+    #[test]
+    fn durability_breaks_at_zero() {
+        let mut durability = Durability::new(3);
+        assert!(!durability.wear(2));
+        assert_eq!(durability.current, 1);
+        assert!(durability.wear(5));
+        assert_eq!(durability.current, 0);
+    }
+
+    #[test]
+    fn registry_needs_core_items() {
+        let item_registry = ItemRegistry::load(Path::new(ITEMS_PATH)).unwrap();
+        for core_id in ItemId::CORE {
+            assert!(item_registry.get(&core_id).is_some());
         }
-        println!("Unique items: {}", inventory.unique_items.len());
+
+        let without_chest = r#"(items: {"rock": (display_name: "Rock", icon: "", stack_size: 50, weight: 1.0, volume: 1.0)})"#;
+        assert!(matches!(
+            ItemRegistry::from_ron(without_chest),
+            Err(ItemRegistryError::MissingCoreItem(ItemId::CHEST))
+        ));
     }
 }
@@ -0,0 +1,14 @@
+pub mod build;
+pub mod coordinates;
+pub mod items;
+pub mod logistics;
+pub mod map;
+pub mod pathfinding;
+pub mod save;
+pub mod scenario;
+pub mod selection;
+pub mod simulation;
+pub mod terrain;
+pub mod units;
+
+pub const UPS_TARGET: f64 = 30.0;
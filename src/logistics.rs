@@ -0,0 +1,173 @@
+use crate::{
+    items::{Inventory, ItemId, ItemRegistry},
+    map::{Chest, world_pos_to_rounded_tile},
+    units::{
+        Unit,
+        jobs::{JobBoard, assign_jobs_system},
+        tasks::{
+            CurrentTask, Reservations, Task, TaskKind, TaskQueue, TaskStatus,
+            start_next_task_system,
+        },
+    },
+};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// max quantity a unit carries for one delivery ; bigger requests are split between several units
+pub const MAX_ITEMS_PER_TRIP: u32 = 10;
+pub const LOGISTICS_PRIORITY: i32 = 0;
+
+pub struct LogisticsPlugin;
+
+impl Plugin for LogisticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            logistics_dispatcher_system
+                .after(start_next_task_system)
+                .before(assign_jobs_system),
+        );
+    }
+}
+
+/// items a logistics chest gives to the network
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ProvideFilter {
+    #[default]
+    Nothing,
+    Everything,
+    Only(HashSet<ItemId>),
+}
+
+/// chest connected to the logistics network ; needs Chest and Inventory
+#[derive(Component, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogisticsChest {
+    /// kind -> quantity the chest wants to have ; units bring the missing items
+    pub requests: HashMap<ItemId, u32>,
+    pub provides: ProvideFilter,
+}
+
+impl LogisticsChest {
+    pub fn provider() -> Self {
+        Self {
+            requests: HashMap::new(),
+            provides: ProvideFilter::Everything,
+        }
+    }
+
+    pub fn requester(requests: impl IntoIterator<Item = (ItemId, u32)>) -> Self {
+        Self {
+            requests: requests.into_iter().collect(),
+            provides: ProvideFilter::Nothing,
+        }
+    }
+
+    /// a chest never gives away what it requests, otherwise units would move items back and forth
+    pub fn provides(&self, kind: &ItemId) -> bool {
+        if self.requests.contains_key(kind) {
+            return false;
+        }
+        match &self.provides {
+            ProvideFilter::Nothing => false,
+            ProvideFilter::Everything => true,
+            ProvideFilter::Only(kinds) => kinds.contains(kind),
+        }
+    }
+
+    /// how many `kind` are missing to reach the request threshold
+    pub fn missing(&self, kind: &ItemId, inventory: &Inventory) -> u32 {
+        self.requests
+            .get(kind)
+            .map_or(0, |wanted| wanted.saturating_sub(inventory.count(kind)))
+    }
+}
+
+/// posts a GetItems -> DeliverItems job for every item missing in a requester chest
+fn logistics_dispatcher_system(
+    reservations: Res<Reservations>,
+    item_registry: Res<ItemRegistry>,
+    mut job_board: ResMut<JobBoard>,
+    chest_query: Query<(Entity, &GlobalTransform, &Inventory, &LogisticsChest), With<Chest>>,
+    unit_query: Query<(&CurrentTask, &TaskQueue), With<Unit>>,
+) {
+    // items not reserved in the providers, minus what units will try to take
+    let mut supply: HashMap<ItemId, u32> = HashMap::new();
+    for (chest_ent, _, inventory, logistics_chest) in chest_query.iter() {
+        for (kind, quantity) in inventory.stackable_items.iter() {
+            if logistics_chest.provides(kind) {
+                let free = quantity.saturating_sub(reservations.total_reserved(chest_ent, *kind));
+                *supply.entry(*kind).or_insert(0) += free;
+            }
+        }
+    }
+
+    // tasks done by the units or waiting on the board
+    let tasks = unit_query
+        .iter()
+        .flat_map(|(current_task, task_queue)| current_task.task.iter().chain(task_queue.0.iter()))
+        .flat_map(|task| task.iter())
+        .chain(job_board.tasks());
+    let mut incoming: HashMap<Entity, HashMap<ItemId, u32>> = HashMap::new();
+    for task in tasks {
+        match task.kind {
+            TaskKind::GetItems { kind, quantity } if task.status == TaskStatus::Pending => {
+                let free = supply.entry(kind).or_insert(0);
+                *free = free.saturating_sub(quantity);
+            }
+            TaskKind::DeliverItems { kind, quantity, to } if !task.status.is_finished() => {
+                *incoming.entry(to).or_default().entry(kind).or_insert(0) += quantity;
+            }
+            _ => {}
+        }
+    }
+
+    for (chest_ent, global_transform, inventory, logistics_chest) in chest_query.iter() {
+        let chest_tile_pos = world_pos_to_rounded_tile(global_transform.translation().xy());
+        for kind in logistics_chest.requests.keys() {
+            let already_incoming = incoming
+                .get(&chest_ent)
+                .and_then(|kinds| kinds.get(kind))
+                .copied()
+                .unwrap_or(0);
+            // never more than what fits in the chest
+            let mut missing = logistics_chest
+                .missing(kind, inventory)
+                .min(inventory.space_for(kind, &item_registry))
+                .saturating_sub(already_incoming);
+
+            while missing > 0 {
+                let free = supply.entry(*kind).or_insert(0);
+                if *free == 0 {
+                    break;
+                }
+                let quantity = missing.min(*free).min(MAX_ITEMS_PER_TRIP);
+
+                let get_items = Task::new(
+                    TaskKind::GetItems {
+                        kind: *kind,
+                        quantity,
+                    },
+                    Vec::new(),
+                );
+                let deliver_items = Task::new(
+                    TaskKind::DeliverItems {
+                        kind: *kind,
+                        quantity,
+                        to: chest_ent,
+                    },
+                    Vec::new(),
+                );
+                job_board.post(
+                    Task::sequence(vec![get_items, deliver_items]),
+                    LOGISTICS_PRIORITY,
+                    Some(chest_tile_pos),
+                );
+
+                *free -= quantity;
+                missing -= quantity;
+            }
+        }
+    }
+}
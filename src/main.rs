@@ -1,18 +1,3 @@
-use crate::{
-    items::{Inventory, ItemKind, display_inventories},
-    map::{
-        Chest, ChunkManager, Crafter, MapPlugin, Provider, Requester, Structure, StructureManager,
-        TILE_SIZE, place_structure, rounded_tile_pos_to_world,
-    },
-    pathfinding::PathfindingPlugin,
-    units::{
-        TileMovement, Unit, UnitUnitCollisions, UnitsPlugin, display_units_inventory_system,
-        display_units_with_no_current_action_system, move_and_collide_units_system,
-        states::Available,
-        tasks::{TasksPlugin, display_reservations_system},
-        test_units_control_system, update_sprite_facing_system,
-    },
-};
 use bevy::{
     color::palettes::css::GREEN,
     diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin},
@@ -23,20 +8,44 @@ use bevy::{
     prelude::*,
     time::common_conditions::on_timer,
 };
-use rand::{Rng, rng};
-use std::time::Duration;
-
-mod items;
-mod map;
-mod pathfinding;
-mod units;
+use bevy_ecs_tilemap::TilemapPlugin;
+use overlord::{
+    UPS_TARGET,
+    items::display_inventories,
+    map::{TILE_SIZE, WorldSeed},
+    save::SavePlugin,
+    scenario::{DEFAULT_SCENARIO_PATH, Scenario, ScenarioPlugin},
+    simulation::{SimulationPlugins, display_headless_summary},
+    units::tasks::display_reservations_system,
+};
+use std::{path::Path, time::Duration};
 
-pub const UPS_TARGET: f64 = 30.0;
 const ZOOM_IN_SPEED: f32 = 0.25 / 400000000.0;
 const ZOOM_OUT_SPEED: f32 = 4.0 * 400000000.0;
 const CAMERA_SPEED: f32 = 37.5;
 
 fn main() {
+    let scenario_path = parse_scenario_path();
+    let mut scenario = match Scenario::load(Path::new(&scenario_path)) {
+        Ok(scenario) => scenario,
+        Err(error) => {
+            println!("Can't load scenario {}: {}", scenario_path, error);
+            return;
+        }
+    };
+    // --seed wins over the seed of the scenario
+    let world_seed = parse_world_seed()
+        .or(scenario.seed)
+        .map(WorldSeed)
+        .unwrap_or_default();
+    scenario.seed = Some(world_seed.0);
+    println!("Scenario: {} | World seed: {}", scenario_path, world_seed.0);
+
+    if let Some(ticks) = parse_headless_ticks() {
+        run_headless(scenario, ticks);
+        return;
+    }
+
     App::new()
         .add_plugins(
             DefaultPlugins
@@ -51,10 +60,12 @@ fn main() {
                 .set(ImagePlugin::default_nearest()),
         )
         .add_plugins(FrameTimeDiagnosticsPlugin::default())
-        .add_plugins(UnitsPlugin)
-        .add_plugins(MapPlugin)
-        .add_plugins(PathfindingPlugin)
-        .add_plugins(TasksPlugin)
+        .add_plugins(TilemapPlugin)
+        .add_plugins(SimulationPlugins)
+        .add_plugins(ScenarioPlugin)
+        .add_plugins(SavePlugin)
+        .insert_resource(world_seed)
+        .insert_resource(scenario)
         .insert_resource(TimeState::default())
         .insert_resource(UpsCounter {
             ticks: 0,
@@ -88,6 +99,52 @@ fn main() {
         .run();
 }
 
+/// runs the simulation without window as fast as possible then prints the logistics chests
+fn run_headless(scenario: Scenario, ticks: u32) {
+    let mut scenario_app = scenario.app();
+
+    let start = std::time::Instant::now();
+    scenario_app.run_ticks(ticks);
+    let elapsed = start.elapsed();
+
+    println!(
+        "Headless: {} ticks in {:.2?} ({:.0} ticks/s)",
+        ticks,
+        elapsed,
+        ticks as f64 / elapsed.as_secs_f64()
+    );
+    display_headless_summary(scenario_app.app.world_mut());
+    println!("Failed tasks: {}", scenario_app.failures().len());
+}
+
+/// reads `--headless <ticks>` from the command line
+fn parse_headless_ticks() -> Option<u32> {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter()
+        .position(|arg| arg == "--headless")
+        .and_then(|index| args.get(index + 1))
+        .and_then(|ticks| ticks.parse().ok())
+}
+
+/// reads `--seed <u64>` from the command line
+fn parse_world_seed() -> Option<u64> {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter()
+        .position(|arg| arg == "--seed")
+        .and_then(|index| args.get(index + 1))
+        .and_then(|seed| seed.parse().ok())
+}
+
+/// reads `--scenario <path>` from the command line ; the default scenario if missing
+fn parse_scenario_path() -> String {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter()
+        .position(|arg| arg == "--scenario")
+        .and_then(|index| args.get(index + 1))
+        .cloned()
+        .unwrap_or(DEFAULT_SCENARIO_PATH.to_string())
+}
+
 #[derive(Resource)]
 pub struct UpsCounter {
     ticks: u32,
@@ -124,9 +181,6 @@ fn setup_system(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
-    asset_server: Res<AssetServer>,
-    mut structure_manager: ResMut<StructureManager>,
-    mut chunk_manager: ResMut<ChunkManager>,
 ) {
     let mut orthographic_projection = OrthographicProjection::default_2d();
     orthographic_projection.scale *= 0.8;
@@ -136,152 +190,6 @@ fn setup_system(
         Mesh2d(meshes.add(Rectangle::new(20.0, 20.0))),
         MeshMaterial2d(materials.add(Color::from(GREEN))),
     ));
-
-    let mut rng = rng();
-    let player_texture_handle = asset_server.load("default.png");
-    for _i in 0..100 {
-        // let random_multiplier = rng.random_range(1..=50);
-        let random_multiplier = rng.random_range(1..=5);
-        let random_speed = UPS_TARGET as u32 / random_multiplier;
-        let world_pos = rounded_tile_pos_to_world(IVec2::new(0, 0));
-
-        // let mut sprite = Sprite::from_image(player_texture_handle.clone());
-        let sprite = Sprite {
-            image: player_texture_handle.clone(),
-            // custom_size: Some(Vec2::new(32.0, 32.0)),
-            ..default()
-        };
-        // uses Unit required componenents to make it easier
-        commands.spawn((
-            Unit {
-                name: "Unit".into(),
-            },
-            // Sprite::from_image(player_texture_handle.clone()),
-            sprite,
-            Transform::from_translation(world_pos.extend(0.0)),
-            TileMovement::new(random_speed),
-            Available,
-            UnitUnitCollisions,
-        ));
-    }
-    let random_speed = u32::MAX;
-    let world_pos = rounded_tile_pos_to_world(IVec2::new(5, 0));
-    // uses Unit required componenents to make it easier
-    commands.spawn((
-        Unit {
-            name: "AFK Player".into(),
-        },
-        Sprite::from_image(player_texture_handle.clone()),
-        Transform::from_translation(world_pos.extend(0.0)),
-        TileMovement::new(random_speed),
-        UnitUnitCollisions,
-    ));
-
-    // provider chest
-    let mut inventory = Inventory::new();
-    inventory.add(ItemKind::Rock, 1000);
-    let chest_entity = commands
-        .spawn((
-            Structure,
-            Chest,
-            Sprite::from_image(asset_server.load("structures/chest.png")),
-            inventory,
-            Provider,
-        ))
-        .id();
-    let rounded_tile_pos = IVec2::new(10, 5);
-    place_structure(
-        &mut commands,
-        &asset_server,
-        &chest_entity,
-        &mut structure_manager,
-        &mut chunk_manager,
-        rounded_tile_pos,
-    );
-
-    // provider chest 2
-    let mut inventory = Inventory::new();
-    inventory.add(ItemKind::Rock, 1000);
-    let chest_entity = commands
-        .spawn((
-            Structure,
-            Chest,
-            Sprite::from_image(asset_server.load("structures/chest.png")),
-            inventory,
-            Provider,
-        ))
-        .id();
-    let rounded_tile_pos = IVec2::new(5, 10);
-    place_structure(
-        &mut commands,
-        &asset_server,
-        &chest_entity,
-        &mut structure_manager,
-        &mut chunk_manager,
-        rounded_tile_pos,
-    );
-
-    // provider chest 3
-    let mut inventory = Inventory::new();
-    inventory.add(ItemKind::Rock, 1000);
-    let chest_entity = commands
-        .spawn((
-            Structure,
-            Chest,
-            Sprite::from_image(asset_server.load("structures/chest.png")),
-            inventory,
-            Provider,
-        ))
-        .id();
-    let rounded_tile_pos = IVec2::new(5, -10);
-    place_structure(
-        &mut commands,
-        &asset_server,
-        &chest_entity,
-        &mut structure_manager,
-        &mut chunk_manager,
-        rounded_tile_pos,
-    );
-
-    // requester chest
-    let mut inventory = Inventory::new();
-    inventory.add(ItemKind::Rock, 1);
-    let chest_entity = commands
-        .spawn((
-            Structure,
-            Chest,
-            Sprite::from_image(asset_server.load("structures/chest.png")),
-            inventory,
-            Requester,
-        ))
-        .id();
-    let rounded_tile_pos = IVec2::new(-10, 5);
-    place_structure(
-        &mut commands,
-        &asset_server,
-        &chest_entity,
-        &mut structure_manager,
-        &mut chunk_manager,
-        rounded_tile_pos,
-    );
-
-    // crafter
-    let crafter_entity = commands
-        .spawn((
-            Structure,
-            Crafter,
-            Sprite::from_image(asset_server.load("structures/crafter.png")),
-        ))
-        .id();
-    let rounded_tile_pos = IVec2::new(-3, 5);
-    place_structure(
-        &mut commands,
-        &asset_server,
-        &crafter_entity,
-        &mut structure_manager,
-        &mut chunk_manager,
-        rounded_tile_pos,
-    );
 }
 
 fn handle_camera_inputs_system(
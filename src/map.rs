@@ -1,8 +1,16 @@
-use crate::units::Unit;
+use crate::{
+    UPS_TARGET,
+    coordinates::{self, ChunkPos, WorldPos},
+    items::{CraftRecipeId, Inventory, InventoryCapacity, ItemId},
+    terrain::{TerrainFeature, TerrainGenerator, TileKind},
+    units::Unit,
+};
 use bevy::{ecs::system::entity_command, prelude::*};
 use bevy_ecs_tilemap::prelude::*;
-use rand::Rng;
-use std::collections::HashMap;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 pub const TILE_SIZE: TilemapTileSize = TilemapTileSize { x: 16.0, y: 16.0 };
 pub const CHUNK_SIZE: UVec2 = UVec2 { x: 32, y: 32 };
@@ -13,87 +21,434 @@ pub const RENDER_CHUNK_SIZE: UVec2 = UVec2 {
 };
 pub const TILE_LAYER_LEVEL: f32 = -1.0;
 pub const STRUCTURE_LAYER_LEVEL: f32 = 0.0;
+pub const CHEST_CAPACITY: InventoryCapacity = InventoryCapacity::Slots(32);
 
 pub struct MapPlugin;
 
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut bevy::app::App) {
-        app.add_plugins(TilemapPlugin)
+        app.init_resource::<WorldSeed>()
+            .init_resource::<ChunkStreamingSettings>()
             .insert_resource(ChunkManager::default())
             .insert_resource(StructureManager::default())
+            .init_resource::<GroundManager>()
+            .add_event::<StructureChanged>()
+            .add_event::<GroundChanged>()
             .add_systems(
                 FixedUpdate,
                 (
+                    send_structure_changes_system,
                     spawn_chunks_around_camera_system,
                     spawn_chunks_around_units_system,
+                    unload_far_chunks_system
+                        .after(spawn_chunks_around_camera_system)
+                        .after(spawn_chunks_around_units_system),
+                    sync_ground_system.after(unload_far_chunks_system),
                 ),
             );
     }
 }
 
+/// seed of the world generation ; the same seed always generates the same chunks
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WorldSeed(pub u64);
+
+impl Default for WorldSeed {
+    fn default() -> Self {
+        Self(rand::rng().random())
+    }
+}
+
+impl WorldSeed {
+    /// RNG of a chunk, derived only from (seed, chunk position) so it doesn't depend on the load order
+    pub fn chunk_rng(&self, rounded_chunk_pos: IVec2) -> StdRng {
+        let packed_chunk_pos =
+            ((rounded_chunk_pos.x as u32 as u64) << 32) | rounded_chunk_pos.y as u32 as u64;
+        StdRng::seed_from_u64(splitmix64(self.0 ^ splitmix64(packed_chunk_pos)))
+    }
+
+    /// RNG of the units spawned by a scenario (their random speeds)
+    pub fn units_rng(&self) -> StdRng {
+        StdRng::seed_from_u64(splitmix64(!self.0))
+    }
+}
+
+// mixes the bits so close inputs (neighbour chunks) give unrelated outputs
+pub fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
 #[derive(Resource, Default, Debug)]
 pub struct ChunkManager {
     pub spawned_chunks: HashMap<IVec2, Entity>, // rounded_chunk_pos -> chunk
 }
 
+/// distances in chunks ; unload_radius must be bigger than the load radiuses or chunks will be spawned and unloaded every tick
+#[derive(Resource, Debug)]
+pub struct ChunkStreamingSettings {
+    pub load_radius_around_camera: i32,
+    pub load_radius_around_units: i32,
+    pub unload_radius: i32, // around the camera and every unit
+}
+
+impl Default for ChunkStreamingSettings {
+    fn default() -> Self {
+        Self {
+            load_radius_around_camera: 4,
+            load_radius_around_units: 2,
+            unload_radius: 6,
+        }
+    }
+}
+
 /// to quickly find the Structure at coordinates without checking every Structure
 #[derive(Resource, Default, Debug)]
 pub struct StructureManager {
     pub structures: HashMap<IVec2, Entity>, // rounded_tile_pos -> structure
+    /// sent as StructureChanged events by send_structure_changes_system
+    pub changes: Vec<StructureChanged>,
+    /// Generated structures removed by the player ; they aren't spawned again with their chunk
+    pub removed_generated: HashMap<IVec2, HashSet<IVec2>>, // rounded_chunk_pos -> rounded_tile_pos
+}
+
+impl StructureManager {
+    pub fn insert(&mut self, rounded_tile_pos: IVec2, structure_entity: Entity) {
+        self.structures.insert(rounded_tile_pos, structure_entity);
+        self.changes.push(StructureChanged::Added(rounded_tile_pos));
+    }
+
+    pub fn remove(&mut self, rounded_tile_pos: IVec2) -> Option<Entity> {
+        let structure_entity = self.structures.remove(&rounded_tile_pos)?;
+        self.changes
+            .push(StructureChanged::Removed(rounded_tile_pos));
+        Some(structure_entity)
+    }
+
+    /// removes a Generated structure for good, even after its chunk is unloaded
+    pub fn remove_generated(&mut self, rounded_tile_pos: IVec2) -> Option<Entity> {
+        let structure_entity = self.remove(rounded_tile_pos)?;
+        self.removed_generated
+            .entry(rounded_tile_pos_to_rounded_chunk(rounded_tile_pos))
+            .or_default()
+            .insert(rounded_tile_pos);
+        Some(structure_entity)
+    }
+
+    pub fn is_generated_removed(&self, rounded_tile_pos: IVec2) -> bool {
+        self.removed_generated
+            .get(&rounded_tile_pos_to_rounded_chunk(rounded_tile_pos))
+            .is_some_and(|removed| removed.contains(&rounded_tile_pos))
+    }
+
+    pub fn clear(&mut self) {
+        for (&rounded_tile_pos, _) in self.structures.iter() {
+            self.changes
+                .push(StructureChanged::Removed(rounded_tile_pos));
+        }
+        self.structures.clear();
+        self.removed_generated.clear();
+    }
+}
+
+/// a structure was added to or removed from the StructureManager ; the pathfinding updates its caches and the paths going through it
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StructureChanged {
+    Added(IVec2),
+    Removed(IVec2),
+}
+
+impl StructureChanged {
+    pub fn rounded_tile_pos(&self) -> IVec2 {
+        match self {
+            StructureChanged::Added(rounded_tile_pos)
+            | StructureChanged::Removed(rounded_tile_pos) => *rounded_tile_pos,
+        }
+    }
+}
+
+/// ground of the tiles, read by the movement and the pathfinding ; cheap to clone for the snapshots of the pathfinding
+#[derive(Clone, Default, Debug)]
+pub struct Ground {
+    /// generated ground of the spawned chunks by rounded_chunk_pos ; index x + y * CHUNK_SIZE.x
+    chunks: HashMap<IVec2, Arc<Vec<TileKind>>>,
+    /// laid by the player (roads) ; replaces the generated ground, even in the chunks not spawned
+    laid: Arc<HashMap<IVec2, TileKind>>,
+}
+
+impl Ground {
+    /// grass in the chunks not spawned, like is_tile_passable supposes there is no wall there
+    pub fn tile_kind(&self, rounded_tile_pos: IVec2) -> TileKind {
+        if let Some(tile_kind) = self.laid.get(&rounded_tile_pos) {
+            return *tile_kind;
+        }
+        let tile_pos = coordinates::TilePos(rounded_tile_pos);
+        let Some(tile_kinds) = self.chunks.get(&tile_pos.to_chunk().0) else {
+            return TileKind::Grass;
+        };
+        let local_tile_pos = tile_pos.to_local().0;
+        tile_kinds[(local_tile_pos.x + local_tile_pos.y * CHUNK_SIZE.x) as usize]
+    }
+
+    pub fn movement_cost(&self, rounded_tile_pos: IVec2) -> Option<f32> {
+        self.tile_kind(rounded_tile_pos).movement_cost()
+    }
+
+    pub fn laid(&self) -> &HashMap<IVec2, TileKind> {
+        &self.laid
+    }
+}
+
+#[derive(Resource, Default, Debug)]
+pub struct GroundManager {
+    pub ground: Ground,
+    /// entities of the chunks whose ground is in `ground`, to notice the chunks spawned again
+    chunk_entities: HashMap<IVec2, Entity>,
+    /// sent as GroundChanged events by sync_ground_system
+    changed_chunks: HashSet<IVec2>,
+    /// laid tiles whose texture must be updated
+    pending_textures: Vec<IVec2>,
+}
+
+impl GroundManager {
+    pub fn lay(&mut self, rounded_tile_pos: IVec2, tile_kind: TileKind) {
+        Arc::make_mut(&mut self.ground.laid).insert(rounded_tile_pos, tile_kind);
+        self.changed_chunks
+            .insert(rounded_tile_pos_to_rounded_chunk(rounded_tile_pos));
+        self.pending_textures.push(rounded_tile_pos);
+    }
+
+    /// the chunks are spawned again after a load, with the generated ground
+    pub fn clear_laid(&mut self) {
+        for &rounded_tile_pos in self.ground.laid.keys() {
+            self.changed_chunks
+                .insert(rounded_tile_pos_to_rounded_chunk(rounded_tile_pos));
+        }
+        Arc::make_mut(&mut self.ground.laid).clear();
+    }
+}
+
+/// the ground of the chunk changed (spawned, unloaded, or a tile was laid)
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GroundChanged {
+    pub rounded_chunk_pos: IVec2,
 }
 
 #[derive(Component)]
 pub struct Structure;
 
+/// structure created by the terrain generation ; it is generated again with its chunk so it doesn't need to be saved
 #[derive(Component)]
-pub struct Wall;
+pub struct Generated;
+
+/// structures that can be built (not generated with the terrain)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StructureKind {
+    Wall,
+    Chest,
+    Crafter,
+}
+
+impl StructureKind {
+    pub fn sprite_path(&self) -> &'static str {
+        match self {
+            StructureKind::Wall => "structures/wall.png",
+            StructureKind::Chest => "structures/chest.png",
+            StructureKind::Crafter => "structures/crafter.png",
+        }
+    }
+
+    /// materials consumed to build it from a blueprint
+    pub fn build_cost(&self) -> Vec<(ItemId, u32)> {
+        match self {
+            StructureKind::Wall => vec![(ItemId::ROCK, 2)],
+            StructureKind::Chest => vec![(ItemId::CHEST, 1)],
+            StructureKind::Crafter => vec![(ItemId::ROCK, 20)],
+        }
+    }
+
+    pub fn build_ticks(&self) -> u32 {
+        match self {
+            StructureKind::Wall => UPS_TARGET as u32 * 2,
+            StructureKind::Chest => UPS_TARGET as u32,
+            StructureKind::Crafter => UPS_TARGET as u32 * 5,
+        }
+    }
+}
 
 #[derive(Component)]
-pub struct Chest;
-// TODO: delete these two component and do something better
-#[derive(Component)]
-pub struct Provider;
+pub struct Wall;
+
+/// generated with the terrain
 #[derive(Component)]
-pub struct Requester;
+pub struct Ore {
+    pub kind: ItemId,
+}
 
 #[derive(Component)]
-pub struct Crafter;
+pub struct Chest;
+
+/// crafting machine ; can only be used by one unit at a time
+#[derive(Component, Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Crafter {
+    pub progress: Option<CraftingProgress>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CraftingProgress {
+    pub owner: Entity, // unit using the crafter
+    pub recipe: CraftRecipeId,
+    pub quantity: u32,
+    pub remaining_ticks: u32,
+}
+
+pub fn send_structure_changes_system(
+    mut structure_manager: ResMut<StructureManager>,
+    mut structure_changed_events: EventWriter<StructureChanged>,
+) {
+    if structure_manager.changes.is_empty() {
+        return;
+    }
+    structure_changed_events.write_batch(structure_manager.changes.drain(..));
+}
+
+/// keeps the ground of the spawned chunks and the textures of the laid tiles up to date
+pub fn sync_ground_system(
+    mut commands: Commands,
+    world_seed: Res<WorldSeed>,
+    chunk_manager: Res<ChunkManager>,
+    mut ground_manager: ResMut<GroundManager>,
+    mut ground_changed_events: EventWriter<GroundChanged>,
+    tile_storage_query: Query<&TileStorage>,
+) {
+    let ground_manager = &mut *ground_manager;
+
+    // unloaded, or despawned by a load
+    let removed_chunks: Vec<IVec2> = ground_manager
+        .chunk_entities
+        .iter()
+        .filter(|(chunk_pos, chunk_entity)| {
+            chunk_manager.spawned_chunks.get(*chunk_pos) != Some(*chunk_entity)
+        })
+        .map(|(chunk_pos, _)| *chunk_pos)
+        .collect();
+    for chunk_pos in removed_chunks {
+        ground_manager.chunk_entities.remove(&chunk_pos);
+        ground_manager.ground.chunks.remove(&chunk_pos);
+        ground_manager.changed_chunks.insert(chunk_pos);
+    }
+
+    let terrain = TerrainGenerator::new(&world_seed);
+    for (&chunk_pos, &chunk_entity) in chunk_manager.spawned_chunks.iter() {
+        if ground_manager.chunk_entities.contains_key(&chunk_pos) {
+            continue;
+        }
+        let tile_kinds: Vec<TileKind> = (0..CHUNK_SIZE.y as i32)
+            .flat_map(|y| (0..CHUNK_SIZE.x as i32).map(move |x| IVec2::new(x, y)))
+            .map(|local_tile_pos| {
+                terrain.tile_kind(local_tile_pos_to_rounded_tile(local_tile_pos, chunk_pos))
+            })
+            .collect();
+        ground_manager
+            .ground
+            .chunks
+            .insert(chunk_pos, Arc::new(tile_kinds));
+        ground_manager
+            .chunk_entities
+            .insert(chunk_pos, chunk_entity);
+        ground_manager.changed_chunks.insert(chunk_pos);
+        // spawn_chunk only knows the generated ground
+        let laid_in_chunk: Vec<IVec2> = ground_manager
+            .ground
+            .laid
+            .keys()
+            .filter(|rounded_tile_pos| {
+                rounded_tile_pos_to_rounded_chunk(**rounded_tile_pos) == chunk_pos
+            })
+            .copied()
+            .collect();
+        ground_manager.pending_textures.extend(laid_in_chunk);
+    }
+
+    let GroundManager {
+        ground,
+        chunk_entities,
+        pending_textures,
+        ..
+    } = ground_manager;
+    pending_textures.retain(|rounded_tile_pos| {
+        let chunk_pos = rounded_tile_pos_to_rounded_chunk(*rounded_tile_pos);
+        // the texture is set when the chunk spawns
+        let Some(&chunk_entity) = chunk_entities.get(&chunk_pos) else {
+            return false;
+        };
+        // its tiles are not spawned yet
+        let Ok(tile_storage) = tile_storage_query.get(chunk_entity) else {
+            return true;
+        };
+        let local_tile_pos = coordinates::TilePos(*rounded_tile_pos).to_local().0;
+        if let Some(tile_entity) = tile_storage.get(&TilePos {
+            x: local_tile_pos.x,
+            y: local_tile_pos.y,
+        }) {
+            commands.entity(tile_entity).insert(TileTextureIndex(
+                ground.tile_kind(*rounded_tile_pos).texture_index(),
+            ));
+        }
+        false
+    });
+
+    ground_changed_events.write_batch(
+        ground_manager
+            .changed_chunks
+            .drain()
+            .map(|rounded_chunk_pos| GroundChanged { rounded_chunk_pos }),
+    );
+}
 
 pub fn spawn_chunk(
     commands: &mut Commands,
     asset_server: &AssetServer,
     mut structure_manager: &mut ResMut<StructureManager>,
+    world_seed: &WorldSeed,
     chunk_pos: IVec2,
 ) -> Entity {
     let tilemap_entity = commands.spawn_empty().id();
     let mut tile_storage = TileStorage::empty(CHUNK_SIZE.into());
-    let mut rng = rand::rng();
+    let mut rng = world_seed.chunk_rng(chunk_pos);
+    let terrain = TerrainGenerator::new(world_seed);
 
     // Collecte les positions des structures à créer
     let mut structures_to_spawn = Vec::new();
+    // structures placed by the player that were kept when the chunk was unloaded
+    let mut structures_to_reattach = Vec::new();
 
     // Spawn the elements of the tilemap.
     for x in 0..CHUNK_SIZE.x {
         for y in 0..CHUNK_SIZE.y {
             let local_tile_pos = TilePos { x, y };
+            let rounded_tile_pos =
+                local_tile_pos_to_rounded_tile(IVec2::new(x as i32, y as i32), chunk_pos);
+            let tile_kind = terrain.tile_kind(rounded_tile_pos);
             let tile_entity = commands
                 .spawn(TileBundle {
                     position: local_tile_pos,
                     tilemap_id: TilemapId(tilemap_entity),
-                    texture_index: TileTextureIndex(0),
+                    texture_index: TileTextureIndex(tile_kind.texture_index()),
                     ..Default::default()
                 })
                 .id();
 
-            let is_wall = rng.random_bool(0.2);
-            if is_wall
-                && (chunk_pos.x > 0 || chunk_pos.x < 0)
-                && (chunk_pos.y > 0 || chunk_pos.y < 0)
+            // always called so the rng gives the same results whatever the player built
+            let feature = terrain.feature(rounded_tile_pos, &mut rng);
+            if let Some(&structure_entity) = structure_manager.structures.get(&rounded_tile_pos) {
+                structures_to_reattach.push((rounded_tile_pos, structure_entity));
+            } else if let Some(feature) = feature
+                && !structure_manager.is_generated_removed(rounded_tile_pos)
             {
-                let local_tile_pos: IVec2 =
-                    IVec2::new(local_tile_pos.x as i32, local_tile_pos.y as i32);
-                let rounded_tile_pos = local_tile_pos_to_rounded_tile(local_tile_pos, chunk_pos);
-                structures_to_spawn.push(rounded_tile_pos);
+                structures_to_spawn.push((rounded_tile_pos, feature));
             }
 
             match commands.get_entity(tilemap_entity) {
@@ -114,10 +469,10 @@ pub fn spawn_chunk(
         TILE_LAYER_LEVEL,
     ));
 
-    let image_handles = vec![
-        asset_server.load("tiles/grass.png"),
-        asset_server.load("tiles/stone.png"),
-    ];
+    let image_handles = TileKind::ALL
+        .iter()
+        .map(|tile_kind| asset_server.load(tile_kind.texture_path()))
+        .collect();
 
     // Configure le tilemap
     match commands.get_entity(tilemap_entity) {
@@ -139,18 +494,29 @@ pub fn spawn_chunk(
 
     // Spawn les structures APRÈS avoir configuré le tilemap
     // et les attache directement au tilemap
-    for rounded_tile_pos in structures_to_spawn {
-        let wall_entity = commands
-            .spawn((
-                Structure,
-                Wall,
-                Sprite::from_image(asset_server.load("structures/wall.png")),
-            ))
-            .id();
+    for (rounded_tile_pos, feature) in structures_to_spawn {
+        let structure_entity = match feature {
+            TerrainFeature::Wall => commands
+                .spawn((
+                    Structure,
+                    Generated,
+                    Wall,
+                    Sprite::from_image(asset_server.load("structures/wall.png")),
+                ))
+                .id(),
+            TerrainFeature::RockOre => commands
+                .spawn((
+                    Structure,
+                    Generated,
+                    Ore { kind: ItemId::ROCK },
+                    Sprite::from_image(asset_server.load("structures/rock_ore.png")),
+                ))
+                .id(),
+        };
 
         spawn_structure_in_chunk(
             commands,
-            &wall_entity,
+            &structure_entity,
             &mut structure_manager,
             tilemap_entity,
             rounded_tile_pos,
@@ -158,9 +524,54 @@ pub fn spawn_chunk(
         );
     }
 
+    for (rounded_tile_pos, structure_entity) in structures_to_reattach {
+        spawn_structure_in_chunk(
+            commands,
+            &structure_entity,
+            structure_manager,
+            tilemap_entity,
+            rounded_tile_pos,
+            tilemap_world_pos,
+        );
+    }
+
     tilemap_entity
 }
 
+/// despawns a chunk ; its Generated structures are despawned with it, the others are detached and stay in the StructureManager
+pub fn unload_chunk(
+    commands: &mut Commands,
+    structure_manager: &mut ResMut<StructureManager>,
+    structure_query: &Query<Has<Generated>, With<Structure>>,
+    chunk_entity: Entity,
+    chunk_pos: IVec2,
+) {
+    for x in 0..CHUNK_SIZE.x as i32 {
+        for y in 0..CHUNK_SIZE.y as i32 {
+            let rounded_tile_pos = local_tile_pos_to_rounded_tile(IVec2::new(x, y), chunk_pos);
+            let Some(&structure_entity) = structure_manager.structures.get(&rounded_tile_pos)
+            else {
+                continue;
+            };
+
+            if structure_query.get(structure_entity).unwrap_or(true) {
+                structure_manager.remove(rounded_tile_pos);
+            } else {
+                // its transform was relative to the chunk
+                let world_pos = rounded_tile_pos_to_world(rounded_tile_pos);
+                commands
+                    .entity(structure_entity)
+                    .remove::<ChildOf>()
+                    .insert(Transform::from_translation(
+                        world_pos.extend(STRUCTURE_LAYER_LEVEL),
+                    ));
+            }
+        }
+    }
+
+    commands.entity(chunk_entity).despawn();
+}
+
 fn spawn_structure_in_chunk(
     commands: &mut Commands,
     structure_entity: &Entity,
@@ -193,9 +604,28 @@ fn spawn_structure_in_chunk(
     };
 
     // Enregistre la structure dans le manager
-    structure_manager
-        .structures
-        .insert(rounded_tile_pos, *structure_entity);
+    structure_manager.insert(rounded_tile_pos, *structure_entity);
+}
+
+/// spawns the entity of a structure, without position ; use place_structure to put it in the world
+pub fn spawn_structure(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    kind: StructureKind,
+) -> Entity {
+    let sprite = Sprite::from_image(asset_server.load(kind.sprite_path()));
+    match kind {
+        StructureKind::Wall => commands.spawn((Structure, Wall, sprite)).id(),
+        StructureKind::Chest => commands
+            .spawn((
+                Structure,
+                Chest,
+                sprite,
+                Inventory::with_capacity(CHEST_CAPACITY),
+            ))
+            .id(),
+        StructureKind::Crafter => commands.spawn((Structure, Crafter::default(), sprite)).id(),
+    }
 }
 
 // add transform to structure_entity and add it to structure_manager
@@ -205,6 +635,7 @@ pub fn place_structure(
     structure_entity: &Entity,
     structure_manager: &mut ResMut<StructureManager>,
     chunk_manager: &mut ResMut<ChunkManager>, // Maintenant mutable
+    world_seed: &WorldSeed,
     rounded_tile_pos: IVec2,
 ) {
     let rounded_chunk_pos = rounded_tile_pos_to_rounded_chunk(rounded_tile_pos);
@@ -214,7 +645,13 @@ pub fn place_structure(
         .spawned_chunks
         .contains_key(&rounded_chunk_pos)
     {
-        let entity = spawn_chunk(commands, asset_server, structure_manager, rounded_chunk_pos);
+        let entity = spawn_chunk(
+            commands,
+            asset_server,
+            structure_manager,
+            world_seed,
+            rounded_chunk_pos,
+        );
         chunk_manager
             .spawned_chunks
             .insert(rounded_chunk_pos, entity);
@@ -248,10 +685,7 @@ pub fn get_neighbors(pos: IVec2) -> impl Iterator<Item = IVec2> {
         })
 }
 
-pub fn is_tile_passable(
-    rounded_tile_pos: IVec2,
-    structure_manager: &Res<StructureManager>,
-) -> bool {
+pub fn is_tile_passable(rounded_tile_pos: IVec2, structure_manager: &StructureManager) -> bool {
     if let Some(_structure_entity) = structure_manager.structures.get(&rounded_tile_pos) {
         return false;
     }
@@ -262,6 +696,7 @@ pub fn is_tile_passable(
 
 // ========= coordinates conversion =========
 // world_pos = (5.5 * TILE_SIZE.X, 0.5 * TILE_SIZE.y) | tile_pos = (5.5, 0.5) | rounded_tile_pos = (5, 0)
+// IVec2 versions of the conversions of the coordinates module
 
 pub fn local_tile_pos_to_rounded_tile(local_tile_pos: IVec2, rounded_chunk_pos: IVec2) -> IVec2 {
     IVec2::new(
@@ -277,10 +712,7 @@ pub fn tile_pos_to_world(tile_pos: Vec2) -> Vec2 {
 
 // adds 0.5 to coordinates to make entities spawn based on the corner of there sprite and not the center
 pub fn rounded_tile_pos_to_world(rounded_tile_pos: IVec2) -> Vec2 {
-    Vec2::new(
-        rounded_tile_pos.x as f32 * TILE_SIZE.x + 0.5 * TILE_SIZE.x,
-        rounded_tile_pos.y as f32 * TILE_SIZE.y + 0.5 * TILE_SIZE.y,
-    )
+    coordinates::TilePos(rounded_tile_pos).to_world().0
 }
 
 // (5.5, 0.5) => (5, 0)
@@ -295,49 +727,43 @@ pub fn world_pos_to_tile(world_pos: Vec2) -> Vec2 {
 
 // Conversion monde -> coordonnées logiques
 pub fn world_pos_to_rounded_tile(world_pos: Vec2) -> IVec2 {
-    IVec2::new(
-        (world_pos.x / TILE_SIZE.x).floor() as i32,
-        (world_pos.y / TILE_SIZE.y).floor() as i32,
-    )
+    WorldPos(world_pos).to_tile().0
+}
+
+/// world position under the cursor ; None if there is no window or the cursor is outside of it
+pub fn cursor_world_pos(
+    windows: &Query<&Window>,
+    cameras: &Query<(&Camera, &GlobalTransform)>,
+) -> Option<Vec2> {
+    let window = windows.iter().next()?;
+    let (camera, camera_transform) = cameras.iter().next()?;
+    let cursor_pos = window.cursor_position()?;
+    camera
+        .viewport_to_world_2d(camera_transform, cursor_pos)
+        .ok()
 }
 
 /// Convertit une position monde (pixels) en position de chunk.
 pub fn world_pos_to_rounded_chunk(world_pos: &Vec2) -> IVec2 {
-    // let chunk_size_pixels = CHUNK_SIZE.as_vec2() * Vec2::new(TILE_SIZE.x, TILE_SIZE.y);
-    // let pos = *world_pos / chunk_size_pixels;
-    // IVec2::new(pos.x.floor() as i32, pos.y.floor() as i32)
-    IVec2::new(
-        (world_pos.x / (CHUNK_SIZE.x as f32 * TILE_SIZE.x)).floor() as i32,
-        (world_pos.y / (CHUNK_SIZE.y as f32 * TILE_SIZE.y)).floor() as i32,
-    )
+    WorldPos(*world_pos).to_chunk().0
 }
 
 pub fn rounded_chunk_pos_to_rounded_tile(rounded_chunk_pos: &IVec2) -> IVec2 {
-    IVec2::new(
-        rounded_chunk_pos.x * CHUNK_SIZE.x as i32,
-        rounded_chunk_pos.y * CHUNK_SIZE.y as i32,
-    )
+    ChunkPos(*rounded_chunk_pos).origin_tile().0
 }
 
 pub fn rounded_tile_pos_to_rounded_chunk(rounded_tile_pos: IVec2) -> IVec2 {
-    IVec2::new(
-        rounded_tile_pos.x / CHUNK_SIZE.x as i32,
-        rounded_tile_pos.y / CHUNK_SIZE.y as i32,
-    )
+    coordinates::TilePos(rounded_tile_pos).to_chunk().0
 }
 
 pub fn tile_pos_to_rounded_chunk(tile_pos: Vec2) -> IVec2 {
-    IVec2::new(
-        (tile_pos.x / CHUNK_SIZE.x as f32).floor() as i32,
-        (tile_pos.y / CHUNK_SIZE.y as f32).floor() as i32,
-    )
+    coordinates::TilePos(tile_pos_to_rounded_tile(tile_pos))
+        .to_chunk()
+        .0
 }
 
 pub fn camera_pos_to_rounded_chunk_pos(camera_pos: &Vec2) -> IVec2 {
-    let camera_pos = camera_pos.as_ivec2();
-    let chunk_size: IVec2 = IVec2::new(CHUNK_SIZE.x as i32, CHUNK_SIZE.y as i32);
-    let tile_size: IVec2 = IVec2::new(TILE_SIZE.x as i32, TILE_SIZE.y as i32);
-    camera_pos / (chunk_size * tile_size)
+    world_pos_to_rounded_chunk(camera_pos)
 }
 // ==========================================
 
@@ -347,18 +773,21 @@ fn spawn_chunks_around_camera_system(
     camera_query: Query<&Transform, With<Camera>>,
     mut chunk_manager: ResMut<ChunkManager>,
     mut structure_manager: ResMut<StructureManager>,
+    world_seed: Res<WorldSeed>,
+    settings: Res<ChunkStreamingSettings>,
 ) {
-    const SIZE: i32 = 4;
+    let size = settings.load_radius_around_camera;
     for transform in camera_query.iter() {
         let camera_chunk_pos = world_pos_to_rounded_chunk(&transform.translation.xy());
-        for y in (camera_chunk_pos.y - SIZE)..(camera_chunk_pos.y + SIZE) {
-            for x in (camera_chunk_pos.x - SIZE)..(camera_chunk_pos.x + SIZE) {
+        for y in (camera_chunk_pos.y - size)..(camera_chunk_pos.y + size) {
+            for x in (camera_chunk_pos.x - size)..(camera_chunk_pos.x + size) {
                 let chunk_pos = IVec2::new(x, y);
                 if !chunk_manager.spawned_chunks.contains_key(&chunk_pos) {
                     let entity = spawn_chunk(
                         &mut commands,
                         &asset_server,
                         &mut structure_manager,
+                        &world_seed,
                         chunk_pos,
                     );
                     chunk_manager.spawned_chunks.insert(chunk_pos, entity);
@@ -374,19 +803,22 @@ fn spawn_chunks_around_units_system(
     unit_query: Query<&Transform, With<Unit>>,
     mut chunk_manager: ResMut<ChunkManager>,
     mut structure_manager: ResMut<StructureManager>,
+    world_seed: Res<WorldSeed>,
+    settings: Res<ChunkStreamingSettings>,
 ) {
-    const SIZE: i32 = 2;
+    let size = settings.load_radius_around_units;
     // for transform in camera_query.iter() {
     for unit_transform in unit_query {
         let camera_chunk_pos = camera_pos_to_rounded_chunk_pos(&unit_transform.translation.xy());
-        for y in (camera_chunk_pos.y - SIZE)..(camera_chunk_pos.y + SIZE) {
-            for x in (camera_chunk_pos.x - SIZE)..(camera_chunk_pos.x + SIZE) {
+        for y in (camera_chunk_pos.y - size)..(camera_chunk_pos.y + size) {
+            for x in (camera_chunk_pos.x - size)..(camera_chunk_pos.x + size) {
                 let chunk_pos = IVec2::new(x, y);
                 if !chunk_manager.spawned_chunks.contains_key(&IVec2::new(x, y)) {
                     let entity = spawn_chunk(
                         &mut commands,
                         &asset_server,
                         &mut structure_manager,
+                        &world_seed,
                         chunk_pos,
                     );
                     chunk_manager.spawned_chunks.insert(chunk_pos, entity);
@@ -395,3 +827,43 @@ fn spawn_chunks_around_units_system(
         }
     }
 }
+
+fn unload_far_chunks_system(
+    mut commands: Commands,
+    camera_query: Query<&Transform, With<Camera>>,
+    unit_query: Query<&Transform, With<Unit>>,
+    structure_query: Query<Has<Generated>, With<Structure>>,
+    mut chunk_manager: ResMut<ChunkManager>,
+    mut structure_manager: ResMut<StructureManager>,
+    settings: Res<ChunkStreamingSettings>,
+) {
+    let anchor_chunks: HashSet<IVec2> = camera_query
+        .iter()
+        .chain(unit_query.iter())
+        .map(|transform| world_pos_to_rounded_chunk(&transform.translation.xy()))
+        .collect();
+
+    let chunks_to_unload: Vec<IVec2> = chunk_manager
+        .spawned_chunks
+        .keys()
+        .filter(|chunk_pos| {
+            anchor_chunks.iter().all(|anchor_chunk| {
+                let delta = (**chunk_pos - *anchor_chunk).abs();
+                delta.x.max(delta.y) > settings.unload_radius
+            })
+        })
+        .copied()
+        .collect();
+
+    for chunk_pos in chunks_to_unload {
+        if let Some(chunk_entity) = chunk_manager.spawned_chunks.remove(&chunk_pos) {
+            unload_chunk(
+                &mut commands,
+                &mut structure_manager,
+                &structure_query,
+                chunk_entity,
+                chunk_pos,
+            );
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! flow fields : one search from the target gives the way to it from every tile around, for the agents sharing a target
+
+use super::{PassabilitySnapshot, PathNode, is_diagonal, walkable_neighbors};
+use crate::map::CHUNK_SIZE;
+use bevy::prelude::*;
+use std::collections::{BinaryHeap, HashMap};
+
+/// from this many agents waiting for a path to the same tile, they follow a flow field
+pub const FLOW_FIELD_MIN_AGENTS: usize = 8;
+/// tiles around the agents and the target covered by the field, so they can go around obstacles
+const FLOW_FIELD_MARGIN: i32 = CHUNK_SIZE.x as i32 / 2;
+
+#[derive(Debug)]
+pub struct FlowField {
+    pub target: IVec2,
+    /// tiles covered by the field
+    pub area: IRect,
+    /// integration field : cost to reach the target from the tile
+    pub costs: HashMap<IVec2, f32>,
+    /// direction field : step toward the target, zero on the target (or next to it when it is blocked)
+    pub directions: HashMap<IVec2, IVec2>,
+}
+
+impl FlowField {
+    pub fn new(target: IVec2, area: IRect, passability: &PassabilitySnapshot) -> Self {
+        let movement_cost = |pos| {
+            if !area.contains(pos) {
+                return None;
+            }
+            passability.movement_cost(pos)
+        };
+        // a blocked target (chest, crafter...) is reached from the tiles next to it
+        let ends: Vec<IVec2> = if passability.is_passable(target) {
+            vec![target]
+        } else {
+            walkable_neighbors(target, &movement_cost)
+                .map(|(neighbor_pos, _)| neighbor_pos)
+                .collect()
+        };
+
+        let mut costs = HashMap::new();
+        let mut directions = HashMap::new();
+        let mut open_set = BinaryHeap::new();
+        for end in ends {
+            costs.insert(end, 0.0);
+            directions.insert(end, IVec2::ZERO);
+            open_set.push(PathNode {
+                pos: end,
+                g_cost: 0.0,
+                h_cost: 0.0,
+                parent: None,
+            });
+        }
+        // Dijkstra from the target, backwards : the step from the neighbor to the current tile costs the ground of the current tile
+        while let Some(current_node) = open_set.pop() {
+            if current_node.g_cost > costs[&current_node.pos] {
+                continue;
+            }
+            let Some(current_cost) = movement_cost(current_node.pos) else {
+                continue;
+            };
+            for (neighbor_pos, _) in walkable_neighbors(current_node.pos, &movement_cost) {
+                let step_cost = if is_diagonal(current_node.pos, neighbor_pos) {
+                    1.414
+                } else {
+                    1.0
+                };
+                let new_g_cost = current_node.g_cost + step_cost * current_cost;
+                if costs
+                    .get(&neighbor_pos)
+                    .is_some_and(|&cost| cost <= new_g_cost)
+                {
+                    continue;
+                }
+                costs.insert(neighbor_pos, new_g_cost);
+                directions.insert(neighbor_pos, current_node.pos - neighbor_pos);
+                open_set.push(PathNode {
+                    pos: neighbor_pos,
+                    g_cost: new_g_cost,
+                    h_cost: 0.0,
+                    parent: Some(current_node.pos),
+                });
+            }
+        }
+
+        FlowField {
+            target,
+            area,
+            costs,
+            directions,
+        }
+    }
+
+    /// None if the tile is outside the field or can't reach the target ; the tile itself when it is at the end
+    pub fn next_tile(&self, rounded_tile_pos: IVec2) -> Option<IVec2> {
+        self.directions
+            .get(&rounded_tile_pos)
+            .map(|direction| rounded_tile_pos + *direction)
+    }
+}
+
+/// flow fields by target tile ; dropped when no agent follows them anymore
+#[derive(Resource, Default, Debug)]
+pub struct FlowFields {
+    pub fields: HashMap<IVec2, FlowField>,
+}
+
+impl FlowFields {
+    /// builds the field of the target again if it doesn't cover every start yet
+    pub fn prepare(&mut self, target: IVec2, starts: &[IVec2], passability: &PassabilitySnapshot) {
+        let existing_area = self.fields.get(&target).map(|flow_field| flow_field.area);
+        if let Some(area) = existing_area
+            && starts.iter().all(|start| area.contains(*start))
+        {
+            return;
+        }
+
+        let mut area = IRect::from_corners(target, target);
+        for start in starts {
+            area = area.union_point(*start);
+        }
+        area = area.inflate(FLOW_FIELD_MARGIN);
+        if let Some(existing_area) = existing_area {
+            area = area.union(existing_area);
+        }
+        self.fields
+            .insert(target, FlowField::new(target, area, passability));
+    }
+
+    pub fn next_tile(&self, target: IVec2, rounded_tile_pos: IVec2) -> Option<IVec2> {
+        self.fields.get(&target)?.next_tile(rounded_tile_pos)
+    }
+
+    /// drops the fields that overlap the tiles
+    pub fn invalidate_area(&mut self, changed_area: IRect) {
+        self.fields
+            // the edges of the rects are tiles too
+            .retain(|_, flow_field| {
+                flow_field
+                    .area
+                    .inflate(1)
+                    .intersect(changed_area)
+                    .is_empty()
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{Ground, StructureManager};
+
+    #[test]
+    fn directions_lead_around_the_wall_to_the_target() {
+        let mut structure_manager = StructureManager::default();
+        for y in -5..=5 {
+            structure_manager.insert(IVec2::new(3, y), Entity::PLACEHOLDER);
+        }
+        let passability = PassabilitySnapshot::new(&structure_manager, &Ground::default(), 0);
+        let target = IVec2::new(6, 0);
+        let mut flow_fields = FlowFields::default();
+        flow_fields.prepare(target, &[IVec2::new(0, 0)], &passability);
+
+        let mut tile = IVec2::new(0, 0);
+        for _ in 0..100 {
+            let next_tile = flow_fields.next_tile(target, tile).unwrap();
+            if next_tile == tile {
+                break;
+            }
+            assert!(passability.is_passable(next_tile));
+            tile = next_tile;
+        }
+        assert_eq!(tile, target);
+        // the integration field is the cost to the target
+        let flow_field = &flow_fields.fields[&target];
+        assert_eq!(flow_field.costs[&target], 0.0);
+        assert!(flow_field.costs[&IVec2::new(0, 0)] > 12.0);
+        assert!(flow_field.next_tile(IVec2::new(3, 0)).is_none());
+    }
+
+    #[test]
+    fn blocked_target_ends_next_to_it() {
+        let mut structure_manager = StructureManager::default();
+        let chest_pos = IVec2::new(5, 5);
+        structure_manager.insert(chest_pos, Entity::PLACEHOLDER);
+        let passability = PassabilitySnapshot::new(&structure_manager, &Ground::default(), 0);
+        let mut flow_fields = FlowFields::default();
+        flow_fields.prepare(chest_pos, &[IVec2::new(0, 0)], &passability);
+
+        let mut tile = IVec2::new(0, 0);
+        while flow_fields.next_tile(chest_pos, tile).unwrap() != tile {
+            tile = flow_fields.next_tile(chest_pos, tile).unwrap();
+        }
+        assert_eq!((tile - chest_pos).abs().max_element(), 1);
+    }
+}
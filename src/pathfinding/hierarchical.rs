@@ -0,0 +1,489 @@
+//! HPA* : the chunks are linked by entrances on their borders, a long path is planned between the entrances then refined inside each chunk
+
+use super::{
+    PassabilitySnapshot, PathNode, a_star, cost_heuristic, reconstruct_path, walkable_neighbors,
+};
+use crate::map::{CHUNK_SIZE, local_tile_pos_to_rounded_tile, rounded_tile_pos_to_rounded_chunk};
+use bevy::prelude::*;
+use std::{
+    collections::{BinaryHeap, HashMap, VecDeque},
+    sync::Arc,
+};
+
+/// an entrance at least this wide gets a transition at each end instead of one in the middle
+const WIDE_ENTRANCE: i32 = 6;
+/// the abstract search doesn't go further than this around the chunks of the start and the target
+const MAX_DETOUR_CHUNKS: i32 = 4;
+const MAX_ABSTRACT_EXPANSIONS: usize = 5_000;
+
+/// border shared by a chunk and its east or north neighbour
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Border {
+    East,
+    North,
+}
+
+impl Border {
+    fn across(&self) -> IVec2 {
+        match self {
+            Border::East => IVec2::X,
+            Border::North => IVec2::Y,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ChunkGraph {
+    entrances: Vec<IVec2>,
+    /// entrance -> (entrance, cost) ; inside the chunk, or the tile on the other side of the border
+    edges: HashMap<IVec2, Vec<(IVec2, f32)>>,
+}
+
+/// (tile in the chunk, tile in the neighbour) pairs to cross a border
+type Crossings = Arc<Vec<(IVec2, IVec2)>>;
+
+/// abstract graph of the chunks ; a chunk is built when a search goes through it
+/// cloning it is cheap : the clones share the borders and the chunks already built
+#[derive(Default, Debug, Clone)]
+pub struct PathGraph {
+    /// generation of the PassabilitySnapshot it was built from
+    pub generation: u64,
+    /// keyed by the west / south chunk
+    borders: HashMap<(IVec2, Border), Crossings>,
+    chunks: HashMap<IVec2, Arc<ChunkGraph>>,
+}
+
+impl PathGraph {
+    /// forgets what depends on the structures of the chunk ; the neighbours have entrances on the shared borders
+    pub fn invalidate_chunk(&mut self, rounded_chunk_pos: IVec2) {
+        self.borders.remove(&(rounded_chunk_pos, Border::East));
+        self.borders.remove(&(rounded_chunk_pos, Border::North));
+        self.borders
+            .remove(&(rounded_chunk_pos - IVec2::X, Border::East));
+        self.borders
+            .remove(&(rounded_chunk_pos - IVec2::Y, Border::North));
+        self.chunks.remove(&rounded_chunk_pos);
+        for step in [IVec2::X, IVec2::Y, IVec2::NEG_X, IVec2::NEG_Y] {
+            self.chunks.remove(&(rounded_chunk_pos + step));
+        }
+    }
+
+    pub fn is_chunk_built(&self, rounded_chunk_pos: IVec2) -> bool {
+        self.chunks.contains_key(&rounded_chunk_pos)
+    }
+
+    /// adds the borders and the chunks built by a search on a clone of the same generation
+    pub fn merge(&mut self, other: PathGraph) {
+        if other.generation != self.generation {
+            return;
+        }
+        for (key, crossings) in other.borders {
+            self.borders.entry(key).or_insert(crossings);
+        }
+        for (rounded_chunk_pos, chunk_graph) in other.chunks {
+            self.chunks.entry(rounded_chunk_pos).or_insert(chunk_graph);
+        }
+    }
+
+    fn border(
+        &mut self,
+        rounded_chunk_pos: IVec2,
+        border: Border,
+        passability: &PassabilitySnapshot,
+    ) -> &[(IVec2, IVec2)] {
+        self.borders
+            .entry((rounded_chunk_pos, border))
+            .or_insert_with(|| Arc::new(find_crossings(rounded_chunk_pos, border, passability)))
+    }
+
+    fn chunk_graph(
+        &mut self,
+        rounded_chunk_pos: IVec2,
+        passability: &PassabilitySnapshot,
+    ) -> &ChunkGraph {
+        if !self.chunks.contains_key(&rounded_chunk_pos) {
+            let chunk_graph = self.build_chunk_graph(rounded_chunk_pos, passability);
+            self.chunks.insert(rounded_chunk_pos, Arc::new(chunk_graph));
+        }
+        &self.chunks[&rounded_chunk_pos]
+    }
+
+    fn build_chunk_graph(
+        &mut self,
+        rounded_chunk_pos: IVec2,
+        passability: &PassabilitySnapshot,
+    ) -> ChunkGraph {
+        // (tile in this chunk, tile in the neighbour)
+        let mut crossings: Vec<(IVec2, IVec2)> = Vec::new();
+        crossings.extend_from_slice(self.border(rounded_chunk_pos, Border::East, passability));
+        crossings.extend_from_slice(self.border(rounded_chunk_pos, Border::North, passability));
+        for (chunk_pos, border) in [
+            (rounded_chunk_pos - IVec2::X, Border::East),
+            (rounded_chunk_pos - IVec2::Y, Border::North),
+        ] {
+            let reversed: Vec<(IVec2, IVec2)> = self
+                .border(chunk_pos, border, passability)
+                .iter()
+                .map(|&(from, to)| (to, from))
+                .collect();
+            crossings.extend(reversed);
+        }
+
+        let mut chunk_graph = ChunkGraph::default();
+        for (entrance, across) in crossings {
+            if !chunk_graph.entrances.contains(&entrance) {
+                chunk_graph.entrances.push(entrance);
+            }
+            // find_crossings only keeps passable tiles
+            let cost = passability.movement_cost(across).unwrap_or(1.0);
+            chunk_graph
+                .edges
+                .entry(entrance)
+                .or_default()
+                .push((across, cost));
+        }
+        for &entrance in chunk_graph.entrances.iter() {
+            let costs = costs_in_chunk(entrance, rounded_chunk_pos, passability);
+            let edges = chunk_graph.edges.get_mut(&entrance).unwrap();
+            for other in chunk_graph.entrances.iter() {
+                if *other != entrance
+                    && let Some(&cost) = costs.get(other)
+                {
+                    edges.push((*other, cost));
+                }
+            }
+        }
+        chunk_graph
+    }
+
+    /// plans between the entrances of the chunks then refines each part with a local A* ; None if there is no route
+    pub fn find_path(
+        &mut self,
+        start: IVec2,
+        end: IVec2,
+        passability: &PassabilitySnapshot,
+    ) -> Option<VecDeque<IVec2>> {
+        let start_chunk = rounded_tile_pos_to_rounded_chunk(start);
+        let end_chunk = rounded_tile_pos_to_rounded_chunk(end);
+        if start_chunk == end_chunk
+            && let Some(path) = path_in_chunk(start, end, start_chunk, passability)
+        {
+            return Some(path);
+        }
+
+        // the start and the end are linked to the entrances of their chunk only for this search
+        let start_costs = costs_in_chunk(start, start_chunk, passability);
+        let end_costs = costs_in_chunk(end, end_chunk, passability);
+        let min_chunk = start_chunk.min(end_chunk) - IVec2::splat(MAX_DETOUR_CHUNKS);
+        let max_chunk = start_chunk.max(end_chunk) + IVec2::splat(MAX_DETOUR_CHUNKS);
+
+        let mut open_set = BinaryHeap::new();
+        let mut all_nodes: HashMap<IVec2, PathNode> = HashMap::new();
+        let start_node = PathNode {
+            pos: start,
+            g_cost: 0.0,
+            h_cost: cost_heuristic(start, end),
+            parent: None,
+        };
+        open_set.push(start_node.clone());
+        all_nodes.insert(start, start_node);
+
+        let mut expansions: usize = 0;
+        let mut reached_end = false;
+        while let Some(current_node) = open_set.pop() {
+            // already reached with a lower cost
+            if current_node.g_cost > all_nodes[&current_node.pos].g_cost {
+                continue;
+            }
+            if current_node.pos == end {
+                reached_end = true;
+                break;
+            }
+            expansions += 1;
+            if expansions > MAX_ABSTRACT_EXPANSIONS {
+                return None;
+            }
+
+            let current_chunk = rounded_tile_pos_to_rounded_chunk(current_node.pos);
+            let chunk_graph = self.chunk_graph(current_chunk, passability);
+            let mut neighbors = chunk_graph
+                .edges
+                .get(&current_node.pos)
+                .cloned()
+                .unwrap_or_default();
+            if current_node.pos == start {
+                neighbors.extend(chunk_graph.entrances.iter().filter_map(|entrance| {
+                    start_costs.get(entrance).map(|&cost| (*entrance, cost))
+                }));
+            }
+            if current_chunk == end_chunk
+                && let Some(&cost) = end_costs.get(&current_node.pos)
+            {
+                neighbors.push((end, cost));
+            }
+
+            for (neighbor_pos, cost) in neighbors {
+                let neighbor_chunk = rounded_tile_pos_to_rounded_chunk(neighbor_pos);
+                if neighbor_chunk.cmplt(min_chunk).any() || neighbor_chunk.cmpgt(max_chunk).any() {
+                    continue;
+                }
+                let new_g_cost = current_node.g_cost + cost;
+                if all_nodes
+                    .get(&neighbor_pos)
+                    .is_some_and(|node| node.g_cost <= new_g_cost)
+                {
+                    continue;
+                }
+                let neighbor_node = PathNode {
+                    pos: neighbor_pos,
+                    g_cost: new_g_cost,
+                    h_cost: cost_heuristic(neighbor_pos, end),
+                    parent: Some(current_node.pos),
+                };
+                open_set.push(neighbor_node.clone());
+                all_nodes.insert(neighbor_pos, neighbor_node);
+            }
+        }
+        if !reached_end {
+            return None;
+        }
+
+        let abstract_path = reconstruct_path(&all_nodes, end);
+        let mut path = VecDeque::from([start]);
+        for (&from, &to) in abstract_path.iter().zip(abstract_path.iter().skip(1)) {
+            let from_chunk = rounded_tile_pos_to_rounded_chunk(from);
+            // crossing of a border
+            if from_chunk != rounded_tile_pos_to_rounded_chunk(to) {
+                path.push_back(to);
+                continue;
+            }
+            let part = path_in_chunk(from, to, from_chunk, passability)?;
+            path.extend(part.into_iter().skip(1));
+        }
+        Some(path)
+    }
+}
+
+// the entrances of a border : one transition per opening, two if it is wide
+fn find_crossings(
+    rounded_chunk_pos: IVec2,
+    border: Border,
+    passability: &PassabilitySnapshot,
+) -> Vec<(IVec2, IVec2)> {
+    let (first_tile, along, length) = match border {
+        Border::East => (
+            local_tile_pos_to_rounded_tile(
+                IVec2::new(CHUNK_SIZE.x as i32 - 1, 0),
+                rounded_chunk_pos,
+            ),
+            IVec2::Y,
+            CHUNK_SIZE.y as i32,
+        ),
+        Border::North => (
+            local_tile_pos_to_rounded_tile(
+                IVec2::new(0, CHUNK_SIZE.y as i32 - 1),
+                rounded_chunk_pos,
+            ),
+            IVec2::X,
+            CHUNK_SIZE.x as i32,
+        ),
+    };
+    let across = border.across();
+
+    let mut crossings = Vec::new();
+    let mut opening_start = None;
+    for i in 0..=length {
+        let tile = first_tile + along * i;
+        let is_open =
+            i < length && passability.is_passable(tile) && passability.is_passable(tile + across);
+        match (is_open, opening_start) {
+            (true, None) => opening_start = Some(i),
+            (false, Some(start)) => {
+                opening_start = None;
+                let end = i - 1;
+                let transitions = if end - start + 1 >= WIDE_ENTRANCE {
+                    vec![start, end]
+                } else {
+                    vec![(start + end) / 2]
+                };
+                for transition in transitions {
+                    let tile = first_tile + along * transition;
+                    crossings.push((tile, tile + across));
+                }
+            }
+            _ => {}
+        }
+    }
+    crossings
+}
+
+fn movement_cost_in_chunk(
+    rounded_tile_pos: IVec2,
+    rounded_chunk_pos: IVec2,
+    passability: &PassabilitySnapshot,
+) -> Option<f32> {
+    if rounded_tile_pos_to_rounded_chunk(rounded_tile_pos) != rounded_chunk_pos {
+        return None;
+    }
+    passability.movement_cost(rounded_tile_pos)
+}
+
+/// A* that stays inside the chunk
+fn path_in_chunk(
+    start: IVec2,
+    end: IVec2,
+    rounded_chunk_pos: IVec2,
+    passability: &PassabilitySnapshot,
+) -> Option<VecDeque<IVec2>> {
+    a_star(
+        start,
+        end,
+        |pos| movement_cost_in_chunk(pos, rounded_chunk_pos, passability),
+        (CHUNK_SIZE.x * CHUNK_SIZE.y) as usize,
+        false,
+    )
+}
+
+/// Dijkstra from the tile to every tile of the chunk it can reach without leaving it
+fn costs_in_chunk(
+    start: IVec2,
+    rounded_chunk_pos: IVec2,
+    passability: &PassabilitySnapshot,
+) -> HashMap<IVec2, f32> {
+    let movement_cost = |pos| movement_cost_in_chunk(pos, rounded_chunk_pos, passability);
+    let mut costs = HashMap::from([(start, 0.0)]);
+    let mut open_set = BinaryHeap::from([PathNode {
+        pos: start,
+        g_cost: 0.0,
+        h_cost: 0.0,
+        parent: None,
+    }]);
+    while let Some(current_node) = open_set.pop() {
+        if current_node.g_cost > costs[&current_node.pos] {
+            continue;
+        }
+        for (neighbor_pos, move_cost) in walkable_neighbors(current_node.pos, &movement_cost) {
+            let new_g_cost = current_node.g_cost + move_cost;
+            if costs
+                .get(&neighbor_pos)
+                .is_some_and(|&cost| cost <= new_g_cost)
+            {
+                continue;
+            }
+            costs.insert(neighbor_pos, new_g_cost);
+            open_set.push(PathNode {
+                pos: neighbor_pos,
+                g_cost: new_g_cost,
+                h_cost: 0.0,
+                parent: Some(current_node.pos),
+            });
+        }
+    }
+    costs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{Ground, StructureManager};
+
+    // wall on x = 40 from y = -40 to 40, except a door at y = 35
+    fn walled_structure_manager() -> StructureManager {
+        let mut structure_manager = StructureManager::default();
+        for y in -40..=40 {
+            if y != 35 {
+                structure_manager.insert(IVec2::new(40, y), Entity::PLACEHOLDER);
+            }
+        }
+        structure_manager
+    }
+
+    fn assert_walkable(path: &VecDeque<IVec2>, passability: &PassabilitySnapshot) {
+        for (from, to) in path.iter().zip(path.iter().skip(1)) {
+            let step = (*to - *from).abs();
+            assert!(step.max_element() == 1, "{} -> {} is not a step", from, to);
+            assert!(passability.is_passable(*to), "{} is blocked", to);
+        }
+    }
+
+    #[test]
+    fn path_goes_through_the_door_of_the_wall() {
+        let passability =
+            PassabilitySnapshot::new(&walled_structure_manager(), &Ground::default(), 0);
+        let mut path_graph = PathGraph::default();
+
+        let path = path_graph
+            .find_path(IVec2::new(0, 0), IVec2::new(80, 0), &passability)
+            .unwrap();
+        assert_eq!(path.front(), Some(&IVec2::new(0, 0)));
+        assert_eq!(path.back(), Some(&IVec2::new(80, 0)));
+        assert!(path.contains(&IVec2::new(40, 35)));
+        assert_walkable(&path, &passability);
+    }
+
+    #[test]
+    fn closing_the_door_only_rebuilds_its_chunk_and_neighbours() {
+        let mut structure_manager = walled_structure_manager();
+        let mut path_graph = PathGraph::default();
+        path_graph
+            .find_path(
+                IVec2::new(0, 0),
+                IVec2::new(80, 0),
+                &PassabilitySnapshot::new(&structure_manager, &Ground::default(), 0),
+            )
+            .unwrap();
+        assert!(path_graph.is_chunk_built(IVec2::new(0, 0)));
+
+        structure_manager.insert(IVec2::new(40, 35), Entity::PLACEHOLDER);
+        path_graph.invalidate_chunk(rounded_tile_pos_to_rounded_chunk(IVec2::new(40, 35)));
+        // the door is in the chunk (1, 1)
+        assert!(!path_graph.is_chunk_built(IVec2::new(1, 1)));
+        assert!(path_graph.is_chunk_built(IVec2::new(0, 0)));
+
+        // goes around the end of the wall
+        let passability = PassabilitySnapshot::new(&structure_manager, &Ground::default(), 1);
+        let path = path_graph
+            .find_path(IVec2::new(0, 0), IVec2::new(80, 0), &passability)
+            .unwrap();
+        assert!(!path.contains(&IVec2::new(40, 35)));
+        assert_eq!(path.back(), Some(&IVec2::new(80, 0)));
+        assert_walkable(&path, &passability);
+    }
+
+    #[test]
+    fn merge_only_keeps_chunks_of_the_same_generation() {
+        let passability =
+            PassabilitySnapshot::new(&walled_structure_manager(), &Ground::default(), 0);
+        let mut path_graph = PathGraph::default();
+        let mut searched_graph = path_graph.clone();
+        searched_graph
+            .find_path(IVec2::new(0, 0), IVec2::new(80, 0), &passability)
+            .unwrap();
+
+        let mut newer_graph = path_graph.clone();
+        newer_graph.generation = 1;
+        newer_graph.merge(searched_graph.clone());
+        assert!(!newer_graph.is_chunk_built(IVec2::new(0, 0)));
+
+        path_graph.merge(searched_graph);
+        assert!(path_graph.is_chunk_built(IVec2::new(0, 0)));
+    }
+
+    #[test]
+    fn enclosed_target_has_no_path() {
+        let mut structure_manager = StructureManager::default();
+        for neighbor_pos in crate::map::get_neighbors(IVec2::new(80, 0)) {
+            structure_manager.insert(neighbor_pos, Entity::PLACEHOLDER);
+        }
+        let mut path_graph = PathGraph::default();
+        assert!(
+            path_graph
+                .find_path(
+                    IVec2::new(0, 0),
+                    IVec2::new(80, 0),
+                    &PassabilitySnapshot::new(&structure_manager, &Ground::default(), 0)
+                )
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,751 @@
+pub mod flow_field;
+pub mod hierarchical;
+
+use crate::UPS_TARGET;
+use crate::map::{
+    CHUNK_SIZE, Ground, GroundChanged, GroundManager, StructureChanged, StructureManager,
+    get_neighbors, local_tile_pos_to_rounded_tile, rounded_tile_pos_to_rounded_chunk,
+    send_structure_changes_system, sync_ground_system, world_pos_to_rounded_tile,
+};
+use crate::terrain::TileKind;
+use crate::units::{Direction, TileMovement};
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task, block_on, futures_lite::future},
+};
+use flow_field::{FLOW_FIELD_MIN_AGENTS, FlowFields};
+use hierarchical::PathGraph;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+// const LIMIT_STUCK_STICKS: u32 = UPS_TARGET as u32 * 10; // stops the pathfinding if stuck for too long
+const LIMIT_STUCK_STICKS: u32 = UPS_TARGET as u32 * 5; // stops the pathfinding if stuck for too long
+/// expansions of the A* looking for a detour around a new structure ; beyond, the whole path is computed again
+const REPAIR_LIMIT: usize = 500;
+
+pub struct PathfindingPlugin;
+
+impl Plugin for PathfindingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PathfindingSettings>()
+            .init_resource::<PassabilitySnapshot>()
+            .init_resource::<SharedPathGraph>()
+            .init_resource::<PathRequests>()
+            .init_resource::<FlowFields>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    invalidate_path_caches_system
+                        .after(send_structure_changes_system)
+                        .after(sync_ground_system),
+                    repair_paths_system.after(invalidate_path_caches_system),
+                    pathfinding_system.after(repair_paths_system),
+                    movement_system.after(pathfinding_system),
+                ),
+            );
+    }
+}
+
+#[derive(Resource, Debug)]
+pub struct PathfindingSettings {
+    /// paths started on the AsyncComputeTaskPool each tick ; the other requests wait in PathRequests
+    pub max_path_computations_per_tick: usize,
+    /// waits for the paths started at the previous tick instead of polling them, so a run is reproducible
+    pub wait_for_results: bool,
+}
+
+impl Default for PathfindingSettings {
+    fn default() -> Self {
+        Self {
+            max_path_computations_per_tick: 16,
+            wait_for_results: false,
+        }
+    }
+}
+
+/// copy of the blocked tiles and of the ground for the computations running on other threads
+/// replaced when a structure is added or removed or the ground changes
+#[derive(Resource, Clone, Default, Debug)]
+pub struct PassabilitySnapshot {
+    pub generation: u64,
+    blocked_tiles: Arc<HashSet<IVec2>>,
+    ground: Ground,
+}
+
+impl PassabilitySnapshot {
+    pub fn new(structure_manager: &StructureManager, ground: &Ground, generation: u64) -> Self {
+        Self {
+            generation,
+            blocked_tiles: Arc::new(structure_manager.structures.keys().copied().collect()),
+            ground: ground.clone(),
+        }
+    }
+
+    /// cost of walking on the tile, see TileKind::movement_cost ; None if a structure or water blocks it
+    pub fn movement_cost(&self, rounded_tile_pos: IVec2) -> Option<f32> {
+        if self.blocked_tiles.contains(&rounded_tile_pos) {
+            return None;
+        }
+        self.ground.movement_cost(rounded_tile_pos)
+    }
+
+    /// map::is_tile_passable, and not in water
+    pub fn is_passable(&self, rounded_tile_pos: IVec2) -> bool {
+        self.movement_cost(rounded_tile_pos).is_some()
+    }
+}
+
+/// the graph is kept between the computations ; each computation searches a clone of the current generation
+/// and gives back the chunks it built, so the searches never wait for each other nor for the FixedUpdate
+#[derive(Resource, Default, Debug)]
+pub struct SharedPathGraph(pub PathGraph);
+
+/// agents waiting for a computation, first come first served
+#[derive(Resource, Default, Debug)]
+pub struct PathRequests {
+    pub queue: VecDeque<Entity>,
+}
+
+/// path computed on the AsyncComputeTaskPool ; ignored if the target of the agent changed meanwhile
+/// also returns the PathGraph it searched, to keep the chunks it built
+#[derive(Component)]
+pub struct PathComputation {
+    pub target: IVec2,
+    task: Task<(Option<VecDeque<IVec2>>, PathGraph)>,
+}
+
+#[derive(Clone)]
+struct PathNode {
+    pos: IVec2,
+    g_cost: f32,
+    h_cost: f32,
+    parent: Option<IVec2>,
+}
+
+impl PathNode {
+    fn f_cost(&self) -> f32 {
+        self.g_cost + self.h_cost
+    }
+}
+
+impl Ord for PathNode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .f_cost()
+            .partial_cmp(&self.f_cost())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for PathNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for PathNode {
+    fn eq(&self, other: &Self) -> bool {
+        self.pos == other.pos
+    }
+}
+
+impl Eq for PathNode {}
+
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct PathfindingAgent {
+    pub target: Option<IVec2>,
+    pub path: VecDeque<IVec2>,
+    pub last_tile_pos: Option<IVec2>,
+    pub stuck_ticks_counter: u32,
+    /// follows the FlowField of its target instead of its own path ; the fields are not saved
+    #[serde(skip)]
+    pub follows_flow_field: bool,
+}
+
+impl Default for PathfindingAgent {
+    fn default() -> Self {
+        PathfindingAgent {
+            target: None,
+            path: VecDeque::new(),
+            last_tile_pos: None,
+            stuck_ticks_counter: 0,
+            follows_flow_field: false,
+        }
+    }
+}
+
+impl PathfindingAgent {
+    pub fn reset(&mut self) {
+        self.target = None;
+        self.path.clear();
+        self.last_tile_pos = None;
+        self.stuck_ticks_counter = 0;
+        self.follows_flow_field = false;
+    }
+}
+
+// ========== FONCTIONS UTILITAIRES ==========
+fn is_diagonal(from: IVec2, to: IVec2) -> bool {
+    (from.x - to.x).abs() == 1 && (from.y - to.y).abs() == 1
+}
+
+fn heuristic(a: IVec2, b: IVec2) -> f32 {
+    let dx = (a.x - b.x) as f32;
+    let dy = (a.y - b.y) as f32;
+    (dx * dx + dy * dy).sqrt()
+}
+
+// roads cost less than a tile : the heuristic must stay under the real cost
+fn cost_heuristic(a: IVec2, b: IVec2) -> f32 {
+    heuristic(a, b) * TileKind::MIN_MOVEMENT_COST
+}
+
+fn reconstruct_path(all_nodes: &HashMap<IVec2, PathNode>, mut current: IVec2) -> VecDeque<IVec2> {
+    let mut path = VecDeque::new();
+    while let Some(node) = all_nodes.get(&current) {
+        path.push_front(node.pos);
+        if let Some(parent) = node.parent {
+            current = parent;
+        } else {
+            break;
+        }
+    }
+    path
+}
+
+// reachable neighbors with the cost of the step, from the ground of the tile it goes to ; a diagonal can't cut a corner
+fn walkable_neighbors(
+    pos: IVec2,
+    movement_cost: &impl Fn(IVec2) -> Option<f32>,
+) -> impl Iterator<Item = (IVec2, f32)> {
+    get_neighbors(pos).filter_map(move |neighbor_pos| {
+        let cost = movement_cost(neighbor_pos)?;
+        if is_diagonal(pos, neighbor_pos) {
+            let corner_1 = IVec2 {
+                x: pos.x,
+                y: neighbor_pos.y,
+            };
+            let corner_2 = IVec2 {
+                x: neighbor_pos.x,
+                y: pos.y,
+            };
+            if movement_cost(corner_1).is_none() || movement_cost(corner_2).is_none() {
+                return None;
+            }
+            Some((neighbor_pos, 1.414 * cost))
+        } else {
+            Some((neighbor_pos, cost))
+        }
+    })
+}
+
+/// A* between two tiles ; on the expansions limit returns the path toward the closest node if return_partial_on_limit
+fn a_star(
+    start_grid: IVec2,
+    end_grid: IVec2,
+    movement_cost: impl Fn(IVec2) -> Option<f32>,
+    max_expansions: usize,
+    return_partial_on_limit: bool,
+) -> Option<VecDeque<IVec2>> {
+    let mut open_set = BinaryHeap::new();
+    let mut all_nodes: HashMap<IVec2, PathNode> = HashMap::new();
+
+    let start_node = PathNode {
+        pos: start_grid,
+        g_cost: 0.0,
+        h_cost: cost_heuristic(start_grid, end_grid),
+        parent: None,
+    };
+    open_set.push(start_node.clone());
+    all_nodes.insert(start_grid, start_node);
+
+    let mut expansions: usize = 0;
+
+    while let Some(current_node) = open_set.pop() {
+        // already reached with a lower cost ; happens more with the ground costs
+        if current_node.g_cost > all_nodes[&current_node.pos].g_cost {
+            continue;
+        }
+        expansions += 1;
+        if expansions > max_expansions {
+            if return_partial_on_limit {
+                if let Some(best) = all_nodes
+                    .values()
+                    .min_by(|a, b| a.h_cost.partial_cmp(&b.h_cost).unwrap_or(Ordering::Equal))
+                {
+                    return Some(reconstruct_path(&all_nodes, best.pos));
+                } else {
+                    return None;
+                }
+            } else {
+                return None;
+            }
+        }
+
+        if current_node.pos == end_grid {
+            return Some(reconstruct_path(&all_nodes, end_grid));
+        }
+
+        for (neighbor_pos, move_cost) in walkable_neighbors(current_node.pos, &movement_cost) {
+            let new_g_cost = current_node.g_cost + move_cost;
+
+            if let Some(existing_node) = all_nodes.get_mut(&neighbor_pos) {
+                if new_g_cost < existing_node.g_cost {
+                    existing_node.g_cost = new_g_cost;
+                    existing_node.parent = Some(current_node.pos);
+                    open_set.push(existing_node.clone());
+                }
+            } else {
+                let neighbor_node = PathNode {
+                    pos: neighbor_pos,
+                    g_cost: new_g_cost,
+                    h_cost: cost_heuristic(neighbor_pos, end_grid),
+                    parent: Some(current_node.pos),
+                };
+                open_set.push(neighbor_node.clone());
+                all_nodes.insert(neighbor_pos, neighbor_node);
+            }
+        }
+    }
+
+    None
+}
+
+fn find_path(
+    start_grid: IVec2,
+    end_grid: IVec2,
+    passability: &PassabilitySnapshot,
+    path_graph: &mut PathGraph,
+) -> Option<VecDeque<IVec2>> {
+    // if target not reachable, find nearest passable tile
+    let actual_end_grid = if !passability.is_passable(end_grid) {
+        find_nearest_passable_tile(end_grid, start_grid, passability).unwrap_or(start_grid)
+    } else {
+        end_grid
+    };
+
+    const BASE_LIMIT: usize = 500;
+    const PER_TILE_LIMIT: usize = 40;
+    const MAX_LIMIT: usize = 20_000;
+
+    let dist_tiles = heuristic(start_grid, actual_end_grid);
+    let per_tile_extra = ((dist_tiles).round() as isize).max(0) as usize;
+    let mut max_expansions = BASE_LIMIT + per_tile_extra * PER_TILE_LIMIT;
+    if max_expansions > MAX_LIMIT {
+        max_expansions = MAX_LIMIT;
+    }
+    let movement_cost = |pos| passability.movement_cost(pos);
+
+    // short trips: the entrances of the chunks would make a detour
+    if dist_tiles < CHUNK_SIZE.x as f32
+        && let Some(path) = a_star(
+            start_grid,
+            actual_end_grid,
+            movement_cost,
+            max_expansions,
+            false,
+        )
+    {
+        return Some(path);
+    }
+
+    let hierarchical_path = path_graph.find_path(start_grid, actual_end_grid, passability);
+    if hierarchical_path.is_some() {
+        return hierarchical_path;
+    }
+
+    // no route between the chunks : gets as close as possible
+    a_star(
+        start_grid,
+        actual_end_grid,
+        movement_cost,
+        max_expansions,
+        true,
+    )
+}
+
+// trouve la case passable la plus proche en privilégiant la direction d'approche
+fn find_nearest_passable_tile(
+    target: IVec2,
+    start: IVec2,
+    passability: &PassabilitySnapshot,
+) -> Option<IVec2> {
+    // Calcule la direction d'approche depuis le point de départ
+    let approach_dir = IVec2::new((target.x - start.x).signum(), (target.y - start.y).signum());
+
+    // Liste des directions à tester, en commençant par celle opposée à l'approche
+    let mut directions = Vec::new();
+
+    // Direction opposée à l'approche (côté le plus proche)
+    if approach_dir.x != 0 || approach_dir.y != 0 {
+        directions.push(IVec2::new(-approach_dir.x, -approach_dir.y));
+    }
+
+    // Directions perpendiculaires à l'approche
+    if approach_dir.x != 0 {
+        directions.push(IVec2::new(0, 1)); // Nord
+        directions.push(IVec2::new(0, -1)); // Sud
+    }
+    if approach_dir.y != 0 {
+        directions.push(IVec2::new(1, 0)); // Est
+        directions.push(IVec2::new(-1, 0)); // Ouest
+    }
+
+    // Diagonales (moins prioritaires)
+    directions.extend([
+        IVec2::new(-1, -1),
+        IVec2::new(1, -1),
+        IVec2::new(-1, 1),
+        IVec2::new(1, 1),
+    ]);
+
+    // Direction d'approche en dernier recours
+    if approach_dir.x != 0 || approach_dir.y != 0 {
+        directions.push(approach_dir);
+    }
+
+    // Teste chaque direction par ordre de priorité
+    for radius in 1..=5 {
+        // Réduit le rayon pour être plus efficace
+        for &dir in &directions {
+            let candidate = target + dir * radius;
+            if passability.is_passable(candidate) {
+                return Some(candidate);
+            }
+        }
+    }
+
+    None
+}
+
+// ========== SYSTÈMES BEVY ==========
+/// tiles covered by the chunk
+fn chunk_area(rounded_chunk_pos: IVec2) -> IRect {
+    IRect::from_corners(
+        local_tile_pos_to_rounded_tile(IVec2::ZERO, rounded_chunk_pos),
+        local_tile_pos_to_rounded_tile(CHUNK_SIZE.as_ivec2() - IVec2::ONE, rounded_chunk_pos),
+    )
+}
+
+/// forgets the parts of the graph and the flow fields where structures were added or removed or the ground changed
+pub fn invalidate_path_caches_system(
+    mut structure_changed_events: EventReader<StructureChanged>,
+    mut ground_changed_events: EventReader<GroundChanged>,
+    structure_manager: Res<StructureManager>,
+    ground_manager: Res<GroundManager>,
+    mut passability: ResMut<PassabilitySnapshot>,
+    mut shared_path_graph: ResMut<SharedPathGraph>,
+    mut flow_fields: ResMut<FlowFields>,
+) {
+    let mut changed_areas: Vec<IRect> = structure_changed_events
+        .read()
+        .map(|structure_changed| {
+            let rounded_tile_pos = structure_changed.rounded_tile_pos();
+            IRect::from_corners(rounded_tile_pos, rounded_tile_pos)
+        })
+        .collect();
+    let mut changed_chunks: HashSet<IVec2> = changed_areas
+        .iter()
+        .map(|area| rounded_tile_pos_to_rounded_chunk(area.min))
+        .collect();
+    for ground_changed in ground_changed_events.read() {
+        changed_areas.push(chunk_area(ground_changed.rounded_chunk_pos));
+        changed_chunks.insert(ground_changed.rounded_chunk_pos);
+    }
+    if changed_areas.is_empty() {
+        return;
+    }
+
+    let generation = passability.generation + 1;
+    let path_graph = &mut shared_path_graph.0;
+    path_graph.generation = generation;
+    for rounded_chunk_pos in changed_chunks {
+        path_graph.invalidate_chunk(rounded_chunk_pos);
+    }
+    for area in changed_areas {
+        flow_fields.invalidate_area(area);
+    }
+    *passability = PassabilitySnapshot::new(&structure_manager, &ground_manager.ground, generation);
+}
+
+/// paths going through a new structure (or a tile that became water) get a detour around it, or are computed again by pathfinding_system
+pub fn repair_paths_system(
+    mut structure_changed_events: EventReader<StructureChanged>,
+    mut ground_changed_events: EventReader<GroundChanged>,
+    passability: Res<PassabilitySnapshot>,
+    mut agents_query: Query<(&mut PathfindingAgent, &Transform)>,
+) {
+    let added_tiles: HashSet<IVec2> = structure_changed_events
+        .read()
+        .filter_map(|structure_changed| match structure_changed {
+            StructureChanged::Added(rounded_tile_pos) => Some(*rounded_tile_pos),
+            StructureChanged::Removed(_) => None,
+        })
+        .collect();
+    let changed_ground_chunks: HashSet<IVec2> = ground_changed_events
+        .read()
+        .map(|ground_changed| ground_changed.rounded_chunk_pos)
+        .collect();
+    if added_tiles.is_empty() && changed_ground_chunks.is_empty() {
+        return;
+    }
+
+    for (mut agent, transform) in agents_query.iter_mut() {
+        if !agent.path.iter().any(|rounded_tile_pos| {
+            added_tiles.contains(rounded_tile_pos)
+                || (changed_ground_chunks
+                    .contains(&rounded_tile_pos_to_rounded_chunk(*rounded_tile_pos))
+                    && !passability.is_passable(*rounded_tile_pos))
+        }) {
+            continue;
+        }
+        let current_tile_pos = world_pos_to_rounded_tile(transform.translation.xy());
+        if !repair_path(&mut agent.path, current_tile_pos, &passability) {
+            agent.path.clear();
+        }
+        agent.stuck_ticks_counter = 0;
+    }
+}
+
+/// replaces each blocked part of the path by a short detour ; false if one has none
+/// a spawned chunk can put several parts of the path in water
+fn repair_path(
+    path: &mut VecDeque<IVec2>,
+    current_tile_pos: IVec2,
+    passability: &PassabilitySnapshot,
+) -> bool {
+    while let Some(first_blocked) = path
+        .iter()
+        .position(|rounded_tile_pos| !passability.is_passable(*rounded_tile_pos))
+    {
+        // the target itself is blocked
+        let Some(after_blocked) = path
+            .iter()
+            .skip(first_blocked)
+            .position(|rounded_tile_pos| passability.is_passable(*rounded_tile_pos))
+            .map(|index| index + first_blocked)
+        else {
+            return false;
+        };
+        let detour_start = if first_blocked == 0 {
+            current_tile_pos
+        } else {
+            path[first_blocked - 1]
+        };
+        let Some(detour) = a_star(
+            detour_start,
+            path[after_blocked],
+            |pos| passability.movement_cost(pos),
+            REPAIR_LIMIT,
+            false,
+        ) else {
+            return false;
+        };
+
+        // [.. detour_start] + detour + [after detour end ..]
+        let rest = path.split_off(after_blocked);
+        path.truncate(first_blocked.saturating_sub(1));
+        path.extend(detour);
+        path.extend(rest.into_iter().skip(1));
+    }
+    true
+}
+
+/// Système qui calcule le chemin pour les agents.
+/// the agents going to the same tile share a flow field when they are many, the others get a path computed on the AsyncComputeTaskPool
+#[allow(clippy::too_many_arguments)]
+pub fn pathfinding_system(
+    mut commands: Commands,
+    mut agents_query: Query<(
+        Entity,
+        &mut PathfindingAgent,
+        &Transform,
+        Option<&mut PathComputation>,
+    )>,
+    passability: Res<PassabilitySnapshot>,
+    mut shared_path_graph: ResMut<SharedPathGraph>,
+    settings: Res<PathfindingSettings>,
+    mut path_requests: ResMut<PathRequests>,
+    mut flow_fields: ResMut<FlowFields>,
+) {
+    // results of the computations started at the previous ticks
+    let mut searched_graphs = Vec::new();
+    for (agent_ent, mut agent, _, path_computation) in agents_query.iter_mut() {
+        let Some(mut path_computation) = path_computation else {
+            continue;
+        };
+        let (result, searched_graph) = if settings.wait_for_results {
+            block_on(&mut path_computation.task)
+        } else {
+            match block_on(future::poll_once(&mut path_computation.task)) {
+                Some(result) => result,
+                None => continue,
+            }
+        };
+        searched_graphs.push(searched_graph);
+        // the agent may be despawned by a command of this frame
+        commands.entity(agent_ent).try_remove::<PathComputation>();
+        if agent.target != Some(path_computation.target)
+            || !agent.path.is_empty()
+            || agent.follows_flow_field
+        {
+            continue;
+        }
+        match result {
+            // computed before a structure was added on it : waits for a new computation
+            Some(new_path)
+                if new_path
+                    .iter()
+                    .skip(1)
+                    .any(|rounded_tile_pos| !passability.is_passable(*rounded_tile_pos)) => {}
+            Some(new_path) => agent.path = new_path,
+            None => agent.reset(),
+        }
+    }
+
+    // the graphs of an older generation are ignored by merge
+    for searched_graph in searched_graphs {
+        shared_path_graph.0.merge(searched_graph);
+    }
+
+    let is_waiting = |agent: &PathfindingAgent, path_computation: Option<&PathComputation>| {
+        agent.target.is_some()
+            && agent.path.is_empty()
+            && !agent.follows_flow_field
+            && path_computation.is_none()
+    };
+
+    let mut starts_by_target: HashMap<IVec2, Vec<IVec2>> = HashMap::new();
+    for (_, agent, transform, path_computation) in agents_query.iter() {
+        if is_waiting(agent, path_computation)
+            && let Some(target) = agent.target
+        {
+            let start_tile = world_pos_to_rounded_tile(transform.translation.xy());
+            starts_by_target.entry(target).or_default().push(start_tile);
+        }
+    }
+    for (target, starts) in starts_by_target.iter() {
+        if starts.len() >= FLOW_FIELD_MIN_AGENTS {
+            flow_fields.prepare(*target, starts, &passability);
+        }
+    }
+
+    for (agent_ent, mut agent, transform, path_computation) in agents_query.iter_mut() {
+        if !is_waiting(&agent, path_computation.as_deref()) {
+            continue;
+        }
+        let Some(target) = agent.target else {
+            continue;
+        };
+        let start_tile = world_pos_to_rounded_tile(transform.translation.xy());
+        if flow_fields.next_tile(target, start_tile).is_some() {
+            agent.follows_flow_field = true;
+        } else if !path_requests.queue.contains(&agent_ent) {
+            path_requests.queue.push_back(agent_ent);
+        }
+    }
+
+    let task_pool = AsyncComputeTaskPool::get();
+    let mut started = 0;
+    while started < settings.max_path_computations_per_tick
+        && let Some(agent_ent) = path_requests.queue.pop_front()
+    {
+        // the agent may have stopped waiting since its request
+        let Ok((_, agent, transform, path_computation)) = agents_query.get(agent_ent) else {
+            continue;
+        };
+        if !is_waiting(agent, path_computation) {
+            continue;
+        }
+        let Some(target) = agent.target else {
+            continue;
+        };
+        let start_tile = world_pos_to_rounded_tile(transform.translation.xy());
+        let passability = passability.clone();
+        // same generation as the snapshot ; the clone shares the chunks already built
+        let mut path_graph = shared_path_graph.0.clone();
+        let task = task_pool.spawn(async move {
+            let path = find_path(start_tile, target, &passability, &mut path_graph);
+            (path, path_graph)
+        });
+        commands
+            .entity(agent_ent)
+            .try_insert(PathComputation { target, task });
+        started += 1;
+    }
+
+    let followed_targets: HashSet<IVec2> = agents_query
+        .iter()
+        .filter(|(_, agent, _, _)| agent.follows_flow_field)
+        .filter_map(|(_, agent, _, _)| agent.target)
+        .collect();
+    flow_fields
+        .fields
+        .retain(|target, _| followed_targets.contains(target));
+}
+
+/// makes the entiry moves along the path
+pub fn movement_system(
+    mut agents_query: Query<(&mut PathfindingAgent, &mut TileMovement, &Transform)>,
+    flow_fields: Res<FlowFields>,
+) {
+    for (mut agent, mut tile_movement, transform) in agents_query.iter_mut() {
+        if agent.path.is_empty() && !agent.follows_flow_field {
+            continue;
+        }
+
+        let current_tile_pos = world_pos_to_rounded_tile(transform.translation.xy());
+
+        if let Some(last_tile_pos) = agent.last_tile_pos
+            && last_tile_pos == current_tile_pos
+        {
+            agent.stuck_ticks_counter += 1;
+        }
+        if agent.stuck_ticks_counter > LIMIT_STUCK_STICKS {
+            agent.reset();
+            continue;
+        }
+
+        // remove all leading waypoints that are equal to current pos
+        while let Some(&front) = agent.path.front() {
+            if front == current_tile_pos {
+                agent.path.pop_front();
+            } else {
+                break;
+            }
+        }
+        // the flow field gives the next waypoint
+        if agent.path.is_empty()
+            && agent.follows_flow_field
+            && let Some(target) = agent.target
+        {
+            match flow_fields.next_tile(target, current_tile_pos) {
+                Some(next_tile) if next_tile != current_tile_pos => agent.path.push_back(next_tile),
+                Some(_) => {}
+                // the field was dropped or doesn't go there : computes its own path
+                None => {
+                    tile_movement.direction = Direction::Null;
+                    agent.follows_flow_field = false;
+                    continue;
+                }
+            }
+        }
+        // if path got emptied, reset and clear direction
+        if agent.path.is_empty() {
+            tile_movement.direction = Direction::Null;
+            agent.reset();
+            continue;
+        }
+
+        let next_waypoint = *agent.path.front().unwrap();
+
+        agent.stuck_ticks_counter = 0;
+        agent.last_tile_pos = Some(current_tile_pos);
+
+        let delta = next_waypoint - current_tile_pos;
+        let step = IVec2::new(delta.x.signum(), delta.y.signum());
+        tile_movement.direction = Direction::from(step);
+    }
+}
@@ -0,0 +1,657 @@
+use crate::{
+    build::{Blueprint, BlueprintManager, spawn_blueprint},
+    items::{Durability, Inventory, Item, UniqueItemKind},
+    logistics::LogisticsChest,
+    map::{
+        Chest, ChunkManager, Crafter, Generated, GroundManager, StructureKind, StructureManager,
+        Wall, WorldSeed, place_structure, rounded_tile_pos_to_rounded_chunk,
+        rounded_tile_pos_to_world, spawn_chunk, spawn_structure, world_pos_to_rounded_tile,
+    },
+    pathfinding::PathfindingAgent,
+    terrain::TileKind,
+    units::{
+        TileMovement, Unit, UnitUnitCollisions,
+        jobs::{AssignedJob, Capabilities, Job, JobBoard},
+        states::{Available, Unavailable},
+        tasks::{
+            Action, ActionQueue, CurrentAction, CurrentTask, Reservations, Task, TaskKind,
+            TaskQueue,
+        },
+    },
+};
+use bevy::{input::common_conditions::input_just_pressed, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, fs, path::Path};
+
+/// bump it every time the save format changes ; older saves are refused
+pub const SAVE_VERSION: u32 = 1;
+pub const QUICKSAVE_PATH: &str = "saves/quicksave.ron";
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                save_system
+                    .pipe(write_quicksave_system)
+                    .run_if(input_just_pressed(KeyCode::F5)),
+                quickload_system.run_if(input_just_pressed(KeyCode::F9)),
+            ),
+        );
+    }
+}
+
+/// the whole simulation ; entities are the ones at save time and are remapped on load
+#[derive(Serialize, Deserialize)]
+pub struct SaveFile {
+    pub version: u32,
+    pub world_seed: u64,
+    pub spawned_chunks: Vec<IVec2>,
+    /// roads and other tiles laid over the generated ground
+    pub laid_ground: Vec<(IVec2, TileKind)>,
+    /// Generated structures removed by the player
+    pub removed_generated: Vec<IVec2>,
+    pub structures: Vec<SavedStructure>,
+    pub units: Vec<SavedUnit>,
+    pub unique_items: Vec<SavedUniqueItem>,
+    pub blueprints: Vec<SavedBlueprint>,
+    pub reservations: Reservations,
+    pub job_board: JobBoard,
+}
+
+/// structure placed by the player ; Generated structures come back with their chunk
+#[derive(Serialize, Deserialize)]
+pub struct SavedStructure {
+    pub entity: Entity,
+    pub kind: StructureKind,
+    pub rounded_tile_pos: IVec2,
+    pub inventory: Option<Inventory>,
+    pub crafter: Option<Crafter>,
+    pub logistics_chest: Option<LogisticsChest>,
+}
+
+/// unique item in an inventory of a structure or a unit
+#[derive(Serialize, Deserialize)]
+pub struct SavedUniqueItem {
+    pub entity: Entity,
+    pub kind: UniqueItemKind,
+    pub durability: Durability,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SavedBlueprint {
+    pub entity: Entity,
+    pub blueprint: Blueprint,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SavedUnit {
+    pub entity: Entity,
+    pub name: String,
+    pub rounded_tile_pos: IVec2,
+    pub tile_movement: TileMovement,
+    pub inventory: Inventory,
+    pub action_queue: ActionQueue,
+    pub current_action: CurrentAction,
+    pub current_task: CurrentTask,
+    pub task_queue: TaskQueue,
+    pub assigned_job: Option<Job>,
+    pub capabilities: Option<Capabilities>,
+    pub pathfinding_agent: PathfindingAgent,
+    pub available: bool,
+    pub unavailable: bool,
+    pub unit_unit_collisions: bool,
+}
+
+// only reads the version so we can refuse a save before parsing the rest
+#[derive(Deserialize)]
+struct SaveHeader {
+    version: u32,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(std::io::Error),
+    Serialize(ron::Error),
+    Deserialize(ron::error::SpannedError),
+    WrongVersion { found: u32, expected: u32 },
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(error) => write!(f, "io error: {}", error),
+            SaveError::Serialize(error) => write!(f, "can't serialize the save: {}", error),
+            SaveError::Deserialize(error) => write!(f, "can't parse the save: {}", error),
+            SaveError::WrongVersion { found, expected } => {
+                write!(f, "save version {} but expected {}", found, expected)
+            }
+        }
+    }
+}
+
+pub fn save_to_string(save: &SaveFile) -> Result<String, SaveError> {
+    ron::ser::to_string_pretty(save, ron::ser::PrettyConfig::default())
+        .map_err(SaveError::Serialize)
+}
+
+pub fn save_from_str(text: &str) -> Result<SaveFile, SaveError> {
+    let header: SaveHeader = ron::from_str(text).map_err(SaveError::Deserialize)?;
+    if header.version != SAVE_VERSION {
+        return Err(SaveError::WrongVersion {
+            found: header.version,
+            expected: SAVE_VERSION,
+        });
+    }
+    ron::from_str(text).map_err(SaveError::Deserialize)
+}
+
+pub fn write_save_file(save: &SaveFile, path: &Path) -> Result<(), SaveError> {
+    let text = save_to_string(save)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(SaveError::Io)?;
+    }
+    fs::write(path, text).map_err(SaveError::Io)
+}
+
+pub fn read_save_file(path: &Path) -> Result<SaveFile, SaveError> {
+    let text = fs::read_to_string(path).map_err(SaveError::Io)?;
+    save_from_str(&text)
+}
+
+// ========= entity remapping =========
+
+/// replaces the entities of the save by the ones spawned on load
+trait RemapEntities {
+    fn remap_entities(&mut self, entity_map: &HashMap<Entity, Entity>);
+}
+
+// entities missing from the save become PLACEHOLDER so actions using them fail instead of targeting a random entity
+fn remap(entity: &mut Entity, entity_map: &HashMap<Entity, Entity>) {
+    *entity = entity_map
+        .get(entity)
+        .copied()
+        .unwrap_or(Entity::PLACEHOLDER);
+}
+
+impl RemapEntities for Action {
+    fn remap_entities(&mut self, entity_map: &HashMap<Entity, Entity>) {
+        match self {
+            Action::MoveTo(_) => {}
+            Action::Craft { with, .. } => remap(with, entity_map),
+            Action::Take { from, .. } => remap(from, entity_map),
+            Action::Drop { to, .. } => remap(to, entity_map),
+            Action::TakeUnique { item, from } => {
+                remap(item, entity_map);
+                remap(from, entity_map);
+            }
+            Action::DropUnique { item, to } => {
+                remap(item, entity_map);
+                remap(to, entity_map);
+            }
+            Action::Build { blueprint } => remap(blueprint, entity_map),
+        }
+    }
+}
+
+impl RemapEntities for Task {
+    fn remap_entities(&mut self, entity_map: &HashMap<Entity, Entity>) {
+        match &mut self.kind {
+            TaskKind::Action(action) => action.remap_entities(entity_map),
+            TaskKind::DeliverItems { to, .. } => remap(to, entity_map),
+            TaskKind::GetItems { .. }
+            | TaskKind::Sequence
+            | TaskKind::Parallel
+            | TaskKind::AnyOf => {}
+        }
+        for sub_task in &mut self.sub_tasks {
+            sub_task.remap_entities(entity_map);
+        }
+    }
+}
+
+impl RemapEntities for ActionQueue {
+    fn remap_entities(&mut self, entity_map: &HashMap<Entity, Entity>) {
+        for action in &mut self.0 {
+            action.remap_entities(entity_map);
+        }
+    }
+}
+
+impl RemapEntities for CurrentAction {
+    fn remap_entities(&mut self, entity_map: &HashMap<Entity, Entity>) {
+        if let Some(action) = &mut self.action {
+            action.remap_entities(entity_map);
+        }
+    }
+}
+
+impl RemapEntities for CurrentTask {
+    fn remap_entities(&mut self, entity_map: &HashMap<Entity, Entity>) {
+        if let Some(task) = &mut self.task {
+            task.remap_entities(entity_map);
+        }
+    }
+}
+
+impl RemapEntities for TaskQueue {
+    fn remap_entities(&mut self, entity_map: &HashMap<Entity, Entity>) {
+        for task in &mut self.0 {
+            task.remap_entities(entity_map);
+        }
+    }
+}
+
+impl RemapEntities for Job {
+    fn remap_entities(&mut self, entity_map: &HashMap<Entity, Entity>) {
+        self.task.remap_entities(entity_map);
+    }
+}
+
+impl RemapEntities for JobBoard {
+    fn remap_entities(&mut self, entity_map: &HashMap<Entity, Entity>) {
+        for job in &mut self.jobs {
+            job.remap_entities(entity_map);
+        }
+    }
+}
+
+impl RemapEntities for Inventory {
+    fn remap_entities(&mut self, entity_map: &HashMap<Entity, Entity>) {
+        for item_entity in &mut self.unique_items {
+            remap(item_entity, entity_map);
+        }
+    }
+}
+
+impl RemapEntities for Crafter {
+    fn remap_entities(&mut self, entity_map: &HashMap<Entity, Entity>) {
+        if let Some(progress) = &mut self.progress {
+            remap(&mut progress.owner, entity_map);
+        }
+    }
+}
+
+impl RemapEntities for Reservations {
+    fn remap_entities(&mut self, entity_map: &HashMap<Entity, Entity>) {
+        let reserved = std::mem::take(&mut self.reserved);
+        for (chest, owner_map) in reserved {
+            let Some(&new_chest) = entity_map.get(&chest) else {
+                continue;
+            };
+            for (owner, kind_map) in owner_map {
+                let Some(&new_owner) = entity_map.get(&owner) else {
+                    continue;
+                };
+                self.reserved
+                    .entry(new_chest)
+                    .or_default()
+                    .insert(new_owner, kind_map);
+            }
+        }
+    }
+}
+
+// ========= systems =========
+
+/// the whole simulation as a SaveFile ; piped into write_quicksave_system by F5
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn save_system(
+    world_seed: Res<WorldSeed>,
+    chunk_manager: Res<ChunkManager>,
+    structure_manager: Res<StructureManager>,
+    ground_manager: Res<GroundManager>,
+    reservations: Res<Reservations>,
+    job_board: Res<JobBoard>,
+    structure_query: Query<
+        (
+            Has<Wall>,
+            Has<Chest>,
+            Option<&Crafter>,
+            Option<&Inventory>,
+            Option<&LogisticsChest>,
+        ),
+        Without<Generated>,
+    >,
+    unit_query: Query<(
+        Entity,
+        &Unit,
+        &Transform,
+        &TileMovement,
+        &Inventory,
+        &ActionQueue,
+        &CurrentAction,
+        &CurrentTask,
+        &TaskQueue,
+        Option<&AssignedJob>,
+        Option<&Capabilities>,
+        &PathfindingAgent,
+        Has<Available>,
+        Has<Unavailable>,
+        Has<UnitUnitCollisions>,
+    )>,
+    unique_item_query: Query<(Entity, &UniqueItemKind, &Durability), With<Item>>,
+    blueprint_query: Query<(Entity, &Blueprint)>,
+) -> SaveFile {
+    let mut structures = Vec::new();
+    for (&rounded_tile_pos, &entity) in structure_manager.structures.iter() {
+        let Ok((is_wall, is_chest, crafter, inventory, logistics_chest)) =
+            structure_query.get(entity)
+        else {
+            continue;
+        };
+        let kind = if is_chest {
+            StructureKind::Chest
+        } else if crafter.is_some() {
+            StructureKind::Crafter
+        } else if is_wall {
+            StructureKind::Wall
+        } else {
+            continue;
+        };
+        structures.push(SavedStructure {
+            entity,
+            kind,
+            rounded_tile_pos,
+            inventory: inventory.cloned(),
+            crafter: crafter.cloned(),
+            logistics_chest: logistics_chest.cloned(),
+        });
+    }
+
+    let units = unit_query
+        .iter()
+        .map(
+            |(
+                entity,
+                unit,
+                transform,
+                tile_movement,
+                inventory,
+                action_queue,
+                current_action,
+                current_task,
+                task_queue,
+                assigned_job,
+                capabilities,
+                pathfinding_agent,
+                available,
+                unavailable,
+                unit_unit_collisions,
+            )| SavedUnit {
+                entity,
+                name: unit.name.clone(),
+                rounded_tile_pos: world_pos_to_rounded_tile(transform.translation.xy()),
+                tile_movement: tile_movement.clone(),
+                inventory: inventory.clone(),
+                action_queue: action_queue.clone(),
+                current_action: current_action.clone(),
+                current_task: current_task.clone(),
+                task_queue: task_queue.clone(),
+                assigned_job: assigned_job.map(|assigned_job| assigned_job.0.clone()),
+                capabilities: capabilities.cloned(),
+                pathfinding_agent: pathfinding_agent.clone(),
+                available,
+                unavailable,
+                unit_unit_collisions,
+            },
+        )
+        .collect();
+
+    let unique_items = unique_item_query
+        .iter()
+        .map(|(entity, kind, durability)| SavedUniqueItem {
+            entity,
+            kind: *kind,
+            durability: *durability,
+        })
+        .collect();
+
+    let blueprints = blueprint_query
+        .iter()
+        .map(|(entity, blueprint)| SavedBlueprint {
+            entity,
+            blueprint: blueprint.clone(),
+        })
+        .collect();
+
+    SaveFile {
+        version: SAVE_VERSION,
+        world_seed: world_seed.0,
+        spawned_chunks: chunk_manager.spawned_chunks.keys().copied().collect(),
+        laid_ground: ground_manager
+            .ground
+            .laid()
+            .iter()
+            .map(|(rounded_tile_pos, tile_kind)| (*rounded_tile_pos, *tile_kind))
+            .collect(),
+        removed_generated: structure_manager
+            .removed_generated
+            .values()
+            .flatten()
+            .copied()
+            .collect(),
+        structures,
+        units,
+        unique_items,
+        blueprints,
+        reservations: reservations.clone(),
+        job_board: job_board.clone(),
+    }
+}
+
+fn write_quicksave_system(In(save): In<SaveFile>) {
+    match write_save_file(&save, Path::new(QUICKSAVE_PATH)) {
+        Ok(()) => info!("Game saved to {}", QUICKSAVE_PATH),
+        Err(error) => warn!("Save failed: {}", error),
+    }
+}
+
+fn quickload_system(mut commands: Commands) {
+    match read_save_file(Path::new(QUICKSAVE_PATH)) {
+        Ok(save) => {
+            commands.run_system_cached_with(load_system, save);
+            info!("Game loaded from {}", QUICKSAVE_PATH);
+        }
+        Err(error) => warn!("Load failed: {}", error),
+    }
+}
+
+/// replaces the whole simulation by the save
+#[allow(clippy::too_many_arguments)]
+pub fn load_system(
+    In(save): In<SaveFile>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut world_seed: ResMut<WorldSeed>,
+    mut chunk_manager: ResMut<ChunkManager>,
+    mut structure_manager: ResMut<StructureManager>,
+    mut ground_manager: ResMut<GroundManager>,
+    mut blueprint_manager: ResMut<BlueprintManager>,
+    mut reservations: ResMut<Reservations>,
+    mut job_board: ResMut<JobBoard>,
+    unit_query: Query<Entity, With<Unit>>,
+    unique_item_query: Query<Entity, With<Item>>,
+) {
+    // the structures kept when their chunk was unloaded aren't children of a chunk anymore
+    for (_, &structure_entity) in structure_manager.structures.iter() {
+        commands.entity(structure_entity).despawn();
+    }
+    for (_, chunk_entity) in chunk_manager.spawned_chunks.drain() {
+        commands.entity(chunk_entity).despawn();
+    }
+    for unit_entity in unit_query.iter() {
+        commands.entity(unit_entity).despawn();
+    }
+    for item_entity in unique_item_query.iter() {
+        commands.entity(item_entity).despawn();
+    }
+    structure_manager.clear();
+    ground_manager.clear_laid();
+    for (_, blueprint_entity) in blueprint_manager.blueprints.drain() {
+        commands.entity(blueprint_entity).despawn();
+    }
+
+    *world_seed = WorldSeed(save.world_seed);
+    load_save(
+        &mut commands,
+        &asset_server,
+        &world_seed,
+        &mut chunk_manager,
+        &mut structure_manager,
+        &mut ground_manager,
+        &mut blueprint_manager,
+        &mut reservations,
+        &mut job_board,
+        save,
+    );
+}
+
+/// spawns the content of the save in an empty world
+#[allow(clippy::too_many_arguments)]
+pub fn load_save(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    world_seed: &WorldSeed,
+    chunk_manager: &mut ResMut<ChunkManager>,
+    structure_manager: &mut ResMut<StructureManager>,
+    ground_manager: &mut ResMut<GroundManager>,
+    blueprint_manager: &mut ResMut<BlueprintManager>,
+    reservations: &mut ResMut<Reservations>,
+    job_board: &mut ResMut<JobBoard>,
+    save: SaveFile,
+) {
+    for (rounded_tile_pos, tile_kind) in save.laid_ground {
+        ground_manager.lay(rounded_tile_pos, tile_kind);
+    }
+    for rounded_tile_pos in save.removed_generated {
+        structure_manager
+            .removed_generated
+            .entry(rounded_tile_pos_to_rounded_chunk(rounded_tile_pos))
+            .or_default()
+            .insert(rounded_tile_pos);
+    }
+    for chunk_pos in save.spawned_chunks {
+        let chunk_entity = spawn_chunk(
+            commands,
+            asset_server,
+            structure_manager,
+            world_seed,
+            chunk_pos,
+        );
+        chunk_manager.spawned_chunks.insert(chunk_pos, chunk_entity);
+    }
+
+    // first spawns every entity so references between them can be remapped
+    let mut entity_map: HashMap<Entity, Entity> = HashMap::new();
+    let structure_entities: Vec<Entity> = save
+        .structures
+        .iter()
+        .map(|saved_structure| {
+            let entity = spawn_structure(commands, asset_server, saved_structure.kind);
+            entity_map.insert(saved_structure.entity, entity);
+            entity
+        })
+        .collect();
+    let unit_entities: Vec<Entity> = save
+        .units
+        .iter()
+        .map(|saved_unit| {
+            let entity = commands.spawn_empty().id();
+            entity_map.insert(saved_unit.entity, entity);
+            entity
+        })
+        .collect();
+    for saved_unique_item in save.unique_items {
+        let entity = commands
+            .spawn((Item, saved_unique_item.kind, saved_unique_item.durability))
+            .id();
+        entity_map.insert(saved_unique_item.entity, entity);
+    }
+    for saved_blueprint in save.blueprints {
+        let entity = spawn_blueprint(
+            commands,
+            asset_server,
+            blueprint_manager,
+            saved_blueprint.blueprint,
+        );
+        entity_map.insert(saved_blueprint.entity, entity);
+    }
+
+    for (saved_structure, entity) in save.structures.into_iter().zip(structure_entities) {
+        let mut entity_commands = commands.entity(entity);
+        if let Some(mut inventory) = saved_structure.inventory {
+            inventory.remap_entities(&entity_map);
+            entity_commands.insert(inventory);
+        }
+        if let Some(mut crafter) = saved_structure.crafter {
+            crafter.remap_entities(&entity_map);
+            entity_commands.insert(crafter);
+        }
+        if let Some(logistics_chest) = saved_structure.logistics_chest {
+            entity_commands.insert(logistics_chest);
+        }
+        place_structure(
+            commands,
+            asset_server,
+            &entity,
+            structure_manager,
+            chunk_manager,
+            world_seed,
+            saved_structure.rounded_tile_pos,
+        );
+    }
+
+    for (mut saved_unit, entity) in save.units.into_iter().zip(unit_entities) {
+        saved_unit.inventory.remap_entities(&entity_map);
+        saved_unit.action_queue.remap_entities(&entity_map);
+        saved_unit.current_action.remap_entities(&entity_map);
+        saved_unit.current_task.remap_entities(&entity_map);
+        saved_unit.task_queue.remap_entities(&entity_map);
+        if let Some(assigned_job) = &mut saved_unit.assigned_job {
+            assigned_job.remap_entities(&entity_map);
+        }
+
+        let world_pos = rounded_tile_pos_to_world(saved_unit.rounded_tile_pos);
+        let mut entity_commands = commands.entity(entity);
+        entity_commands.insert((
+            Unit {
+                name: saved_unit.name,
+            },
+            Sprite::from_image(asset_server.load("default.png")),
+            Transform::from_translation(world_pos.extend(0.0)),
+            saved_unit.tile_movement,
+            saved_unit.inventory,
+            saved_unit.action_queue,
+            saved_unit.current_action,
+            saved_unit.current_task,
+            saved_unit.task_queue,
+            saved_unit.pathfinding_agent,
+        ));
+        if saved_unit.available {
+            entity_commands.insert(Available);
+        }
+        if saved_unit.unavailable {
+            entity_commands.insert(Unavailable);
+        }
+        if saved_unit.unit_unit_collisions {
+            entity_commands.insert(UnitUnitCollisions);
+        }
+        if let Some(assigned_job) = saved_unit.assigned_job {
+            entity_commands.insert(AssignedJob(assigned_job));
+        }
+        if let Some(capabilities) = saved_unit.capabilities {
+            entity_commands.insert(capabilities);
+        }
+    }
+
+    let mut saved_reservations = save.reservations;
+    saved_reservations.remap_entities(&entity_map);
+    **reservations = saved_reservations;
+
+    let mut saved_job_board = save.job_board;
+    saved_job_board.remap_entities(&entity_map);
+    **job_board = saved_job_board;
+}
@@ -0,0 +1,647 @@
+use crate::{
+    items::{
+        CraftRecipeId, Inventory, InventoryCapacity, ItemId, ItemRegistry, ItemsTransferred,
+        UniqueItemKind, spawn_unique_item,
+    },
+    logistics::LogisticsChest,
+    map::{
+        CHEST_CAPACITY, ChunkManager, GroundManager, StructureKind, StructureManager, WorldSeed,
+        place_structure, rounded_tile_pos_to_world, spawn_structure, world_pos_to_rounded_tile,
+    },
+    simulation::{headless_app, run_ticks},
+    terrain::TileKind,
+    units::{
+        TileMovement, UNIT_CAPACITY, UNIT_DEFAULT_MOVEMENT_SPEED, Unit, UnitUnitCollisions,
+        jobs::{Capabilities, JobBoard},
+        states::{Available, Unavailable},
+        tasks::{
+            Action, CurrentTask, Task, TaskFailed, TaskKind, TaskQueue, TaskStatus,
+            process_current_action_system, update_task_completion_system,
+        },
+    },
+};
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, fs, path::Path};
+
+pub const DEFAULT_SCENARIO_PATH: &str = "assets/scenarios/default.ron";
+
+pub struct ScenarioPlugin;
+
+impl Plugin for ScenarioPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ScenarioEntities>().add_systems(
+            Startup,
+            spawn_scenario_system.run_if(resource_exists::<Scenario>),
+        );
+    }
+}
+
+/// world described by its structures and units ; `Scenario::app` builds a headless app from it
+#[derive(Resource, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Scenario {
+    /// random seed if missing
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub structures: Vec<ScenarioStructure>,
+    #[serde(default)]
+    pub units: Vec<ScenarioUnit>,
+    /// tiles laid over the generated ground, like roads
+    #[serde(default)]
+    pub ground: Vec<(IVec2, TileKind)>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScenarioStructure {
+    /// used by the tests to find the entity
+    pub name: String,
+    pub kind: StructureKind,
+    pub rounded_tile_pos: IVec2,
+    /// only for chests
+    #[serde(default)]
+    pub items: Vec<(ItemId, u32)>,
+    /// only for chests
+    #[serde(default)]
+    pub unique_items: Vec<UniqueItemKind>,
+    /// CHEST_CAPACITY if missing
+    #[serde(default)]
+    pub capacity: Option<InventoryCapacity>,
+    #[serde(default)]
+    pub logistics_chest: Option<LogisticsChest>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScenarioUnit {
+    /// the same for every copy when count > 1
+    pub name: String,
+    pub rounded_tile_pos: IVec2,
+    /// number of units spawned on the tile
+    #[serde(default = "default_count")]
+    pub count: u32,
+    #[serde(default)]
+    pub speed: UnitSpeed,
+    #[serde(default)]
+    pub collisions: bool,
+    #[serde(default)]
+    pub items: Vec<(ItemId, u32)>,
+    /// every copy of the unit gets its own items
+    #[serde(default)]
+    pub unique_items: Vec<UniqueItemKind>,
+    #[serde(default = "default_unit_capacity")]
+    pub capacity: InventoryCapacity,
+    /// None if the unit can do every job
+    #[serde(default)]
+    pub capabilities: Option<Capabilities>,
+    /// false if the unit never takes jobs from the JobBoard, see Unavailable
+    #[serde(default = "default_available")]
+    pub available: bool,
+    /// put in the TaskQueue of the unit
+    #[serde(default)]
+    pub tasks: Vec<ScenarioTask>,
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+fn default_unit_capacity() -> InventoryCapacity {
+    UNIT_CAPACITY
+}
+
+fn default_available() -> bool {
+    true
+}
+
+/// ticks per tile of a unit ; smaller is faster
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnitSpeed {
+    TicksPerTile(u32),
+    /// every unit gets its own speed in [min, max]
+    Random {
+        min: u32,
+        max: u32,
+    },
+}
+
+impl Default for UnitSpeed {
+    fn default() -> Self {
+        UnitSpeed::TicksPerTile(UNIT_DEFAULT_MOVEMENT_SPEED)
+    }
+}
+
+impl UnitSpeed {
+    pub fn ticks_per_tile(&self, rng: &mut impl Rng) -> u32 {
+        match self {
+            UnitSpeed::TicksPerTile(ticks_per_tile) => *ticks_per_tile,
+            UnitSpeed::Random { min, max } => rng.random_range(*min..=*max),
+        }
+    }
+}
+
+/// Task with the entities written as scenario names
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ScenarioTask {
+    MoveTo(IVec2),
+    Craft {
+        recipe: CraftRecipeId,
+        quantity: u32,
+        with: String,
+    },
+    Take {
+        kind: ItemId,
+        quantity: u32,
+        from: String,
+    },
+    Drop {
+        kind: ItemId,
+        quantity: u32,
+        to: String,
+    },
+    GetItems {
+        kind: ItemId,
+        quantity: u32,
+    },
+    DeliverItems {
+        kind: ItemId,
+        quantity: u32,
+        to: String,
+    },
+    Sequence(Vec<ScenarioTask>),
+    Parallel(Vec<ScenarioTask>),
+    AnyOf(Vec<ScenarioTask>),
+}
+
+impl ScenarioTask {
+    /// Err with the name if an entity doesn't exist
+    pub fn to_task(&self, entities: &HashMap<String, Entity>) -> Result<Task, String> {
+        let entity = |name: &String| entities.get(name).copied().ok_or_else(|| name.clone());
+        let sub_tasks = |scenario_tasks: &Vec<ScenarioTask>| {
+            scenario_tasks
+                .iter()
+                .map(|scenario_task| scenario_task.to_task(entities))
+                .collect::<Result<Vec<Task>, String>>()
+        };
+        let kind = match self {
+            ScenarioTask::MoveTo(rounded_tile_pos) => {
+                TaskKind::Action(Action::MoveTo(*rounded_tile_pos))
+            }
+            ScenarioTask::Craft {
+                recipe,
+                quantity,
+                with,
+            } => TaskKind::Action(Action::Craft {
+                recipe: *recipe,
+                quantity: *quantity,
+                with: entity(with)?,
+            }),
+            ScenarioTask::Take {
+                kind,
+                quantity,
+                from,
+            } => TaskKind::Action(Action::Take {
+                kind: *kind,
+                quantity: *quantity,
+                from: entity(from)?,
+            }),
+            ScenarioTask::Drop { kind, quantity, to } => TaskKind::Action(Action::Drop {
+                kind: *kind,
+                quantity: *quantity,
+                to: entity(to)?,
+            }),
+            ScenarioTask::GetItems { kind, quantity } => TaskKind::GetItems {
+                kind: *kind,
+                quantity: *quantity,
+            },
+            ScenarioTask::DeliverItems { kind, quantity, to } => TaskKind::DeliverItems {
+                kind: *kind,
+                quantity: *quantity,
+                to: entity(to)?,
+            },
+            ScenarioTask::Sequence(scenario_tasks) => {
+                return Ok(Task::sequence(sub_tasks(scenario_tasks)?));
+            }
+            ScenarioTask::Parallel(scenario_tasks) => {
+                return Ok(Task::parallel(sub_tasks(scenario_tasks)?));
+            }
+            ScenarioTask::AnyOf(scenario_tasks) => {
+                return Ok(Task::any_of(sub_tasks(scenario_tasks)?));
+            }
+        };
+        Ok(Task::new(kind, Vec::new()))
+    }
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(std::io::Error),
+    Deserialize(ron::error::SpannedError),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(error) => write!(f, "io error: {}", error),
+            ScenarioError::Deserialize(error) => write!(f, "can't parse the scenario: {}", error),
+        }
+    }
+}
+
+impl ScenarioStructure {
+    pub fn new(name: &str, kind: StructureKind, rounded_tile_pos: IVec2) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+            rounded_tile_pos,
+            items: Vec::new(),
+            unique_items: Vec::new(),
+            capacity: None,
+            logistics_chest: None,
+        }
+    }
+
+    pub fn with_items(mut self, items: impl IntoIterator<Item = (ItemId, u32)>) -> Self {
+        self.items.extend(items);
+        self
+    }
+
+    pub fn with_unique_items(mut self, kinds: impl IntoIterator<Item = UniqueItemKind>) -> Self {
+        self.unique_items.extend(kinds);
+        self
+    }
+
+    pub fn with_capacity(mut self, capacity: InventoryCapacity) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    pub fn with_logistics(mut self, logistics_chest: LogisticsChest) -> Self {
+        self.logistics_chest = Some(logistics_chest);
+        self
+    }
+}
+
+impl ScenarioUnit {
+    pub fn new(name: &str, rounded_tile_pos: IVec2) -> Self {
+        Self {
+            name: name.into(),
+            rounded_tile_pos,
+            count: 1,
+            speed: UnitSpeed::default(),
+            collisions: false,
+            items: Vec::new(),
+            unique_items: Vec::new(),
+            capacity: UNIT_CAPACITY,
+            capabilities: None,
+            available: true,
+            tasks: Vec::new(),
+        }
+    }
+
+    pub fn with_speed(mut self, ticks_per_tile: u32) -> Self {
+        self.speed = UnitSpeed::TicksPerTile(ticks_per_tile);
+        self
+    }
+
+    pub fn with_tasks(mut self, tasks: impl IntoIterator<Item = ScenarioTask>) -> Self {
+        self.tasks.extend(tasks);
+        self
+    }
+
+    pub fn with_items(mut self, items: impl IntoIterator<Item = (ItemId, u32)>) -> Self {
+        self.items.extend(items);
+        self
+    }
+
+    pub fn with_unique_items(mut self, kinds: impl IntoIterator<Item = UniqueItemKind>) -> Self {
+        self.unique_items.extend(kinds);
+        self
+    }
+
+    pub fn with_capacity(mut self, capacity: InventoryCapacity) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    pub fn unavailable(mut self) -> Self {
+        self.available = false;
+        self
+    }
+
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = Some(capabilities);
+        self
+    }
+}
+
+impl Scenario {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed: Some(seed),
+            ..default()
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ScenarioError> {
+        let text = fs::read_to_string(path).map_err(ScenarioError::Io)?;
+        ron::from_str(&text).map_err(ScenarioError::Deserialize)
+    }
+
+    pub fn structure(mut self, structure: ScenarioStructure) -> Self {
+        self.structures.push(structure);
+        self
+    }
+
+    pub fn unit(mut self, unit: ScenarioUnit) -> Self {
+        self.units.push(unit);
+        self
+    }
+
+    pub fn ground(mut self, rounded_tile_pos: IVec2, tile_kind: TileKind) -> Self {
+        self.ground.push((rounded_tile_pos, tile_kind));
+        self
+    }
+
+    /// headless app with the scenario spawned ; no tick has run yet
+    pub fn app(self) -> ScenarioApp {
+        let world_seed = self.seed.map(WorldSeed).unwrap_or_default();
+        let mut app = headless_app(world_seed);
+        app.add_plugins(ScenarioPlugin)
+            .insert_resource(self)
+            .init_resource::<ScenarioFailures>()
+            .init_resource::<ScenarioTransfers>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    record_task_failures_system.after(update_task_completion_system),
+                    record_transfers_system.after(process_current_action_system),
+                ),
+            );
+        run_ticks(&mut app, 0);
+        ScenarioApp { app, ticks: 0 }
+    }
+}
+
+/// name -> entity of everything spawned by the scenario
+#[derive(Resource, Default)]
+pub struct ScenarioEntities(pub HashMap<String, Entity>);
+
+/// every TaskFailed sent since the start ; events are dropped after 2 updates so they are kept here
+#[derive(Resource, Default)]
+pub struct ScenarioFailures(pub Vec<TaskFailed>);
+
+/// every ItemsTransferred sent since the start
+#[derive(Resource, Default)]
+pub struct ScenarioTransfers(pub Vec<ItemsTransferred>);
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_scenario_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    item_registry: Res<ItemRegistry>,
+    scenario: Res<Scenario>,
+    mut scenario_entities: ResMut<ScenarioEntities>,
+    mut structure_manager: ResMut<StructureManager>,
+    mut chunk_manager: ResMut<ChunkManager>,
+    mut ground_manager: ResMut<GroundManager>,
+    world_seed: Res<WorldSeed>,
+) {
+    for (rounded_tile_pos, tile_kind) in scenario.ground.iter() {
+        ground_manager.lay(*rounded_tile_pos, *tile_kind);
+    }
+    for structure in scenario.structures.iter() {
+        let structure_ent = spawn_structure(&mut commands, &asset_server, structure.kind);
+        if structure.kind == StructureKind::Chest {
+            let mut inventory =
+                Inventory::with_capacity(structure.capacity.unwrap_or(CHEST_CAPACITY));
+            for (kind, quantity) in structure.items.iter() {
+                let overflow = inventory.add(*kind, *quantity, &item_registry);
+                if overflow > 0 {
+                    println!(
+                        "Scenario: {} {} don't fit in {}",
+                        overflow, kind, structure.name
+                    );
+                }
+            }
+            add_unique_items(
+                &mut commands,
+                &item_registry,
+                &mut inventory,
+                &structure.unique_items,
+                &structure.name,
+            );
+            commands.entity(structure_ent).insert(inventory);
+        }
+        if let Some(logistics_chest) = &structure.logistics_chest {
+            commands
+                .entity(structure_ent)
+                .insert(logistics_chest.clone());
+        }
+        place_structure(
+            &mut commands,
+            &asset_server,
+            &structure_ent,
+            &mut structure_manager,
+            &mut chunk_manager,
+            &world_seed,
+            structure.rounded_tile_pos,
+        );
+        scenario_entities
+            .0
+            .insert(structure.name.clone(), structure_ent);
+    }
+
+    let mut rng = world_seed.units_rng();
+    let unit_texture_handle = asset_server.load("default.png");
+    for unit in scenario.units.iter() {
+        // every structure has its entity, so the tasks can point to them
+        let mut task_queue = TaskQueue::default();
+        for scenario_task in unit.tasks.iter() {
+            match scenario_task.to_task(&scenario_entities.0) {
+                Ok(task) => task_queue.0.push_back(task),
+                Err(name) => println!(
+                    "Scenario: unknown entity {} in a task of {}",
+                    name, unit.name
+                ),
+            }
+        }
+        let mut inventory = Inventory::with_capacity(unit.capacity);
+        for (kind, quantity) in unit.items.iter() {
+            let overflow = inventory.add(*kind, *quantity, &item_registry);
+            if overflow > 0 {
+                println!("Scenario: {} {} don't fit in {}", overflow, kind, unit.name);
+            }
+        }
+        let world_pos = rounded_tile_pos_to_world(unit.rounded_tile_pos);
+
+        for _i in 0..unit.count {
+            let mut inventory = inventory.clone();
+            add_unique_items(
+                &mut commands,
+                &item_registry,
+                &mut inventory,
+                &unit.unique_items,
+                &unit.name,
+            );
+            let mut unit_commands = commands.spawn((
+                Unit {
+                    name: unit.name.clone(),
+                },
+                Sprite::from_image(unit_texture_handle.clone()),
+                Transform::from_translation(world_pos.extend(0.0)),
+                TileMovement::new(unit.speed.ticks_per_tile(&mut rng)),
+                inventory,
+                task_queue.clone(),
+            ));
+            if unit.available {
+                unit_commands.insert(Available);
+            } else {
+                unit_commands.insert(Unavailable);
+            }
+            if unit.collisions {
+                unit_commands.insert(UnitUnitCollisions);
+            }
+            if let Some(capabilities) = &unit.capabilities {
+                unit_commands.insert(capabilities.clone());
+            }
+            // with count > 1 the name points to the last one
+            scenario_entities
+                .0
+                .insert(unit.name.clone(), unit_commands.id());
+        }
+    }
+}
+
+fn add_unique_items(
+    commands: &mut Commands,
+    item_registry: &ItemRegistry,
+    inventory: &mut Inventory,
+    kinds: &[UniqueItemKind],
+    name: &str,
+) {
+    for kind in kinds {
+        if !inventory.has_space_for_unique_item(item_registry) {
+            println!("Scenario: {:?} doesn't fit in {}", kind, name);
+            continue;
+        }
+        let item_ent = spawn_unique_item(commands, *kind);
+        inventory.add_unique_item(item_ent);
+    }
+}
+
+fn record_task_failures_system(
+    mut task_failed_events: EventReader<TaskFailed>,
+    mut scenario_failures: ResMut<ScenarioFailures>,
+) {
+    scenario_failures
+        .0
+        .extend(task_failed_events.read().cloned());
+}
+
+fn record_transfers_system(
+    mut items_transferred_events: EventReader<ItemsTransferred>,
+    mut scenario_transfers: ResMut<ScenarioTransfers>,
+) {
+    scenario_transfers
+        .0
+        .extend(items_transferred_events.read().copied());
+}
+
+/// headless app of a scenario, with helpers to step it and look at the entities by name
+pub struct ScenarioApp {
+    pub app: App,
+    /// FixedUpdate ticks run since the scenario was spawned
+    pub ticks: u32,
+}
+
+impl ScenarioApp {
+    pub fn run_ticks(&mut self, ticks: u32) {
+        run_ticks(&mut self.app, ticks);
+        self.ticks += ticks;
+    }
+
+    /// runs until `condition` is true ; returns false if it still isn't after `max_ticks`
+    pub fn run_until(&mut self, max_ticks: u32, condition: impl Fn(&Self) -> bool) -> bool {
+        for _ in 0..max_ticks {
+            if condition(self) {
+                return true;
+            }
+            self.run_ticks(1);
+        }
+        condition(self)
+    }
+
+    pub fn entity(&self, name: &str) -> Entity {
+        match self.app.world().resource::<ScenarioEntities>().0.get(name) {
+            Some(entity) => *entity,
+            None => panic!("no entity named {} in the scenario", name),
+        }
+    }
+
+    pub fn inventory(&self, name: &str) -> &Inventory {
+        self.app
+            .world()
+            .get::<Inventory>(self.entity(name))
+            .expect("entity has no inventory")
+    }
+
+    pub fn count(&self, name: &str, kind: ItemId) -> u32 {
+        self.inventory(name).count(&kind)
+    }
+
+    /// unique items of this kind in the inventory
+    pub fn unique_items(&self, name: &str, kind: UniqueItemKind) -> Vec<Entity> {
+        self.inventory(name)
+            .unique_items
+            .iter()
+            .copied()
+            .filter(|item_ent| self.app.world().get::<UniqueItemKind>(*item_ent) == Some(&kind))
+            .collect()
+    }
+
+    pub fn rounded_tile_pos(&self, name: &str) -> IVec2 {
+        let transform = self
+            .app
+            .world()
+            .get::<Transform>(self.entity(name))
+            .expect("entity has no transform");
+        world_pos_to_rounded_tile(transform.translation.xy())
+    }
+
+    /// status of the root task of the unit ; None if the unit has nothing to do
+    pub fn task_status(&self, name: &str) -> Option<TaskStatus> {
+        self.app
+            .world()
+            .get::<CurrentTask>(self.entity(name))
+            .and_then(|current_task| current_task.task.as_ref())
+            .map(|task| task.status)
+    }
+
+    pub fn failures(&self) -> &[TaskFailed] {
+        &self.app.world().resource::<ScenarioFailures>().0
+    }
+
+    pub fn transfers(&self) -> &[ItemsTransferred] {
+        &self.app.world().resource::<ScenarioTransfers>().0
+    }
+
+    /// gives the task directly to the unit, without going through the JobBoard
+    pub fn give_task(&mut self, name: &str, task: Task) {
+        let unit_ent = self.entity(name);
+        let mut current_task = self
+            .app
+            .world_mut()
+            .get_mut::<CurrentTask>(unit_ent)
+            .expect("entity is not a unit");
+        current_task.task = Some(task);
+        current_task.initialized = false;
+    }
+
+    pub fn post_job(&mut self, task: Task, priority: i32, location: Option<IVec2>) -> u64 {
+        self.app
+            .world_mut()
+            .resource_mut::<JobBoard>()
+            .post(task, priority, location)
+    }
+}
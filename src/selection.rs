@@ -0,0 +1,368 @@
+use crate::{
+    build::BuildMode,
+    items::Inventory,
+    map::{Chest, StructureManager, TILE_SIZE, cursor_world_pos, world_pos_to_rounded_tile},
+    pathfinding::PathfindingAgent,
+    units::{
+        Unit,
+        jobs::{AssignedJob, JobBoard},
+        tasks::{
+            Action, ActionQueue, CurrentAction, CurrentTask, Reservations, Task, TaskKind,
+            reset_actions_system,
+        },
+    },
+};
+use bevy::{gizmos::config::GizmoConfigStore, prelude::*};
+use std::collections::HashMap;
+
+pub const SELECTION_COLOR: Color = Color::srgb(0.2, 1.0, 0.2);
+const CONTROL_GROUP_KEYS: [KeyCode; 10] = [
+    KeyCode::Digit0,
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+];
+
+pub struct SelectionPlugin;
+
+impl Plugin for SelectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SelectionDrag>()
+            .init_resource::<ControlGroups>()
+            .add_event::<SelectionOrder>()
+            .add_systems(
+                Update,
+                (
+                    mouse_selection_system,
+                    control_groups_system.after(mouse_selection_system),
+                    mouse_order_system.after(control_groups_system),
+                    apply_selection_orders_system.after(mouse_order_system),
+                    // there are no gizmos without the renderer
+                    draw_selection_system.run_if(resource_exists::<GizmoConfigStore>),
+                ),
+            );
+    }
+}
+
+/// unit selected by the player ; the orders only apply to them
+#[derive(Component)]
+pub struct Selected;
+
+/// world position where the left button was pressed
+#[derive(Resource, Default)]
+pub struct SelectionDrag {
+    pub start: Option<Vec2>,
+}
+
+/// Ctrl + number saves the selection, number selects it again
+#[derive(Resource, Default)]
+pub struct ControlGroups(pub HashMap<usize, Vec<Entity>>);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    MoveTo(IVec2),
+    /// takes everything it can from the chest
+    Fetch {
+        from: Entity,
+    },
+    /// drops everything the unit carries in the chest
+    Deliver {
+        to: Entity,
+    },
+}
+
+/// order given to every Selected unit
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SelectionOrder(pub Order);
+
+/// click selects the unit under the cursor, drag selects the units in the box ; Shift adds to the selection
+#[allow(clippy::too_many_arguments)]
+fn mouse_selection_system(
+    mut commands: Commands,
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    build_mode: Res<BuildMode>,
+    mut selection_drag: ResMut<SelectionDrag>,
+    windows: Query<&Window>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    unit_query: Query<(Entity, &Transform, Has<Selected>), With<Unit>>,
+) {
+    // the left click builds in build mode
+    if *build_mode != BuildMode::None {
+        selection_drag.start = None;
+        return;
+    }
+    let Some(cursor_pos) = cursor_world_pos(&windows, &cameras) else {
+        return;
+    };
+    if mouse_input.just_pressed(MouseButton::Left) {
+        selection_drag.start = Some(cursor_pos);
+    }
+    if !mouse_input.just_released(MouseButton::Left) {
+        return;
+    }
+    let Some(start) = selection_drag.start.take() else {
+        return;
+    };
+
+    let rect = Rect::from_corners(start, cursor_pos);
+    let is_click = rect.width() < TILE_SIZE.x / 2.0 && rect.height() < TILE_SIZE.y / 2.0;
+    let clicked_tile_pos = world_pos_to_rounded_tile(cursor_pos);
+    let mut to_select: Vec<Entity> = unit_query
+        .iter()
+        .filter(|(_, transform, _)| {
+            let unit_pos = transform.translation.xy();
+            if is_click {
+                world_pos_to_rounded_tile(unit_pos) == clicked_tile_pos
+            } else {
+                rect.contains(unit_pos)
+            }
+        })
+        .map(|(unit_ent, _, _)| unit_ent)
+        .collect();
+    // a click selects only one unit
+    if is_click {
+        to_select.truncate(1);
+    }
+
+    let add_to_selection = keyboard_input.pressed(KeyCode::ShiftLeft);
+    for (unit_ent, _, is_selected) in unit_query.iter() {
+        let selected = to_select.contains(&unit_ent) || (add_to_selection && is_selected);
+        if selected && !is_selected {
+            commands.entity(unit_ent).insert(Selected);
+        } else if !selected && is_selected {
+            commands.entity(unit_ent).remove::<Selected>();
+        }
+    }
+}
+
+fn control_groups_system(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut control_groups: ResMut<ControlGroups>,
+    unit_query: Query<(Entity, Has<Selected>), With<Unit>>,
+) {
+    let Some(group) = CONTROL_GROUP_KEYS
+        .iter()
+        .position(|key| keyboard_input.just_pressed(*key))
+    else {
+        return;
+    };
+
+    if keyboard_input.pressed(KeyCode::ControlLeft) {
+        let selected: Vec<Entity> = unit_query
+            .iter()
+            .filter(|(_, is_selected)| *is_selected)
+            .map(|(unit_ent, _)| unit_ent)
+            .collect();
+        info!("Control group {}: {} units", group, selected.len());
+        control_groups.0.insert(group, selected);
+        return;
+    }
+
+    let Some(group_units) = control_groups.0.get_mut(&group) else {
+        return;
+    };
+    // forgets the units that died
+    group_units.retain(|unit_ent| unit_query.contains(*unit_ent));
+    for (unit_ent, is_selected) in unit_query.iter() {
+        let selected = group_units.contains(&unit_ent);
+        if selected && !is_selected {
+            commands.entity(unit_ent).insert(Selected);
+        } else if !selected && is_selected {
+            commands.entity(unit_ent).remove::<Selected>();
+        }
+    }
+}
+
+/// right click on a chest delivers the carried items (Shift: fetches its items), anywhere else moves
+fn mouse_order_system(
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    structure_manager: Res<StructureManager>,
+    windows: Query<&Window>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    chest_query: Query<(), With<Chest>>,
+    mut selection_order_events: EventWriter<SelectionOrder>,
+) {
+    if !mouse_input.just_pressed(MouseButton::Right) {
+        return;
+    }
+    let Some(cursor_pos) = cursor_world_pos(&windows, &cameras) else {
+        return;
+    };
+    let rounded_tile_pos = world_pos_to_rounded_tile(cursor_pos);
+
+    let order = match structure_manager.structures.get(&rounded_tile_pos) {
+        Some(&chest_ent) if chest_query.contains(chest_ent) => {
+            if keyboard_input.pressed(KeyCode::ShiftLeft) {
+                Order::Fetch { from: chest_ent }
+            } else {
+                Order::Deliver { to: chest_ent }
+            }
+        }
+        _ => Order::MoveTo(rounded_tile_pos),
+    };
+    selection_order_events.write(SelectionOrder(order));
+}
+
+/// the order replaces what the unit was doing ; its job goes back on the JobBoard
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn apply_selection_orders_system(
+    mut commands: Commands,
+    mut selection_order_events: EventReader<SelectionOrder>,
+    mut reservations: ResMut<Reservations>,
+    mut job_board: ResMut<JobBoard>,
+    mut unit_query: Query<
+        (
+            Entity,
+            &Inventory,
+            &mut CurrentTask,
+            &mut CurrentAction,
+            &mut ActionQueue,
+            &mut PathfindingAgent,
+            Option<&AssignedJob>,
+        ),
+        With<Selected>,
+    >,
+    chest_query: Query<(&GlobalTransform, &Inventory), With<Chest>>,
+) {
+    for SelectionOrder(order) in selection_order_events.read() {
+        for (
+            unit_ent,
+            unit_inventory,
+            mut current_task,
+            mut current_action,
+            mut action_queue,
+            mut pathfinding_agent,
+            assigned_job,
+        ) in unit_query.iter_mut()
+        {
+            let chest_ent = match order {
+                Order::MoveTo(_) => None,
+                Order::Fetch { from: chest_ent } | Order::Deliver { to: chest_ent } => {
+                    Some(*chest_ent)
+                }
+            };
+            let chest = chest_ent.and_then(|chest_ent| chest_query.get(chest_ent).ok());
+            let Some(task) = order_task(*order, unit_inventory, chest) else {
+                continue;
+            };
+
+            if let Some(assigned_job) = assigned_job {
+                job_board.jobs.push(assigned_job.0.clone());
+                commands.entity(unit_ent).remove::<AssignedJob>();
+            }
+            reservations.release_all_for_owner(unit_ent);
+            reset_actions_system(
+                &mut action_queue,
+                &mut current_action,
+                &mut pathfinding_agent,
+            );
+            current_task.task = Some(task);
+            current_task.initialized = false;
+        }
+    }
+}
+
+/// None if there is nothing to fetch or deliver ; `chest` is the target of Fetch and Deliver
+fn order_task(
+    order: Order,
+    unit_inventory: &Inventory,
+    chest: Option<(&GlobalTransform, &Inventory)>,
+) -> Option<Task> {
+    let action = |action| Task::new(TaskKind::Action(action), Vec::new());
+    let chest = chest.map(|(global_transform, inventory)| {
+        (
+            world_pos_to_rounded_tile(global_transform.translation().xy()),
+            inventory,
+        )
+    });
+
+    match order {
+        Order::MoveTo(rounded_tile_pos) => Some(action(Action::MoveTo(rounded_tile_pos))),
+        Order::Fetch { from } => {
+            let (chest_tile_pos, chest_inventory) = chest?;
+            let mut takes: Vec<Task> = chest_inventory
+                .stackable_items
+                .iter()
+                .map(|(kind, quantity)| {
+                    action(Action::Take {
+                        kind: *kind,
+                        quantity: *quantity,
+                        from,
+                    })
+                })
+                .collect();
+            takes.extend(
+                chest_inventory
+                    .unique_items
+                    .iter()
+                    .map(|item| action(Action::TakeUnique { item: *item, from })),
+            );
+            if takes.is_empty() {
+                return None;
+            }
+            Some(Task::sequence(vec![
+                action(Action::MoveTo(chest_tile_pos)),
+                Task::parallel(takes),
+            ]))
+        }
+        Order::Deliver { to } => {
+            let (chest_tile_pos, _) = chest?;
+            let mut drops: Vec<Task> = unit_inventory
+                .stackable_items
+                .iter()
+                .map(|(kind, quantity)| {
+                    action(Action::Drop {
+                        kind: *kind,
+                        quantity: *quantity,
+                        to,
+                    })
+                })
+                .collect();
+            drops.extend(
+                unit_inventory
+                    .unique_items
+                    .iter()
+                    .map(|item| action(Action::DropUnique { item: *item, to })),
+            );
+            if drops.is_empty() {
+                return None;
+            }
+            Some(Task::sequence(vec![
+                action(Action::MoveTo(chest_tile_pos)),
+                Task::parallel(drops),
+            ]))
+        }
+    }
+}
+
+/// circle around the selected units and box while dragging
+fn draw_selection_system(
+    mut gizmos: Gizmos,
+    selection_drag: Res<SelectionDrag>,
+    windows: Query<&Window>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    unit_query: Query<&Transform, (With<Unit>, With<Selected>)>,
+) {
+    for transform in unit_query.iter() {
+        gizmos.circle_2d(
+            transform.translation.xy(),
+            TILE_SIZE.x * 0.6,
+            SELECTION_COLOR,
+        );
+    }
+    if let Some(start) = selection_drag.start
+        && let Some(cursor_pos) = cursor_world_pos(&windows, &cameras)
+    {
+        let rect = Rect::from_corners(start, cursor_pos);
+        gizmos.rect_2d(rect.center(), rect.size(), SELECTION_COLOR);
+    }
+}
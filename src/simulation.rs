@@ -0,0 +1,96 @@
+use crate::{
+    UPS_TARGET,
+    build::BuildPlugin,
+    items::{Inventory, ItemsPlugin},
+    logistics::{LogisticsChest, LogisticsPlugin},
+    map::{MapPlugin, WorldSeed},
+    pathfinding::{PathfindingPlugin, PathfindingSettings},
+    selection::SelectionPlugin,
+    units::{
+        Unit, UnitsPlugin,
+        jobs::{JobBoard, JobsPlugin},
+        states::Available,
+        tasks::TasksPlugin,
+    },
+};
+use bevy::{
+    app::{PluginGroupBuilder, PluginsState},
+    input::InputPlugin,
+    prelude::*,
+    render::texture::ImagePlugin,
+    time::TimeUpdateStrategy,
+    transform::TransformPlugin,
+};
+
+/// everything needed to run the game logic ; nothing here needs a window or a GPU
+pub struct SimulationPlugins;
+
+impl PluginGroup for SimulationPlugins {
+    fn build(self) -> PluginGroupBuilder {
+        PluginGroupBuilder::start::<Self>()
+            .add(ItemsPlugin)
+            .add(UnitsPlugin)
+            .add(MapPlugin)
+            .add(PathfindingPlugin)
+            .add(TasksPlugin)
+            .add(JobsPlugin)
+            .add(LogisticsPlugin)
+            .add(BuildPlugin)
+            .add(SelectionPlugin)
+    }
+}
+
+/// app without window nor renderer ; every `app.update()` after the first one runs exactly one FixedUpdate tick and the runs are reproducible
+pub fn headless_app(world_seed: WorldSeed) -> App {
+    let timestep = Time::<Fixed>::from_hz(UPS_TARGET).timestep();
+
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        // sprites and chunks still load their textures, so the assets need to exist
+        .add_plugins((AssetPlugin::default(), ImagePlugin::default()))
+        // the debug systems use `input_just_pressed`
+        .add_plugins(InputPlugin)
+        // the planner reads the GlobalTransform of the chests
+        .add_plugins(TransformPlugin)
+        .add_plugins(SimulationPlugins)
+        .insert_resource(world_seed)
+        .insert_resource(Time::<Fixed>::from_hz(UPS_TARGET))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(timestep))
+        // the paths arrive at the next tick whatever the speed of the threads
+        .insert_resource(PathfindingSettings {
+            wait_for_results: true,
+            ..default()
+        });
+    app
+}
+
+/// runs `ticks` FixedUpdate ticks ; the Startup systems are run first if the app never ran
+pub fn run_ticks(app: &mut App, ticks: u32) {
+    if app.plugins_state() != PluginsState::Cleaned {
+        app.finish();
+        app.cleanup();
+        // the first update only runs Startup because the time doesn't advance yet
+        app.update();
+    }
+    for _ in 0..ticks {
+        app.update();
+    }
+}
+
+/// prints the state of the logistics network at the end of a headless run
+pub fn display_headless_summary(world: &mut World) {
+    let mut chest_query = world.query::<(&GlobalTransform, &Inventory, &LogisticsChest)>();
+    for (global_transform, inventory, logistics_chest) in chest_query.iter(world) {
+        println!(
+            "Chest at {:?} | requests: {:?} | {:?}",
+            global_transform.translation().xy(),
+            logistics_chest.requests,
+            inventory.stackable_items
+        );
+    }
+    let mut unit_query = world.query_filtered::<Option<&Available>, With<Unit>>();
+    let units = unit_query.iter(world).count();
+    let available_units = unit_query.iter(world).flatten().count();
+    println!("Units: {} | available: {}", units, available_units);
+    println!("Jobs waiting: {}", world.resource::<JobBoard>().jobs.len());
+}
@@ -0,0 +1,322 @@
+use crate::map::{WorldSeed, splitmix64};
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+// noise scales in tiles ; bigger is smoother
+const ELEVATION_SCALE: f32 = 64.0;
+const MOISTURE_SCALE: f32 = 96.0;
+const ORE_SCALE: f32 = 6.0;
+const OCTAVES: u32 = 4;
+
+const WATER_LEVEL: f32 = -0.3;
+const SAND_LEVEL: f32 = -0.24;
+const STONE_LEVEL: f32 = 0.22;
+const WALL_LEVEL: f32 = 0.3;
+const DESERT_MOISTURE: f32 = -0.3;
+const MUD_MOISTURE: f32 = 0.4;
+const ORE_MIN_ELEVATION: f32 = 0.12;
+const ORE_THRESHOLD: f32 = 0.35;
+const ORE_DENSITY: f64 = 0.6; // inside an ore cluster
+// elevation is flattened to grass around (0, 0) so the spawn area is always walkable
+const SPAWN_AREA_RADIUS: f32 = 24.0;
+
+// salts so each noise layer is independent
+const ELEVATION_SALT: u64 = 1;
+const MOISTURE_SALT: u64 = 2;
+const ORE_SALT: u64 = 3;
+
+/// ground of a tile ; the order matches the textures of the chunk tilemaps
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TileKind {
+    Grass,
+    Stone,
+    Water,
+    Sand,
+    Mud,
+    /// laid by the player, never generated
+    Road,
+}
+
+impl TileKind {
+    pub const ALL: [TileKind; 6] = [
+        TileKind::Grass,
+        TileKind::Stone,
+        TileKind::Water,
+        TileKind::Sand,
+        TileKind::Mud,
+        TileKind::Road,
+    ];
+
+    /// lowest movement_cost, keeps the heuristic of the A* under the real cost
+    pub const MIN_MOVEMENT_COST: f32 = 0.5;
+
+    /// multiplies the time to walk on the tile and its cost for the pathfinding ; None if units can't walk on it
+    pub fn movement_cost(&self) -> Option<f32> {
+        match self {
+            TileKind::Grass | TileKind::Stone => Some(1.0),
+            TileKind::Sand => Some(1.5),
+            TileKind::Mud => Some(2.5),
+            TileKind::Road => Some(Self::MIN_MOVEMENT_COST),
+            TileKind::Water => None,
+        }
+    }
+
+    pub fn texture_index(&self) -> u32 {
+        *self as u32
+    }
+
+    pub fn texture_path(&self) -> &'static str {
+        match self {
+            TileKind::Grass => "tiles/grass.png",
+            TileKind::Stone => "tiles/stone.png",
+            TileKind::Water => "tiles/water.png",
+            TileKind::Sand => "tiles/sand.png",
+            TileKind::Mud => "tiles/mud.png",
+            TileKind::Road => "tiles/road.png",
+        }
+    }
+}
+
+/// structures generated with the terrain
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TerrainFeature {
+    Wall,
+    RockOre,
+}
+
+/// per-tile terrain queries ; only depends on the WorldSeed so it can be asked anywhere, even for chunks not spawned yet
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainGenerator {
+    seed: u64,
+}
+
+impl TerrainGenerator {
+    pub fn new(world_seed: &WorldSeed) -> Self {
+        Self { seed: world_seed.0 }
+    }
+
+    /// in [-1, 1] ; low is sea, high is mountains
+    pub fn elevation(&self, rounded_tile_pos: IVec2) -> f32 {
+        let pos = rounded_tile_pos.as_vec2();
+        let elevation = fbm(self.layer_seed(ELEVATION_SALT), pos / ELEVATION_SCALE);
+        elevation * spawn_area_factor(pos)
+    }
+
+    /// in [-1, 1] ; low is dry
+    pub fn moisture(&self, rounded_tile_pos: IVec2) -> f32 {
+        let pos = rounded_tile_pos.as_vec2();
+        fbm(self.layer_seed(MOISTURE_SALT), pos / MOISTURE_SCALE)
+    }
+
+    pub fn tile_kind(&self, rounded_tile_pos: IVec2) -> TileKind {
+        let elevation = self.elevation(rounded_tile_pos);
+        if elevation < WATER_LEVEL {
+            TileKind::Water
+        } else if elevation < SAND_LEVEL {
+            TileKind::Sand
+        } else if elevation > STONE_LEVEL {
+            TileKind::Stone
+        } else {
+            let moisture = self.moisture(rounded_tile_pos);
+            if moisture < DESERT_MOISTURE {
+                TileKind::Sand
+            } else if moisture > MUD_MOISTURE
+                && rounded_tile_pos.as_vec2().length() >= SPAWN_AREA_RADIUS
+            {
+                TileKind::Mud
+            } else {
+                TileKind::Grass
+            }
+        }
+    }
+
+    /// walls fill the mountain cores and ores are grouped in clusters on their slopes ; `rng` scatters the ores inside a cluster
+    pub fn feature(&self, rounded_tile_pos: IVec2, rng: &mut impl Rng) -> Option<TerrainFeature> {
+        let pos = rounded_tile_pos.as_vec2();
+        if pos.length() < SPAWN_AREA_RADIUS {
+            return None;
+        }
+
+        let elevation = self.elevation(rounded_tile_pos);
+        if elevation > WALL_LEVEL {
+            return Some(TerrainFeature::Wall);
+        }
+        if elevation > ORE_MIN_ELEVATION
+            && fbm(self.layer_seed(ORE_SALT), pos / ORE_SCALE) > ORE_THRESHOLD
+            && rng.random_bool(ORE_DENSITY)
+        {
+            return Some(TerrainFeature::RockOre);
+        }
+        None
+    }
+
+    fn layer_seed(&self, salt: u64) -> u64 {
+        splitmix64(self.seed ^ splitmix64(salt))
+    }
+}
+
+impl WorldSeed {
+    pub fn terrain(&self) -> TerrainGenerator {
+        TerrainGenerator::new(self)
+    }
+}
+
+// 0 at the origin, 1 outside of the spawn area
+fn spawn_area_factor(pos: Vec2) -> f32 {
+    let t = (pos.length() / SPAWN_AREA_RADIUS).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// ========= noise =========
+
+// random value in [-1, 1] at a lattice point
+fn lattice_value(seed: u64, x: i32, y: i32) -> f32 {
+    let packed_pos = ((x as u32 as u64) << 32) | y as u32 as u64;
+    let hash = splitmix64(seed ^ splitmix64(packed_pos));
+    (hash >> 40) as f32 / (1u64 << 24) as f32 * 2.0 - 1.0
+}
+
+// smooth value noise in [-1, 1]
+fn value_noise(seed: u64, pos: Vec2) -> f32 {
+    let cell = pos.floor();
+    let fraction = pos - cell;
+    let smooth = fraction * fraction * (Vec2::splat(3.0) - 2.0 * fraction);
+    let (x, y) = (cell.x as i32, cell.y as i32);
+
+    let bottom = lattice_value(seed, x, y).lerp(lattice_value(seed, x + 1, y), smooth.x);
+    let top = lattice_value(seed, x, y + 1).lerp(lattice_value(seed, x + 1, y + 1), smooth.x);
+    bottom.lerp(top, smooth.y)
+}
+
+// fractal brownian motion: sum of OCTAVES noise layers, each one twice as detailed and half as strong
+fn fbm(seed: u64, pos: Vec2) -> f32 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    let mut total_amplitude = 0.0;
+    for octave in 0..OCTAVES {
+        sum += amplitude * value_noise(seed.wrapping_add(octave as u64), pos * frequency);
+        total_amplitude += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum / total_amplitude
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::CHUNK_SIZE;
+    use rand::{RngCore, SeedableRng, rngs::StdRng};
+    use std::collections::HashMap;
+
+    const SEED: u64 = 42;
+
+    // draws 0 every time, so every tile inside an ore cluster gets an ore
+    struct ZeroRng;
+
+    impl RngCore for ZeroRng {
+        fn next_u32(&mut self) -> u32 {
+            0
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            0
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            dest.fill(0);
+        }
+    }
+
+    fn terrain(seed: u64) -> TerrainGenerator {
+        WorldSeed(seed).terrain()
+    }
+
+    fn region() -> impl Iterator<Item = IVec2> {
+        (-128..128).flat_map(|x| (-128..128).map(move |y| IVec2::new(x, y)))
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_terrain() {
+        let (first, second) = (terrain(SEED), terrain(SEED));
+        let (mut first_rng, mut second_rng) = (StdRng::seed_from_u64(0), StdRng::seed_from_u64(0));
+        for pos in region() {
+            assert_eq!(first.elevation(pos), second.elevation(pos));
+            assert_eq!(first.moisture(pos), second.moisture(pos));
+            assert_eq!(first.tile_kind(pos), second.tile_kind(pos));
+            assert_eq!(
+                first.feature(pos, &mut first_rng),
+                second.feature(pos, &mut second_rng)
+            );
+        }
+
+        let other = terrain(SEED + 1);
+        assert!(region().any(|pos| first.tile_kind(pos) != other.tile_kind(pos)));
+    }
+
+    #[test]
+    fn elevation_has_no_seam_at_chunk_borders() {
+        let terrain = terrain(SEED);
+        let chunk_size = CHUNK_SIZE.x as i32;
+        let (mut border_step, mut inner_step) = (0.0f32, 0.0f32);
+        for pos in region() {
+            for next in [pos + IVec2::X, pos + IVec2::Y] {
+                let step = (terrain.elevation(next) - terrain.elevation(pos)).abs();
+                let crosses_border = (pos.x.div_euclid(chunk_size), pos.y.div_euclid(chunk_size))
+                    != (next.x.div_euclid(chunk_size), next.y.div_euclid(chunk_size));
+                if crosses_border {
+                    border_step = border_step.max(step);
+                } else {
+                    inner_step = inner_step.max(step);
+                }
+            }
+        }
+        assert!(
+            border_step < 0.05,
+            "step of {border_step} at a chunk border"
+        );
+        assert!(border_step <= inner_step);
+    }
+
+    #[test]
+    fn tile_kinds_at_fixed_coordinates() {
+        let terrain = terrain(SEED);
+        assert_eq!(terrain.tile_kind(IVec2::new(0, 0)), TileKind::Grass);
+        assert_eq!(terrain.tile_kind(IVec2::new(80, 0)), TileKind::Water);
+        assert_eq!(terrain.tile_kind(IVec2::new(-128, -38)), TileKind::Sand);
+        assert_eq!(terrain.tile_kind(IVec2::new(-124, 77)), TileKind::Mud);
+        assert_eq!(terrain.tile_kind(IVec2::new(24, 0)), TileKind::Stone);
+
+        let mut counts = HashMap::new();
+        for pos in region() {
+            *counts.entry(terrain.tile_kind(pos)).or_insert(0) += 1;
+        }
+        assert!(!counts.contains_key(&TileKind::Road));
+        let grass = counts[&TileKind::Grass];
+        assert!(counts.values().all(|&count| count <= grass));
+    }
+
+    #[test]
+    fn features_at_fixed_coordinates() {
+        let terrain = terrain(SEED);
+        assert_eq!(
+            terrain.feature(IVec2::new(24, 0), &mut ZeroRng),
+            Some(TerrainFeature::Wall)
+        );
+        assert_eq!(
+            terrain.feature(IVec2::new(30, 0), &mut ZeroRng),
+            Some(TerrainFeature::RockOre)
+        );
+        assert_eq!(
+            terrain.feature(IVec2::new(0, 24), &mut ZeroRng),
+            Some(TerrainFeature::RockOre)
+        );
+        assert_eq!(terrain.feature(IVec2::new(80, 0), &mut ZeroRng), None);
+
+        // the spawn area is kept free whatever the rng draws
+        let mut spawn_area = region().filter(|pos| pos.as_vec2().length() < SPAWN_AREA_RADIUS);
+        assert!(spawn_area.all(|pos| terrain.feature(pos, &mut ZeroRng).is_none()));
+    }
+}
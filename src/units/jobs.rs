@@ -0,0 +1,228 @@
+use crate::{
+    map::world_pos_to_rounded_tile,
+    units::{
+        Unit,
+        states::{Available, Unavailable},
+        tasks::{
+            Action, CurrentTask, FailureReason, MAX_TASKS_RETRIES, Task, TaskFailed, TaskKind,
+            TaskQueue, start_next_task_system, update_task_completion_system,
+        },
+    },
+};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+pub struct JobsPlugin;
+
+impl Plugin for JobsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<JobBoard>().add_systems(
+            FixedUpdate,
+            (
+                release_finished_jobs_system.after(update_task_completion_system),
+                assign_jobs_system
+                    .after(start_next_task_system)
+                    .after(release_finished_jobs_system),
+            ),
+        );
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Capability {
+    Hauling,  // moves items between chests
+    Crafting, // uses crafting machines
+    Building, // builds the blueprints
+}
+
+/// jobs a unit can do ; units without this component can do every job
+#[derive(Component, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Capabilities(pub HashSet<Capability>);
+
+/// task posted on the JobBoard
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    pub task: Task,
+    pub priority: i32,           // bigger first
+    pub location: Option<IVec2>, // units closer to it are preferred
+    pub retries: u32,            // times it was posted again after failing
+}
+
+impl Job {
+    pub fn required_capabilities(&self) -> HashSet<Capability> {
+        let mut capabilities = HashSet::new();
+        for task in self.task.iter() {
+            match task.kind {
+                TaskKind::GetItems { .. }
+                | TaskKind::DeliverItems { .. }
+                | TaskKind::Action(Action::Take { .. })
+                | TaskKind::Action(Action::Drop { .. })
+                | TaskKind::Action(Action::TakeUnique { .. })
+                | TaskKind::Action(Action::DropUnique { .. }) => {
+                    capabilities.insert(Capability::Hauling);
+                }
+                TaskKind::Action(Action::Craft { .. }) => {
+                    capabilities.insert(Capability::Crafting);
+                }
+                TaskKind::Action(Action::Build { .. }) => {
+                    capabilities.insert(Capability::Building);
+                }
+                TaskKind::Action(Action::MoveTo(_))
+                | TaskKind::Sequence
+                | TaskKind::Parallel
+                | TaskKind::AnyOf => {}
+            }
+        }
+        capabilities
+    }
+}
+
+/// jobs waiting for a unit
+#[derive(Resource, Default, Clone, Serialize, Deserialize)]
+pub struct JobBoard {
+    next_id: u64,
+    pub jobs: Vec<Job>,
+}
+
+impl JobBoard {
+    /// returns the id of the job
+    pub fn post(&mut self, task: Task, priority: i32, location: Option<IVec2>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.jobs.push(Job {
+            id,
+            task,
+            priority,
+            location,
+            retries: 0,
+        });
+        id
+    }
+
+    /// posts again a failed job with its id, up to MAX_TASKS_RETRIES times ; false if it can't be retried anymore
+    /// each posting still gets the retries of its tasks, see Task::fail
+    pub fn repost(&mut self, mut job: Job, reason: FailureReason) -> bool {
+        if !reason.can_be_retried() || job.retries >= MAX_TASKS_RETRIES {
+            return false;
+        }
+        job.retries += 1;
+        self.jobs.push(job);
+        true
+    }
+
+    /// every task waiting on the board, sub_tasks included
+    pub fn tasks(&self) -> impl Iterator<Item = &Task> {
+        self.jobs.iter().flat_map(|job| job.task.iter())
+    }
+}
+
+/// job done by the unit ; kept untouched to post it again if it fails
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct AssignedJob(pub Job);
+
+/// gives the jobs to the closest idle unit able to do them, highest priority first
+#[allow(clippy::type_complexity)]
+pub fn assign_jobs_system(
+    mut commands: Commands,
+    mut job_board: ResMut<JobBoard>,
+    mut unit_query: Query<
+        (
+            Entity,
+            &Transform,
+            &mut CurrentTask,
+            &TaskQueue,
+            Option<&Capabilities>,
+        ),
+        (
+            With<Unit>,
+            With<Available>,
+            Without<AssignedJob>,
+            Without<Unavailable>,
+        ),
+    >,
+) {
+    if job_board.jobs.is_empty() {
+        return;
+    }
+
+    let mut idle_units: Vec<(Entity, IVec2, Option<HashSet<Capability>>)> = unit_query
+        .iter()
+        .filter(|(_, _, current_task, task_queue, _)| {
+            current_task.task.is_none() && task_queue.0.is_empty()
+        })
+        .map(|(unit_ent, transform, _, _, capabilities)| {
+            (
+                unit_ent,
+                world_pos_to_rounded_tile(transform.translation.xy()),
+                capabilities.map(|capabilities| capabilities.0.clone()),
+            )
+        })
+        .collect();
+    if idle_units.is_empty() {
+        return;
+    }
+
+    // stable sort so jobs with the same priority keep their posting order
+    let mut jobs = std::mem::take(&mut job_board.jobs);
+    jobs.sort_by_key(|job| -job.priority);
+
+    for job in jobs {
+        let required_capabilities = job.required_capabilities();
+        let best_unit = idle_units
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, _, capabilities))| {
+                capabilities
+                    .as_ref()
+                    .is_none_or(|capabilities| required_capabilities.is_subset(capabilities))
+            })
+            .min_by_key(|(_, (_, unit_tile_pos, _))| {
+                job.location
+                    .map_or(0, |location| (*unit_tile_pos - location).length_squared())
+            })
+            .map(|(index, _)| index);
+
+        let Some(index) = best_unit else {
+            job_board.jobs.push(job);
+            continue;
+        };
+        let (unit_ent, _, _) = idle_units.swap_remove(index);
+        let Ok((_, _, mut current_task, _, _)) = unit_query.get_mut(unit_ent) else {
+            job_board.jobs.push(job);
+            continue;
+        };
+
+        current_task.task = Some(job.task.clone());
+        current_task.initialized = false;
+        // the unit may be despawned by a command of this frame
+        commands.entity(unit_ent).try_insert(AssignedJob(job));
+    }
+}
+
+/// forgets the jobs whose task is finished ; the failed ones go back on the board once the unit used its per-task retries (see `Task::fail`)
+pub fn release_finished_jobs_system(
+    mut commands: Commands,
+    mut job_board: ResMut<JobBoard>,
+    mut task_failed_events: EventReader<TaskFailed>,
+    unit_query: Query<(Entity, &CurrentTask, &AssignedJob), With<Unit>>,
+) {
+    let failures: HashMap<Entity, FailureReason> = task_failed_events
+        .read()
+        .map(|event| (event.unit, event.reason))
+        .collect();
+    for (unit_ent, current_task, assigned_job) in unit_query.iter() {
+        if current_task.task.is_some() {
+            continue;
+        }
+        if let Some(&reason) = failures.get(&unit_ent) {
+            let job = assigned_job.0.clone();
+            let (id, retries) = (job.id, job.retries);
+            if !job_board.repost(job, reason) {
+                warn!("job {} dropped after {} retries: {:?}", id, retries, reason);
+            }
+        }
+        commands.entity(unit_ent).try_remove::<AssignedJob>();
+    }
+}
@@ -1,3 +1,4 @@
+pub mod jobs;
 pub mod states;
 pub mod tasks;
 mod units;
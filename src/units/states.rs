@@ -4,3 +4,7 @@ use bevy::prelude::*;
 #[derive(Component)]
 #[component(storage = "SparseSet")]
 pub struct Available;
+
+/// the unit never becomes Available when it runs out of actions and never gets a job from the JobBoard
+#[derive(Component)]
+pub struct Unavailable;
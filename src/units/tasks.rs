@@ -1,14 +1,27 @@
 use crate::{
-    items::{CraftRecipeId, Inventory, ItemKind},
-    map::{Chest, Provider, Requester, world_pos_to_rounded_tile},
+    UPS_TARGET,
+    build::Blueprint,
+    items::{
+        CraftRecipeId, CraftRecipes, Durability, Inventory, Item, ItemId, ItemRegistry,
+        ItemsTransferred, TransferError, TransferItem, TransferOutcome, UniqueItemKind,
+        transfer_items, wear_unique_item,
+    },
+    logistics::LogisticsChest,
+    map::{Chest, Crafter, CraftingProgress, world_pos_to_rounded_tile},
     pathfinding::PathfindingAgent,
-    units::{UNIT_REACH, Unit, move_and_collide_units_system, states::Available},
+    units::{
+        UNIT_REACH, Unit,
+        jobs::JobBoard,
+        move_and_collide_units_system,
+        states::{Available, Unavailable},
+    },
 };
 use bevy::{
     ecs::{entity, system::entity_command},
-    input::common_conditions::input_pressed,
+    input::common_conditions::input_just_pressed,
     prelude::*,
 };
+use serde::{Deserialize, Serialize};
 use std::{
     cmp::min,
     collections::{HashMap, VecDeque},
@@ -16,28 +29,36 @@ use std::{
 
 // TODO: see how to remove that
 // const BONUS_RANGE: f32 = 0.8;
-const MAX_TASKS_RETRIES: u32 = 3;
+pub const MAX_TASKS_RETRIES: u32 = 3;
+pub const RETRY_BACKOFF_TICKS: u32 = UPS_TARGET as u32; // before the first retry
 
 pub struct TasksPlugin;
 
 impl Plugin for TasksPlugin {
     fn build(&self, app: &mut bevy::app::App) {
-        app.insert_resource(Reservations::default()).add_systems(
-            FixedUpdate,
-            (
-                actions_decompose_planner_system.before(process_current_action_system),
-                process_current_action_system.before(move_and_collide_units_system),
-                update_task_completion_system.after(process_current_action_system),
-                assign_next_action_or_set_available_system,
-                // tests:
-                test_find_2_rocks_system.run_if(input_pressed(KeyCode::KeyE)),
-                test_deliver_2_rocks_system.run_if(input_pressed(KeyCode::KeyR)),
-            ),
-        );
+        app.insert_resource(Reservations::default())
+            .insert_resource(CraftRecipes::default())
+            .add_event::<TaskFailed>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    actions_decompose_planner_system.before(process_current_action_system),
+                    process_current_action_system.before(move_and_collide_units_system),
+                    update_task_completion_system.after(process_current_action_system),
+                    start_next_task_system.after(update_task_completion_system),
+                    assign_next_action_or_set_available_system,
+                    release_abandoned_crafters_system.after(process_current_action_system),
+                    log_task_failures_system.after(update_task_completion_system),
+                    // tests:
+                    test_find_2_rocks_system.run_if(input_just_pressed(KeyCode::KeyE)),
+                    test_deliver_2_rocks_system.run_if(input_just_pressed(KeyCode::KeyR)),
+                    test_craft_chest_system.run_if(input_just_pressed(KeyCode::KeyC)),
+                ),
+            );
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum Action {
     MoveTo(IVec2),
     Craft {
@@ -46,18 +67,29 @@ pub enum Action {
         with: Entity, // crafting machine
     },
     Take {
-        kind: ItemKind,
+        kind: ItemId,
         quantity: u32,
         from: Entity, // chest
     },
     Drop {
-        kind: ItemKind,
+        kind: ItemId,
         quantity: u32,
         to: Entity, // chest
     },
+    TakeUnique {
+        item: Entity,
+        from: Entity, // chest
+    },
+    DropUnique {
+        item: Entity,
+        to: Entity, // chest
+    },
+    Build {
+        blueprint: Entity,
+    },
 }
 
-#[derive(Component, Default, Debug)]
+#[derive(Component, Default, Debug, Clone, Serialize, Deserialize)]
 pub struct ActionQueue(pub VecDeque<Action>);
 
 impl From<Vec<Action>> for ActionQueue {
@@ -66,33 +98,106 @@ impl From<Vec<Action>> for ActionQueue {
     }
 }
 
-#[derive(Component, Default)]
+#[derive(Component, Default, Clone, Serialize, Deserialize)]
 pub struct CurrentAction {
     pub action: Option<Action>,
     pub initialized: bool,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum TaskKind {
     Action(Action),
-    GetItems { kind: ItemKind, quantity: u32 }, // take from provider chest (uses reservations)
-    DeliverItems { kind: ItemKind, quantity: u32 }, // go to requester chest and drop items
+    // take from provider chest (uses reservations)
+    GetItems {
+        kind: ItemId,
+        quantity: u32,
+    },
+    // go to the chest and drop items
+    DeliverItems {
+        kind: ItemId,
+        quantity: u32,
+        to: Entity,
+    },
+    // composite tasks, only made of their sub_tasks ; a unit does one sub_task at a time, in the order of the Vec
+    Sequence, // stops at the first required sub_task failing
+    Parallel, // sub_tasks don't depend on each other: all of them are done, it fails at the end if a required one failed
+    AnyOf,    // stops at the first sub_task completed ; fails if all of them fail
 }
 
-#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+impl TaskKind {
+    pub fn is_composite(&self) -> bool {
+        matches!(
+            self,
+            TaskKind::Sequence | TaskKind::Parallel | TaskKind::AnyOf
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TaskStatus {
     Pending,
     Planned, // already decomposed (actions queued)
     InProgress,
     Completed,
-    Failed,
+    Failed(FailureReason),
 }
 
-#[derive(Debug)]
+impl TaskStatus {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, TaskStatus::Completed | TaskStatus::Failed(_))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FailureReason {
+    NoProvider,        // no chest has the items
+    ReservationDenied, // the items were reserved by another unit
+    PathUnreachable,   // the unit gave up moving before reaching the target
+    TargetOutOfReach,  // the unit isn't next to the chest or crafter
+    TargetMissing,     // the chest or crafter doesn't exist anymore
+    NotEnoughItems,    // the chest or the unit doesn't have the items anymore
+    InventoryFull,     // the items don't fit in the inventory
+    CrafterBusy,       // another unit uses the crafter
+    ToolBroken,        // the tool broke before the last craft
+    UnknownRecipe,
+}
+
+impl From<TransferError> for FailureReason {
+    fn from(error: TransferError) -> Self {
+        match error {
+            TransferError::NotEnoughItems => FailureReason::NotEnoughItems,
+            TransferError::NoSpace => FailureReason::InventoryFull,
+        }
+    }
+}
+
+impl FailureReason {
+    /// false when retrying can't change anything
+    pub fn can_be_retried(&self) -> bool {
+        !matches!(
+            self,
+            FailureReason::TargetMissing | FailureReason::ToolBroken | FailureReason::UnknownRecipe
+        )
+    }
+}
+
+/// sent when a root task failed and won't be retried anymore
+#[derive(Event, Debug, Clone)]
+pub struct TaskFailed {
+    pub unit: Entity,
+    pub task: Task,
+    pub reason: FailureReason,
+}
+
+/// tree of tasks ; the sub_tasks of a non composite task are done first, as a Sequence
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Task {
     pub kind: TaskKind,
     pub sub_tasks: Vec<Task>,
     pub status: TaskStatus,
+    pub optional: bool, // its failure doesn't fail the parent
+    pub retries: u32,
+    pub backoff_ticks: u32, // ticks to wait before planning it again after a failure
 }
 
 impl Task {
@@ -101,11 +206,208 @@ impl Task {
             kind,
             sub_tasks,
             status: TaskStatus::Pending,
+            optional: false,
+            retries: 0,
+            backoff_ticks: 0,
+        }
+    }
+
+    pub fn sequence(sub_tasks: Vec<Task>) -> Self {
+        Self::new(TaskKind::Sequence, sub_tasks)
+    }
+
+    pub fn parallel(sub_tasks: Vec<Task>) -> Self {
+        Self::new(TaskKind::Parallel, sub_tasks)
+    }
+
+    pub fn any_of(sub_tasks: Vec<Task>) -> Self {
+        Self::new(TaskKind::AnyOf, sub_tasks)
+    }
+
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+
+    /// Failed, or back to Pending after a backoff (doubled each retry) if it can be retried
+    pub fn fail(&mut self, reason: FailureReason) {
+        if reason.can_be_retried() && self.retries < MAX_TASKS_RETRIES {
+            self.retries += 1;
+            self.backoff_ticks = RETRY_BACKOFF_TICKS << (self.retries - 1);
+            self.status = TaskStatus::Pending;
+        } else {
+            self.status = TaskStatus::Failed(reason);
+        }
+    }
+
+    /// the task and all its sub_tasks, depth first
+    pub fn iter(&self) -> Box<dyn Iterator<Item = &Task> + '_> {
+        Box::new(std::iter::once(self).chain(self.sub_tasks.iter().flat_map(|task| task.iter())))
+    }
+
+    /// the non composite task to plan or execute now ; None if the tree is finished
+    /// the statuses must be up to date (see `propagate_status`)
+    pub fn active_leaf_mut(&mut self) -> Option<&mut Task> {
+        if self.status.is_finished() {
+            return None;
+        }
+        if let Some(index) = self
+            .sub_tasks
+            .iter()
+            .position(|sub_task| !sub_task.status.is_finished())
+        {
+            return self.sub_tasks[index].active_leaf_mut();
+        }
+        if self.kind.is_composite() {
+            return None;
+        }
+        Some(self)
+    }
+
+    /// splits the active GetItems and the DeliverItems right after it in two trips, the first one carrying `carry` items
+    /// false if the active task isn't a GetItems followed by a DeliverItems of the same items
+    pub fn split_trip(&mut self, carry: u32) -> bool {
+        let Some(index) = self
+            .sub_tasks
+            .iter()
+            .position(|sub_task| !sub_task.status.is_finished())
+        else {
+            return false;
+        };
+        if self.sub_tasks[index]
+            .sub_tasks
+            .iter()
+            .any(|sub_task| !sub_task.status.is_finished())
+        {
+            return self.sub_tasks[index].split_trip(carry);
+        }
+        // the sub_tasks of a Parallel or an AnyOf aren't done one after the other
+        if matches!(self.kind, TaskKind::Parallel | TaskKind::AnyOf) {
+            return false;
+        }
+        let (
+            Some(TaskKind::GetItems { kind, quantity }),
+            Some(TaskKind::DeliverItems {
+                kind: deliver_kind,
+                quantity: deliver_quantity,
+                to,
+            }),
+        ) = (
+            self.sub_tasks.get(index).map(|sub_task| sub_task.kind),
+            self.sub_tasks.get(index + 1).map(|sub_task| sub_task.kind),
+        )
+        else {
+            return false;
+        };
+        if kind != deliver_kind || quantity != deliver_quantity || carry == 0 || carry >= quantity {
+            return false;
+        }
+
+        self.sub_tasks[index].kind = TaskKind::GetItems {
+            kind,
+            quantity: carry,
+        };
+        self.sub_tasks[index + 1].kind = TaskKind::DeliverItems {
+            kind,
+            quantity: carry,
+            to,
+        };
+        let mut next_get_items = Task::new(
+            TaskKind::GetItems {
+                kind,
+                quantity: quantity - carry,
+            },
+            Vec::new(),
+        );
+        next_get_items.optional = self.sub_tasks[index].optional;
+        let mut next_deliver_items = Task::new(
+            TaskKind::DeliverItems {
+                kind,
+                quantity: quantity - carry,
+                to,
+            },
+            Vec::new(),
+        );
+        next_deliver_items.optional = self.sub_tasks[index + 1].optional;
+        self.sub_tasks
+            .splice(index + 2..index + 2, [next_get_items, next_deliver_items]);
+        true
+    }
+
+    /// updates the statuses of the composite tasks, and of the tasks whose sub_tasks failed, from the leaves to the root
+    pub fn propagate_status(&mut self) {
+        if self.status.is_finished() {
+            return;
+        }
+        for sub_task in &mut self.sub_tasks {
+            sub_task.propagate_status();
+        }
+
+        let all_finished = self
+            .sub_tasks
+            .iter()
+            .all(|sub_task| sub_task.status.is_finished());
+        let any_started = self
+            .sub_tasks
+            .iter()
+            .any(|sub_task| sub_task.status != TaskStatus::Pending);
+        let any_completed = self
+            .sub_tasks
+            .iter()
+            .any(|sub_task| sub_task.status == TaskStatus::Completed);
+        // the parent fails with the reason of its sub_task
+        let required_failure = self
+            .sub_tasks
+            .iter()
+            .filter(|sub_task| !sub_task.optional)
+            .find_map(|sub_task| match sub_task.status {
+                TaskStatus::Failed(reason) => Some(reason),
+                _ => None,
+            });
+        let last_failure = self
+            .sub_tasks
+            .iter()
+            .rev()
+            .find_map(|sub_task| match sub_task.status {
+                TaskStatus::Failed(reason) => Some(reason),
+                _ => None,
+            });
+
+        let sub_tasks_status = match (self.kind, last_failure, required_failure) {
+            (TaskKind::AnyOf, _, _) if any_completed => TaskStatus::Completed,
+            (TaskKind::AnyOf, Some(reason), _) if all_finished => TaskStatus::Failed(reason),
+            (TaskKind::Parallel, _, Some(reason)) if all_finished => TaskStatus::Failed(reason),
+            (TaskKind::AnyOf | TaskKind::Parallel, _, _) => {
+                if all_finished {
+                    TaskStatus::Completed
+                } else if any_started {
+                    TaskStatus::InProgress
+                } else {
+                    TaskStatus::Pending
+                }
+            }
+            // Sequence, and the sub_tasks of a non composite task
+            (_, _, Some(reason)) => TaskStatus::Failed(reason),
+            _ => {
+                if all_finished {
+                    TaskStatus::Completed
+                } else if any_started {
+                    TaskStatus::InProgress
+                } else {
+                    TaskStatus::Pending
+                }
+            }
+        };
+
+        if self.kind.is_composite() {
+            self.status = sub_tasks_status;
+        } else if let TaskStatus::Failed(reason) = sub_tasks_status {
+            self.status = TaskStatus::Failed(reason);
         }
     }
 }
 
-#[derive(Component, Default)]
+#[derive(Component, Default, Clone, Serialize, Deserialize)]
 pub struct CurrentTask {
     pub task: Option<Task>,
     pub initialized: bool,
@@ -118,10 +420,14 @@ impl CurrentTask {
     }
 }
 
-#[derive(Resource, Default)]
+/// tasks to start after the CurrentTask ; cleared when a task fails since the next ones usually need it
+#[derive(Component, Default, Debug, Clone, Serialize, Deserialize)]
+pub struct TaskQueue(pub VecDeque<Task>);
+
+#[derive(Resource, Default, Clone, Serialize, Deserialize)]
 pub struct Reservations {
     // chest -> owner -> kind -> qty
-    pub reserved: HashMap<Entity, HashMap<Entity, HashMap<ItemKind, u32>>>,
+    pub reserved: HashMap<Entity, HashMap<Entity, HashMap<ItemId, u32>>>,
 }
 
 impl Reservations {
@@ -132,7 +438,7 @@ impl Reservations {
         &mut self,
         owner: Entity,
         chest: Entity,
-        kind: ItemKind,
+        kind: ItemId,
         qty: u32,
         chest_inv: &Inventory,
     ) -> bool {
@@ -155,7 +461,7 @@ impl Reservations {
 
     /// Release `qty` reserved by `owner` on `chest` for `kind`.
     /// If owner had less reserved than qty, it saturates to zero.
-    pub fn release(&mut self, owner: Entity, chest: Entity, kind: ItemKind, qty: u32) {
+    pub fn release(&mut self, owner: Entity, chest: Entity, kind: ItemId, qty: u32) {
         if let Some(owner_map) = self.reserved.get_mut(&chest) {
             if let Some(kind_map) = owner_map.get_mut(&owner) {
                 if let Some(v) = kind_map.get_mut(&kind) {
@@ -191,8 +497,13 @@ impl Reservations {
         }
     }
 
+    /// Release every reservation on `chest` (it was destroyed).
+    pub fn release_all_for_chest(&mut self, chest: Entity) {
+        self.reserved.remove(&chest);
+    }
+
     /// Total reserved for a given chest and item kind (sum over all owners)
-    pub fn total_reserved(&self, chest: Entity, kind: ItemKind) -> u32 {
+    pub fn total_reserved(&self, chest: Entity, kind: ItemId) -> u32 {
         if let Some(owner_map) = self.reserved.get(&chest) {
             owner_map
                 .values()
@@ -204,7 +515,7 @@ impl Reservations {
     }
 
     /// How much this owner specifically reserved on chest for kind
-    pub fn owner_reserved(&self, owner: Entity, chest: Entity, kind: ItemKind) -> u32 {
+    pub fn owner_reserved(&self, owner: Entity, chest: Entity, kind: ItemId) -> u32 {
         self.reserved
             .get(&chest)
             .and_then(|owners| owners.get(&owner))
@@ -225,9 +536,10 @@ fn tile_distance(a: IVec2, b: IVec2) -> f32 {
 
 /// Planner: decompose Task -> Actions and attempt reservations.
 /// It runs on units that have a CurrentTask (Pending) and an ActionQueue.
-fn actions_decompose_planner_system(
+pub fn actions_decompose_planner_system(
     mut commands: Commands,
     mut reservations: ResMut<Reservations>,
+    item_registry: Res<ItemRegistry>,
     mut unit_query: Query<
         (
             Entity,
@@ -238,55 +550,62 @@ fn actions_decompose_planner_system(
         ),
         (With<Unit>, With<PathfindingAgent>),
     >,
-    provider_chest_query: Query<
-        (Entity, &GlobalTransform, &Inventory),
-        (With<Chest>, With<Provider>, Without<Unit>),
-    >,
-    requester_chest_query: Query<
-        (Entity, &GlobalTransform, &Inventory),
+    chest_query: Query<
         (
-            With<Chest>,
-            With<Requester>,
-            Without<Provider>,
-            Without<Unit>,
+            Entity,
+            &GlobalTransform,
+            &Inventory,
+            Option<&LogisticsChest>,
         ),
+        (With<Chest>, Without<Unit>),
     >,
 ) {
     for (unit_ent, transform, mut unit_inv, mut action_queue, mut current_task) in
         unit_query.iter_mut()
     {
-        let Some(task) = &mut current_task.task else {
+        let current_task = &mut *current_task;
+        let Some(root_task) = &mut current_task.task else {
+            continue;
+        };
+        let Some(task) = root_task.active_leaf_mut() else {
             continue;
         };
         // Only decompose pending tasks
         if task.status != TaskStatus::Pending {
             continue;
         }
+        // waits before retrying a failed task
+        if task.backoff_ticks > 0 {
+            task.backoff_ticks -= 1;
+            continue;
+        }
 
+        // quantity the unit can carry when the GetItems doesn't fit in its inventory
+        let mut trip_to_split = None;
         match task.kind {
             TaskKind::GetItems { kind, quantity } => {
                 // checks if enough in unit's inventory
                 let have = unit_inv.count(&kind);
-                if have >= quantity {
-                    task.status = TaskStatus::Completed;
-                    continue;
-                }
-                let needed = quantity - have;
+                let needed = quantity.saturating_sub(have);
+                let space = unit_inv.space_for(&kind, &item_registry);
                 let unit_tile_pos = world_pos_to_rounded_tile(transform.translation.xy());
 
+                if needed == 0 {
+                    task.status = TaskStatus::Completed;
+                } else if have + space == 0 {
+                    task.fail(FailureReason::InventoryFull);
+                } else if needed > space {
+                    trip_to_split = Some(have + space);
+                }
                 // find best chest taking into account reservations
-                if let Some((chest_ent, chest_tile_pos, available)) = find_best_chest(
-                    unit_tile_pos,
-                    needed,
-                    kind,
-                    &provider_chest_query,
-                    &reservations,
-                ) {
+                else if let Some((chest_ent, chest_tile_pos, available)) =
+                    find_best_chest(unit_tile_pos, needed, kind, &chest_query, &reservations)
+                {
                     // calculate how much we'll request to take (cap to available)
                     let take_qty = std::cmp::min(needed, available);
 
                     // chest inventory borrow for reservation check
-                    if let Ok((_ent, _global_tf, chest_inv)) = provider_chest_query.get(chest_ent) {
+                    if let Ok((_ent, _global_tf, chest_inv, _)) = chest_query.get(chest_ent) {
                         // try to reserve
                         if reservations.try_reserve(unit_ent, chest_ent, kind, take_qty, chest_inv)
                         {
@@ -306,21 +625,19 @@ fn actions_decompose_planner_system(
                                 Err(_) => todo!(),
                             };
                         } else {
-                            task.status = TaskStatus::Failed;
+                            task.fail(FailureReason::ReservationDenied);
                             reservations.release_all_for_owner(unit_ent);
                         }
                     } else {
-                        task.status = TaskStatus::Failed;
+                        task.fail(FailureReason::TargetMissing);
                     }
                 } else {
-                    task.status = TaskStatus::Failed;
+                    task.fail(FailureReason::NoProvider);
                 }
             }
 
-            TaskKind::DeliverItems { kind, quantity } => {
-                if let Ok((requester_ent, requester_global_tf, _req_inv)) =
-                    requester_chest_query.single()
-                {
+            TaskKind::DeliverItems { kind, quantity, to } => {
+                if let Ok((requester_ent, requester_global_tf, _req_inv, _)) = chest_query.get(to) {
                     let req_pos = world_pos_to_rounded_tile(requester_global_tf.translation().xy());
                     action_queue.0.push_back(Action::MoveTo(req_pos));
                     action_queue.0.push_back(Action::Drop {
@@ -336,7 +653,7 @@ fn actions_decompose_planner_system(
                         Err(_) => todo!(),
                     };
                 } else {
-                    task.status = TaskStatus::Failed;
+                    task.fail(FailureReason::TargetMissing);
                 }
             }
 
@@ -350,7 +667,19 @@ fn actions_decompose_planner_system(
                     };
                 }
             }
+
+            // never a leaf
+            TaskKind::Sequence | TaskKind::Parallel | TaskKind::AnyOf => {}
+        }
+
+        // the new GetItems is planned on the next tick
+        if let Some(carry) = trip_to_split
+            && !root_task.split_trip(carry)
+            && let Some(task) = root_task.active_leaf_mut()
+        {
+            task.fail(FailureReason::InventoryFull);
         }
+        root_task.propagate_status();
     }
 }
 
@@ -372,12 +701,33 @@ pub fn reset_actions_system(
     current_action.initialized = false;
 }
 
+/// fails the task being done (it may be retried) and drops the remaining actions and reservations
+fn fail_current_task(
+    reason: FailureReason,
+    unit_ent: Entity,
+    reservations: &mut Reservations,
+    current_task: &mut CurrentTask,
+    action_queue: &mut ActionQueue,
+    current_action: &mut CurrentAction,
+) {
+    if let Some(root_task) = &mut current_task.task {
+        if let Some(task) = root_task.active_leaf_mut() {
+            task.fail(reason);
+        }
+        root_task.propagate_status();
+    }
+    reservations.release_all_for_owner(unit_ent);
+    action_queue.0.clear();
+    current_action.action = None;
+    current_action.initialized = false;
+}
+
 /// pops the front of the ActionQueue to get the next CurrentAction ; add Available component if unit has no more actions to do
 pub fn assign_next_action_or_set_available_system(
     mut commands: Commands,
     mut unit_query: Query<
         (Entity, &mut ActionQueue, &mut CurrentAction),
-        (With<Unit>, Without<Available>),
+        (With<Unit>, Without<Available>, Without<Unavailable>),
     >,
 ) {
     for (entity, mut action_queue, mut current_action) in unit_query.iter_mut() {
@@ -395,35 +745,40 @@ pub fn assign_next_action_or_set_available_system(
     }
 }
 
-/// Executor: process current actions (Take/Drop/MoveTo)
+/// Executor: process current actions (Take/Drop/MoveTo/Craft)
+#[allow(clippy::too_many_arguments)]
 pub fn process_current_action_system(
+    mut commands: Commands,
     mut reservations: ResMut<Reservations>,
+    mut items_transferred_events: EventWriter<ItemsTransferred>,
+    recipes: Res<CraftRecipes>,
+    item_registry: Res<ItemRegistry>,
     mut unit_query: Query<
         (
             Entity,
             &Transform,
             &mut Inventory,
             &mut CurrentAction,
+            &mut ActionQueue,
+            &mut CurrentTask,
             &mut PathfindingAgent,
         ),
         With<Unit>,
     >,
-    mut provider_chest_query: Query<
-        (Entity, &GlobalTransform, &mut Inventory),
-        (With<Chest>, With<Provider>, Without<Unit>),
-    >,
-    mut requester_chest_query: Query<
-        (&GlobalTransform, &mut Inventory),
-        (
-            With<Chest>,
-            With<Requester>,
-            Without<Provider>,
-            Without<Unit>,
-        ),
-    >,
+    mut chest_query: Query<(&GlobalTransform, &mut Inventory), (With<Chest>, Without<Unit>)>,
+    mut crafter_query: Query<(&GlobalTransform, &mut Crafter), Without<Unit>>,
+    mut unique_item_query: Query<(&UniqueItemKind, &mut Durability), With<Item>>,
+    mut blueprint_query: Query<&mut Blueprint>,
 ) {
-    for (unit_ent, unit_transform, mut unit_inventory, mut current_action, mut pathfinding_agent) in
-        unit_query.iter_mut()
+    for (
+        unit_ent,
+        unit_transform,
+        mut unit_inventory,
+        mut current_action,
+        mut action_queue,
+        mut current_task,
+        mut pathfinding_agent,
+    ) in unit_query.iter_mut()
     {
         if current_action.action.is_none() {
             continue;
@@ -443,16 +798,28 @@ pub fn process_current_action_system(
         if let Some(action) = &mut current_action.action {
             match action {
                 Action::MoveTo(target_pos) => {
+                    let target_pos = *target_pos;
                     let current_unit_tile_pos =
                         world_pos_to_rounded_tile(unit_transform.translation.xy());
                     // let distance = current_unit_tile_pos.distance(*target_pos);
-                    let distance = tile_distance(current_unit_tile_pos, *target_pos);
+                    let distance = tile_distance(current_unit_tile_pos, target_pos);
 
                     // Si on est assez proche de la destination, considérer la tâche terminée
                     if pathfinding_agent.path.is_empty() && distance as u8 <= UNIT_REACH {
                         current_action.action = None;
                         pathfinding_agent.reset();
                     }
+                    // the pathfinding gave up (no path, stuck, or end of a partial path)
+                    else if pathfinding_agent.target.is_none() {
+                        fail_current_task(
+                            FailureReason::PathUnreachable,
+                            unit_ent,
+                            &mut reservations,
+                            &mut current_task,
+                            &mut action_queue,
+                            &mut current_action,
+                        );
+                    }
                 }
 
                 Action::Take {
@@ -460,85 +827,500 @@ pub fn process_current_action_system(
                     quantity,
                     from,
                 } => {
+                    let (kind, quantity, from) = (*kind, *quantity, *from);
                     // try to get the chest mutably
-                    if let Ok((chest_ent, global_transform, mut provider_inventory)) =
-                        provider_chest_query.get_mut(*from)
-                    {
-                        // checks if the target is at reach
-                        let current_target_tile_pos =
-                            world_pos_to_rounded_tile(global_transform.translation().xy());
-                        let current_unit_tile_pos =
-                            world_pos_to_rounded_tile(unit_transform.translation.xy());
-                        let distance =
-                            tile_distance(current_target_tile_pos, current_unit_tile_pos);
-                        if distance as u8 > UNIT_REACH {
-                            current_action.action = None;
-                            continue;
-                        }
+                    let Ok((global_transform, mut provider_inventory)) = chest_query.get_mut(from)
+                    else {
+                        fail_current_task(
+                            FailureReason::TargetMissing,
+                            unit_ent,
+                            &mut reservations,
+                            &mut current_task,
+                            &mut action_queue,
+                            &mut current_action,
+                        );
+                        continue;
+                    };
 
-                        let available_quantity = provider_inventory.count(kind);
-                        let quantity_to_take = min(*quantity, available_quantity);
+                    // checks if the target is at reach
+                    let current_target_tile_pos =
+                        world_pos_to_rounded_tile(global_transform.translation().xy());
+                    let current_unit_tile_pos =
+                        world_pos_to_rounded_tile(unit_transform.translation.xy());
+                    let distance = tile_distance(current_target_tile_pos, current_unit_tile_pos);
+                    if distance as u8 > UNIT_REACH {
+                        fail_current_task(
+                            FailureReason::TargetOutOfReach,
+                            unit_ent,
+                            &mut reservations,
+                            &mut current_task,
+                            &mut action_queue,
+                            &mut current_action,
+                        );
+                        continue;
+                    }
 
-                        if quantity_to_take == 0 {
-                            let reserved_by_owner =
-                                reservations.owner_reserved(unit_ent, *from, *kind);
-                            if reserved_by_owner > 0 {
-                                reservations.release(unit_ent, *from, *kind, reserved_by_owner);
-                            }
-                            current_action.action = None;
+                    let quantity_taken = match transfer_items(
+                        from,
+                        &mut provider_inventory,
+                        unit_ent,
+                        &mut unit_inventory,
+                        TransferItem::Stackable { kind, quantity },
+                        &item_registry,
+                        &mut items_transferred_events,
+                    ) {
+                        TransferOutcome::Failed(error) => {
+                            fail_current_task(
+                                error.into(),
+                                unit_ent,
+                                &mut reservations,
+                                &mut current_task,
+                                &mut action_queue,
+                                &mut current_action,
+                            );
                             continue;
                         }
+                        outcome => outcome.transferred(),
+                    };
 
-                        provider_inventory.remove(kind, quantity_to_take);
-                        unit_inventory.add(*kind, quantity_to_take);
-
-                        let reserved_by_owner = reservations.owner_reserved(unit_ent, *from, *kind);
-                        if reserved_by_owner > 0 {
-                            let to_release = min(reserved_by_owner, quantity_to_take);
-                            reservations.release(unit_ent, *from, *kind, to_release);
-                        }
+                    let reserved_by_owner = reservations.owner_reserved(unit_ent, from, kind);
+                    if reserved_by_owner > 0 {
+                        let to_release = min(reserved_by_owner, quantity_taken);
+                        reservations.release(unit_ent, from, kind, to_release);
                     }
                     current_action.action = None;
                 }
 
                 Action::Drop { kind, quantity, to } => {
-                    if let Ok((global_transform, mut requester_inventory)) =
-                        requester_chest_query.get_mut(*to)
-                    {
-                        // checks if the target is at reach
-                        let current_target_tile_pos =
-                            world_pos_to_rounded_tile(global_transform.translation().xy());
-                        let current_unit_tile_pos =
-                            world_pos_to_rounded_tile(unit_transform.translation.xy());
-                        let distance =
-                            tile_distance(current_target_tile_pos, current_unit_tile_pos);
-                        if distance as u8 > UNIT_REACH {
-                            current_action.action = None;
+                    let (kind, quantity, to) = (*kind, *quantity, *to);
+                    let Ok((global_transform, mut requester_inventory)) = chest_query.get_mut(to)
+                    else {
+                        fail_current_task(
+                            FailureReason::TargetMissing,
+                            unit_ent,
+                            &mut reservations,
+                            &mut current_task,
+                            &mut action_queue,
+                            &mut current_action,
+                        );
+                        continue;
+                    };
+
+                    // checks if the target is at reach
+                    let current_target_tile_pos =
+                        world_pos_to_rounded_tile(global_transform.translation().xy());
+                    let current_unit_tile_pos =
+                        world_pos_to_rounded_tile(unit_transform.translation.xy());
+                    let distance = tile_distance(current_target_tile_pos, current_unit_tile_pos);
+                    if distance as u8 > UNIT_REACH {
+                        fail_current_task(
+                            FailureReason::TargetOutOfReach,
+                            unit_ent,
+                            &mut reservations,
+                            &mut current_task,
+                            &mut action_queue,
+                            &mut current_action,
+                        );
+                        continue;
+                    }
+
+                    match transfer_items(
+                        unit_ent,
+                        &mut unit_inventory,
+                        to,
+                        &mut requester_inventory,
+                        TransferItem::Stackable { kind, quantity },
+                        &item_registry,
+                        &mut items_transferred_events,
+                    ) {
+                        TransferOutcome::Failed(error) => {
+                            fail_current_task(
+                                error.into(),
+                                unit_ent,
+                                &mut reservations,
+                                &mut current_task,
+                                &mut action_queue,
+                                &mut current_action,
+                            );
                             continue;
                         }
+                        // the rest is dropped on the next tick ; it fails with InventoryFull
+                        // once nothing fits anymore
+                        TransferOutcome::Partial {
+                            transferred,
+                            requested,
+                        } => {
+                            let remaining =
+                                (requested - transferred).min(unit_inventory.count(&kind));
+                            current_action.action = (remaining > 0).then_some(Action::Drop {
+                                kind,
+                                quantity: remaining,
+                                to,
+                            });
+                        }
+                        TransferOutcome::Complete(_) => current_action.action = None,
+                    }
+                }
+
+                Action::TakeUnique { item, from } => {
+                    let (item, from) = (*item, *from);
+                    let Ok((global_transform, mut provider_inventory)) = chest_query.get_mut(from)
+                    else {
+                        fail_current_task(
+                            FailureReason::TargetMissing,
+                            unit_ent,
+                            &mut reservations,
+                            &mut current_task,
+                            &mut action_queue,
+                            &mut current_action,
+                        );
+                        continue;
+                    };
 
-                        let available_quantity = unit_inventory.count(kind);
-                        let quantity_to_take = min(*quantity, available_quantity);
+                    // checks if the target is at reach
+                    let current_target_tile_pos =
+                        world_pos_to_rounded_tile(global_transform.translation().xy());
+                    let current_unit_tile_pos =
+                        world_pos_to_rounded_tile(unit_transform.translation.xy());
+                    let distance = tile_distance(current_target_tile_pos, current_unit_tile_pos);
+                    if distance as u8 > UNIT_REACH {
+                        fail_current_task(
+                            FailureReason::TargetOutOfReach,
+                            unit_ent,
+                            &mut reservations,
+                            &mut current_task,
+                            &mut action_queue,
+                            &mut current_action,
+                        );
+                        continue;
+                    }
 
-                        if quantity_to_take == 0 {
-                            current_action.action = None;
+                    if let TransferOutcome::Failed(error) = transfer_items(
+                        from,
+                        &mut provider_inventory,
+                        unit_ent,
+                        &mut unit_inventory,
+                        TransferItem::Unique(item),
+                        &item_registry,
+                        &mut items_transferred_events,
+                    ) {
+                        fail_current_task(
+                            error.into(),
+                            unit_ent,
+                            &mut reservations,
+                            &mut current_task,
+                            &mut action_queue,
+                            &mut current_action,
+                        );
+                        continue;
+                    }
+                    current_action.action = None;
+                }
+
+                Action::DropUnique { item, to } => {
+                    let (item, to) = (*item, *to);
+                    let Ok((global_transform, mut requester_inventory)) = chest_query.get_mut(to)
+                    else {
+                        fail_current_task(
+                            FailureReason::TargetMissing,
+                            unit_ent,
+                            &mut reservations,
+                            &mut current_task,
+                            &mut action_queue,
+                            &mut current_action,
+                        );
+                        continue;
+                    };
+
+                    // checks if the target is at reach
+                    let current_target_tile_pos =
+                        world_pos_to_rounded_tile(global_transform.translation().xy());
+                    let current_unit_tile_pos =
+                        world_pos_to_rounded_tile(unit_transform.translation.xy());
+                    let distance = tile_distance(current_target_tile_pos, current_unit_tile_pos);
+                    if distance as u8 > UNIT_REACH {
+                        fail_current_task(
+                            FailureReason::TargetOutOfReach,
+                            unit_ent,
+                            &mut reservations,
+                            &mut current_task,
+                            &mut action_queue,
+                            &mut current_action,
+                        );
+                        continue;
+                    }
+
+                    if let TransferOutcome::Failed(error) = transfer_items(
+                        unit_ent,
+                        &mut unit_inventory,
+                        to,
+                        &mut requester_inventory,
+                        TransferItem::Unique(item),
+                        &item_registry,
+                        &mut items_transferred_events,
+                    ) {
+                        fail_current_task(
+                            error.into(),
+                            unit_ent,
+                            &mut reservations,
+                            &mut current_task,
+                            &mut action_queue,
+                            &mut current_action,
+                        );
+                        continue;
+                    }
+                    current_action.action = None;
+                }
+
+                Action::Build { blueprint } => {
+                    let blueprint_ent = *blueprint;
+                    let Ok(mut blueprint) = blueprint_query.get_mut(blueprint_ent) else {
+                        fail_current_task(
+                            FailureReason::TargetMissing,
+                            unit_ent,
+                            &mut reservations,
+                            &mut current_task,
+                            &mut action_queue,
+                            &mut current_action,
+                        );
+                        continue;
+                    };
+
+                    // checks if the blueprint is at reach
+                    let current_unit_tile_pos =
+                        world_pos_to_rounded_tile(unit_transform.translation.xy());
+                    let distance = tile_distance(blueprint.rounded_tile_pos, current_unit_tile_pos);
+                    if distance as u8 > UNIT_REACH {
+                        fail_current_task(
+                            FailureReason::TargetOutOfReach,
+                            unit_ent,
+                            &mut reservations,
+                            &mut current_task,
+                            &mut action_queue,
+                            &mut current_action,
+                        );
+                        continue;
+                    }
+
+                    // the first builder brings the materials
+                    if !blueprint.materials_delivered {
+                        let build_cost = blueprint.kind.build_cost();
+                        if !build_cost
+                            .iter()
+                            .all(|(kind, amount)| unit_inventory.count(kind) >= *amount)
+                        {
+                            fail_current_task(
+                                FailureReason::NotEnoughItems,
+                                unit_ent,
+                                &mut reservations,
+                                &mut current_task,
+                                &mut action_queue,
+                                &mut current_action,
+                            );
                             continue;
                         }
+                        for (kind, amount) in &build_cost {
+                            unit_inventory.remove(kind, *amount);
+                        }
+                        blueprint.materials_delivered = true;
+                    } else {
+                        blueprint.remaining_ticks = blueprint.remaining_ticks.saturating_sub(1);
+                    }
 
-                        unit_inventory.remove(kind, quantity_to_take);
-                        requester_inventory.add(*kind, quantity_to_take);
+                    // complete_blueprints_system places the structure
+                    if blueprint.is_complete() {
+                        current_action.action = None;
                     }
-                    current_action.action = None
                 }
 
                 Action::Craft {
-                    recipe: _,
-                    quantity: _,
-                    with: _,
+                    recipe,
+                    quantity,
+                    with,
                 } => {
-                    // TODO: do that
-                    todo!();
-                    current_action.action = None;
+                    let (recipe, quantity, with) = (*recipe, *quantity, *with);
+                    let Ok((global_transform, mut crafter)) = crafter_query.get_mut(with) else {
+                        fail_current_task(
+                            FailureReason::TargetMissing,
+                            unit_ent,
+                            &mut reservations,
+                            &mut current_task,
+                            &mut action_queue,
+                            &mut current_action,
+                        );
+                        continue;
+                    };
+
+                    // checks if the crafter is at reach
+                    let current_target_tile_pos =
+                        world_pos_to_rounded_tile(global_transform.translation().xy());
+                    let current_unit_tile_pos =
+                        world_pos_to_rounded_tile(unit_transform.translation.xy());
+                    let distance = tile_distance(current_target_tile_pos, current_unit_tile_pos);
+                    if distance as u8 > UNIT_REACH {
+                        fail_current_task(
+                            FailureReason::TargetOutOfReach,
+                            unit_ent,
+                            &mut reservations,
+                            &mut current_task,
+                            &mut action_queue,
+                            &mut current_action,
+                        );
+                        continue;
+                    }
+
+                    match &mut crafter.progress {
+                        // crafter is free: consume the inputs and start crafting
+                        None => {
+                            let Some(craft_recipe) = recipes.get(&recipe) else {
+                                fail_current_task(
+                                    FailureReason::UnknownRecipe,
+                                    unit_ent,
+                                    &mut reservations,
+                                    &mut current_task,
+                                    &mut action_queue,
+                                    &mut current_action,
+                                );
+                                continue;
+                            };
+                            // the first tool of the right kind carried by the unit
+                            let tool = craft_recipe.tool.map(|tool_kind| {
+                                unit_inventory
+                                    .unique_items
+                                    .iter()
+                                    .copied()
+                                    .find(|item_ent| {
+                                        unique_item_query
+                                            .get(*item_ent)
+                                            .is_ok_and(|(kind, _)| *kind == tool_kind)
+                                    })
+                            });
+                            // the tool is used once per craft : it only does the crafts its durability allows
+                            let crafts = match tool {
+                                Some(Some(tool_ent)) => unique_item_query
+                                    .get(tool_ent)
+                                    .map_or(0, |(_, durability)| {
+                                        quantity.min(durability.current as u32)
+                                    }),
+                                _ => quantity,
+                            };
+                            if crafts == 0
+                                || !craft_recipe.has_inputs(&unit_inventory, crafts)
+                                || tool == Some(None)
+                            {
+                                fail_current_task(
+                                    FailureReason::NotEnoughItems,
+                                    unit_ent,
+                                    &mut reservations,
+                                    &mut current_task,
+                                    &mut action_queue,
+                                    &mut current_action,
+                                );
+                                continue;
+                            }
+
+                            // the outputs must fit once the inputs are consumed
+                            let mut after_craft = unit_inventory.clone();
+                            for (kind, amount) in &craft_recipe.inputs {
+                                after_craft.remove(kind, amount * crafts);
+                            }
+                            if !craft_recipe.outputs.iter().all(|(kind, amount)| {
+                                after_craft.add(*kind, amount * crafts, &item_registry) == 0
+                            }) {
+                                fail_current_task(
+                                    FailureReason::InventoryFull,
+                                    unit_ent,
+                                    &mut reservations,
+                                    &mut current_task,
+                                    &mut action_queue,
+                                    &mut current_action,
+                                );
+                                continue;
+                            }
+
+                            for (kind, amount) in &craft_recipe.inputs {
+                                unit_inventory.remove(kind, amount * crafts);
+                            }
+                            if let Some(Some(tool_ent)) = tool
+                                && let Ok((_, mut durability)) = unique_item_query.get_mut(tool_ent)
+                            {
+                                wear_unique_item(
+                                    &mut commands,
+                                    &mut unit_inventory,
+                                    tool_ent,
+                                    &mut durability,
+                                    crafts as u16,
+                                );
+                            }
+                            crafter.progress = Some(CraftingProgress {
+                                owner: unit_ent,
+                                recipe,
+                                quantity: crafts,
+                                remaining_ticks: craft_recipe.duration_ticks * crafts,
+                            });
+                        }
+                        // crafter already used by another unit
+                        Some(progress) if progress.owner != unit_ent => {
+                            fail_current_task(
+                                FailureReason::CrafterBusy,
+                                unit_ent,
+                                &mut reservations,
+                                &mut current_task,
+                                &mut action_queue,
+                                &mut current_action,
+                            );
+                            continue;
+                        }
+                        // crafting in progress
+                        Some(progress) => {
+                            progress.remaining_ticks = progress.remaining_ticks.saturating_sub(1);
+                        }
+                    }
+
+                    if let Some(progress) = &crafter.progress
+                        && progress.remaining_ticks == 0
+                    {
+                        let crafts = progress.quantity;
+                        let outputs: Vec<(ItemId, u32)> =
+                            recipes
+                                .get(&progress.recipe)
+                                .map_or(Vec::new(), |craft_recipe| {
+                                    craft_recipe
+                                        .outputs
+                                        .iter()
+                                        .map(|(kind, amount)| (*kind, amount * crafts))
+                                        .collect()
+                                });
+                        // the inventory may have changed since the start : the inputs are given back by release_abandoned_crafters_system
+                        if !unit_inventory.has_space_for_all(&outputs, &item_registry) {
+                            fail_current_task(
+                                FailureReason::InventoryFull,
+                                unit_ent,
+                                &mut reservations,
+                                &mut current_task,
+                                &mut action_queue,
+                                &mut current_action,
+                            );
+                            continue;
+                        }
+                        for (kind, quantity) in outputs {
+                            let overflow = unit_inventory.add(kind, quantity, &item_registry);
+                            debug_assert_eq!(overflow, 0);
+                        }
+                        crafter.progress = None;
+                        current_action.action = None;
+                        // the tool broke before the last craft
+                        if crafts < quantity {
+                            fail_current_task(
+                                FailureReason::ToolBroken,
+                                unit_ent,
+                                &mut reservations,
+                                &mut current_task,
+                                &mut action_queue,
+                                &mut current_action,
+                            );
+                        }
+                    }
                 }
             }
         }
@@ -547,35 +1329,59 @@ pub fn process_current_action_system(
 
 /// Mark tasks completed/failed and release leftovers.
 /// Logic:
-/// - If a unit has a Task in Planned state and both ActionQueue empty & no CurrentAction -> mark Completed and clear task + release any leftover reservations.
-/// - If a Task is Failed -> release reservations & clear task (so it can be retried).
-fn update_task_completion_system(
+/// - If the task being done is Planned and both ActionQueue empty & no CurrentAction -> mark it Completed + release any leftover reservations.
+/// - The statuses are then propagated to the root task, and the next sub_task will be planned on the next tick.
+/// - If the root Task is Completed -> clear task.
+/// - If the root Task is Failed -> release reservations, clear task & send TaskFailed (its sub_tasks already used their retries).
+pub fn update_task_completion_system(
     mut commands: Commands,
     mut reservations: ResMut<Reservations>,
+    mut task_failed_events: EventWriter<TaskFailed>,
     mut unit_query: Query<
-        (Entity, &ActionQueue, &mut CurrentTask, &CurrentAction),
+        (
+            Entity,
+            &ActionQueue,
+            &mut CurrentTask,
+            &mut TaskQueue,
+            &CurrentAction,
+        ),
         (With<Unit>, With<Inventory>),
     >,
 ) {
-    for (unit_ent, action_queue, mut current_task, current_action) in unit_query.iter_mut() {
+    for (unit_ent, action_queue, mut current_task, mut task_queue, current_action) in
+        unit_query.iter_mut()
+    {
         // nothing to do
-        let Some(task) = &mut current_task.task else {
+        let Some(root_task) = &mut current_task.task else {
             continue;
         };
 
-        match task.status {
-            TaskStatus::Planned | TaskStatus::InProgress => {
-                if action_queue.0.is_empty() && current_action.action.is_none() {
-                    task.status = TaskStatus::Completed;
-                    reservations.release_all_for_owner(unit_ent);
-                    current_task.reset();
-                    match commands.get_entity(unit_ent) {
-                        Ok(mut entity_command) => entity_command.insert(Available),
-                        Err(_) => todo!(),
-                    };
-                }
+        if let Some(task) = root_task.active_leaf_mut()
+            && matches!(task.status, TaskStatus::Planned | TaskStatus::InProgress)
+            && action_queue.0.is_empty()
+            && current_action.action.is_none()
+        {
+            task.status = TaskStatus::Completed;
+            reservations.release_all_for_owner(unit_ent);
+        }
+        root_task.propagate_status();
+
+        match root_task.status {
+            TaskStatus::Failed(reason) => {
+                task_failed_events.write(TaskFailed {
+                    unit: unit_ent,
+                    task: root_task.clone(),
+                    reason,
+                });
+                reservations.release_all_for_owner(unit_ent);
+                current_task.reset();
+                task_queue.0.clear();
+                match commands.get_entity(unit_ent) {
+                    Ok(mut entity_command) => entity_command.insert(Available),
+                    Err(_) => todo!(),
+                };
             }
-            TaskStatus::Failed => {
+            TaskStatus::Completed => {
                 reservations.release_all_for_owner(unit_ent);
                 current_task.reset();
                 match commands.get_entity(unit_ent) {
@@ -583,27 +1389,89 @@ fn update_task_completion_system(
                     Err(_) => todo!(),
                 };
             }
-            TaskStatus::Pending | TaskStatus::Completed => {
+            TaskStatus::Pending | TaskStatus::Planned | TaskStatus::InProgress => {
                 // nothing special
             }
         }
     }
 }
 
+/// pops the front of the TaskQueue when the unit has no CurrentTask
+pub fn start_next_task_system(
+    mut unit_query: Query<(&mut CurrentTask, &mut TaskQueue), With<Unit>>,
+) {
+    for (mut current_task, mut task_queue) in unit_query.iter_mut() {
+        if current_task.task.is_none()
+            && let Some(next_task) = task_queue.0.pop_front()
+        {
+            current_task.task = Some(next_task);
+            current_task.initialized = false;
+        }
+    }
+}
+
+/// frees crafters whose owner stopped crafting (action reset, unit despawned...) and gives back the inputs
+/// the crafter is kept until the owner has room for them
+fn release_abandoned_crafters_system(
+    recipes: Res<CraftRecipes>,
+    item_registry: Res<ItemRegistry>,
+    mut crafter_query: Query<(Entity, &mut Crafter)>,
+    mut unit_query: Query<(&CurrentAction, &mut Inventory), With<Unit>>,
+) {
+    for (crafter_ent, mut crafter) in crafter_query.iter_mut() {
+        let Some(progress) = &crafter.progress else {
+            continue;
+        };
+
+        if let Ok((current_action, mut owner_inventory)) = unit_query.get_mut(progress.owner) {
+            if let Some(Action::Craft { with, .. }) = current_action.action
+                && with == crafter_ent
+            {
+                continue;
+            }
+            if let Some(craft_recipe) = recipes.get(&progress.recipe) {
+                let inputs: Vec<(ItemId, u32)> = craft_recipe
+                    .inputs
+                    .iter()
+                    .map(|(kind, amount)| (*kind, amount * progress.quantity))
+                    .collect();
+                if !owner_inventory.has_space_for_all(&inputs, &item_registry) {
+                    continue;
+                }
+                for (kind, quantity) in inputs {
+                    owner_inventory.add(kind, quantity, &item_registry);
+                }
+            }
+        }
+        crafter.progress = None;
+    }
+}
+
 fn find_best_chest(
     unit_tile_pos: IVec2,
     desired_quantity: u32,
-    desired_item_kind: ItemKind,
+    desired_item_kind: ItemId,
     chest_query: &Query<
-        (Entity, &GlobalTransform, &Inventory),
-        (With<Chest>, With<Provider>, Without<Unit>),
+        (
+            Entity,
+            &GlobalTransform,
+            &Inventory,
+            Option<&LogisticsChest>,
+        ),
+        (With<Chest>, Without<Unit>),
     >,
     reservations: &Reservations,
 ) -> Option<(Entity, IVec2, u32)> {
     let mut best_with_enough: Option<(Entity, IVec2, u32, f32)> = None; // (entity, tile, qty, distance)
     let mut best_any: Option<(Entity, IVec2, u32, f32)> = None; // nearest with at least 1
 
-    for (chest_ent, chest_global_transform, chest_inv) in chest_query.iter() {
+    for (chest_ent, chest_global_transform, chest_inv, logistics_chest) in chest_query.iter() {
+        // only takes from chests providing that item
+        if !logistics_chest
+            .is_some_and(|logistics_chest| logistics_chest.provides(&desired_item_kind))
+        {
+            continue;
+        }
         let chest_tile = world_pos_to_rounded_tile(chest_global_transform.translation().xy());
         let dist = tile_distance(unit_tile_pos, chest_tile);
         let real_quantity = chest_inv.count(&desired_item_kind);
@@ -642,61 +1510,103 @@ fn find_best_chest(
     }
 }
 
-/// Test helper: assign a GetItems task when pressing E (safe: only assign when no current task or previous task completed/failed)
-fn test_find_2_rocks_system(
-    mut unit_query: Query<&mut CurrentTask, (With<Unit>, With<PathfindingAgent>)>,
-) {
-    let mut counter = 0;
-    for mut unit_current_task in unit_query.iter_mut() {
-        if counter > 0 {
-            return;
-        }
-        // Only assign if there's no task or last task finished/failed
-        let assign = match &unit_current_task.task {
-            None => true,
-            Some(t) => matches!(t.status, TaskStatus::Completed | TaskStatus::Failed),
-        };
-        if assign {
-            let find_2_rocks = Task::new(
-                TaskKind::GetItems {
-                    kind: ItemKind::Rock,
-                    quantity: 2,
-                },
-                Vec::new(),
-            );
-            unit_current_task.task = Some(find_2_rocks);
-            unit_current_task.initialized = false;
-        }
-        counter += 1;
-    }
+/// Test helper: post a GetItems job when pressing E
+fn test_find_2_rocks_system(mut job_board: ResMut<JobBoard>) {
+    let find_2_rocks = Task::new(
+        TaskKind::GetItems {
+            kind: ItemId::ROCK,
+            quantity: 2,
+        },
+        Vec::new(),
+    );
+    job_board.post(find_2_rocks, 0, None);
 }
 
-/// Test helper: assign a DeliverItems task that goes to the requester chest and drops 2 rocks
+/// Test helper: post a job that takes 2 rocks and drops them in the first chest requesting rocks
 fn test_deliver_2_rocks_system(
-    mut unit_query: Query<&mut CurrentTask, (With<Unit>, With<PathfindingAgent>)>,
+    mut job_board: ResMut<JobBoard>,
+    logistics_chest_query: Query<(Entity, &GlobalTransform, &LogisticsChest)>,
 ) {
-    let mut counter = 0;
-    for mut unit_current_task in unit_query.iter_mut() {
-        if counter > 0 {
-            return;
-        }
-        // assign only if idle or previous task finished/failed
-        let assign = match &unit_current_task.task {
-            None => true,
-            Some(t) => matches!(t.status, TaskStatus::Completed | TaskStatus::Failed),
-        };
-        if assign {
-            let deliver_2_rocks = Task::new(
-                TaskKind::DeliverItems {
-                    kind: ItemKind::Rock,
-                    quantity: 2,
+    let Some((requester_ent, global_transform, _)) = logistics_chest_query
+        .iter()
+        .find(|(_, _, logistics_chest)| logistics_chest.requests.contains_key(&ItemId::ROCK))
+    else {
+        return;
+    };
+    let find_2_rocks = Task::new(
+        TaskKind::GetItems {
+            kind: ItemId::ROCK,
+            quantity: 2,
+        },
+        Vec::new(),
+    );
+    let deliver_2_rocks = Task::new(
+        TaskKind::DeliverItems {
+            kind: ItemId::ROCK,
+            quantity: 2,
+            to: requester_ent,
+        },
+        Vec::new(),
+    );
+    let requester_tile_pos = world_pos_to_rounded_tile(global_transform.translation().xy());
+    job_board.post(
+        Task::sequence(vec![find_2_rocks, deliver_2_rocks]),
+        0,
+        Some(requester_tile_pos),
+    );
+}
+
+/// Test helper: post a job that gets the rocks for a chest, brings them to the first crafter and crafts the chest
+fn test_craft_chest_system(
+    mut job_board: ResMut<JobBoard>,
+    recipes: Res<CraftRecipes>,
+    crafter_query: Query<(Entity, &GlobalTransform), With<Crafter>>,
+) {
+    let Some((crafter_ent, global_transform)) = crafter_query.iter().next() else {
+        return;
+    };
+    let Some(recipe) = recipes.get(&CraftRecipeId::Chest) else {
+        return;
+    };
+    let crafter_tile_pos = world_pos_to_rounded_tile(global_transform.translation().xy());
+
+    let get_inputs = recipe
+        .inputs
+        .iter()
+        .map(|(kind, amount)| {
+            Task::new(
+                TaskKind::GetItems {
+                    kind: *kind,
+                    quantity: *amount,
                 },
                 Vec::new(),
-            );
-            unit_current_task.task = Some(deliver_2_rocks);
-            unit_current_task.initialized = false;
-        }
-        counter += 1;
+            )
+        })
+        .collect();
+    let craft_chest = Task::sequence(vec![
+        Task::parallel(get_inputs),
+        Task::new(
+            TaskKind::Action(Action::MoveTo(crafter_tile_pos)),
+            Vec::new(),
+        ),
+        Task::new(
+            TaskKind::Action(Action::Craft {
+                recipe: CraftRecipeId::Chest,
+                quantity: 1,
+                with: crafter_ent,
+            }),
+            Vec::new(),
+        ),
+    ]);
+    job_board.post(craft_chest, 0, Some(crafter_tile_pos));
+}
+
+fn log_task_failures_system(mut task_failed_events: EventReader<TaskFailed>) {
+    for event in task_failed_events.read() {
+        println!(
+            "task {:?} of unit {} failed: {:?}",
+            event.task.kind, event.unit, event.reason
+        );
     }
 }
 
@@ -1,19 +1,21 @@
 use std::{collections::HashSet, time::Duration};
 
 use crate::{
-    UPS_TARGET, UpsCounter,
-    items::Inventory,
+    UPS_TARGET,
+    items::{Inventory, InventoryCapacity},
     map::{
-        StructureManager, TILE_SIZE, get_neighbors, is_tile_passable, rounded_tile_pos_to_world,
-        world_pos_to_rounded_tile,
+        GroundManager, StructureManager, TILE_SIZE, get_neighbors, is_tile_passable,
+        rounded_tile_pos_to_world, world_pos_to_rounded_tile,
     },
     pathfinding::PathfindingAgent,
-    units::tasks::{ActionQueue, CurrentAction, CurrentTask},
+    units::tasks::{ActionQueue, CurrentAction, CurrentTask, TaskQueue},
 };
 use bevy::{prelude::*, time::common_conditions::on_timer};
 use rand::{Rng, rng};
+use serde::{Deserialize, Serialize};
 
 pub const UNIT_REACH: u8 = 1;
+pub const UNIT_CAPACITY: InventoryCapacity = InventoryCapacity::Weight(40.0);
 pub const UNIT_DEFAULT_MOVEMENT_SPEED: u32 = UPS_TARGET as u32; // ticks per tile ; smaller is faster (here its 1 tile per second at normal tickrate by default)
 
 pub struct UnitsPlugin;
@@ -23,7 +25,6 @@ impl Plugin for UnitsPlugin {
         app.add_systems(
             FixedUpdate,
             (
-                test_units_control_system.before(move_and_collide_units_system),
                 move_and_collide_units_system,
                 update_sprite_facing_system.after(move_and_collide_units_system),
                 display_units_with_no_current_action_system
@@ -40,10 +41,11 @@ impl Plugin for UnitsPlugin {
     Transform,
     TileMovement,
     PathfindingAgent,
-    Inventory,
+    Inventory = Inventory::with_capacity(UNIT_CAPACITY),
     ActionQueue,
     CurrentAction,
-    CurrentTask
+    CurrentTask,
+    TaskQueue
 )]
 pub struct Unit {
     pub name: String,
@@ -52,7 +54,7 @@ pub struct Unit {
 #[derive(Component)]
 struct Player;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Direction {
     Null,
     NorthWest,
@@ -97,7 +99,7 @@ impl Direction {
     }
 }
 
-#[derive(Component)]
+#[derive(Component, Clone, Serialize, Deserialize)]
 pub struct TileMovement {
     pub direction: Direction,
     ticks_per_tile: u32, // movement speed ; smaller is faster
@@ -123,6 +125,10 @@ impl TileMovement {
         }
     }
 
+    pub fn ticks_per_tile(&self) -> u32 {
+        self.ticks_per_tile
+    }
+
     pub fn update_speed(&mut self, ticks_per_tile: u32) {
         self.ticks_per_tile = ticks_per_tile;
         self.tick_counter = 0;
@@ -133,8 +139,10 @@ impl TileMovement {
 #[derive(Component)]
 pub struct UnitUnitCollisions;
 
+/// a step takes ticks_per_tile multiplied by the movement cost of the ground it goes to
 pub fn move_and_collide_units_system(
     structure_manager: Res<StructureManager>,
+    ground_manager: Res<GroundManager>,
     mut unit_query: Query<
         (
             &mut Transform,
@@ -158,13 +166,22 @@ pub fn move_and_collide_units_system(
             continue;
         }
 
+        let current_tile = world_pos_to_rounded_tile(transform.translation.xy());
+        let desired_target_tile = current_tile + tile_movement.direction.delta();
+        // water
+        let Some(movement_cost) = ground_manager.ground.movement_cost(desired_target_tile) else {
+            tile_movement.direction = Direction::Null;
+            tile_movement.tick_counter = 0;
+            continue;
+        };
+        let step_ticks =
+            ((tile_movement.ticks_per_tile as f32 * movement_cost).round() as u32).max(1);
+
         tile_movement.tick_counter += 1;
 
-        if tile_movement.tick_counter >= tile_movement.ticks_per_tile {
+        if tile_movement.tick_counter >= step_ticks {
             tile_movement.tick_counter = 0;
 
-            let current_tile = world_pos_to_rounded_tile(transform.translation.xy());
-            let desired_target_tile = current_tile + tile_movement.direction.delta();
             // if there is a structure
             if !is_tile_passable(desired_target_tile, &structure_manager) {
                 tile_movement.direction = Direction::Null;
@@ -0,0 +1,1519 @@
+use bevy::{ecs::system::RunSystemOnce, prelude::*};
+use bevy_ecs_tilemap::prelude::{TileStorage, TileTextureIndex};
+use overlord::{
+    build::{Blueprint, BlueprintManager, BuildCommand},
+    items::{
+        CraftRecipeId, Durability, Inventory, InventoryCapacity, ItemId, ItemsTransferred,
+        TransferItem, UniqueItemKind,
+    },
+    logistics::LogisticsChest,
+    map::{
+        Chest, Crafter, Generated, Structure, StructureKind, StructureManager, Wall, WorldSeed,
+        rounded_tile_pos_to_rounded_chunk, rounded_tile_pos_to_world, spawn_chunk,
+    },
+    pathfinding::{
+        PathComputation, PathRequests, PathfindingAgent, PathfindingSettings,
+        flow_field::FlowFields,
+    },
+    save::{load_system, save_from_str, save_system, save_to_string},
+    scenario::{
+        DEFAULT_SCENARIO_PATH, Scenario, ScenarioApp, ScenarioStructure, ScenarioTask, ScenarioUnit,
+    },
+    selection::{Order, Selected, SelectionOrder},
+    terrain::TileKind,
+    units::{
+        TileMovement, Unit,
+        jobs::{AssignedJob, Capabilities, Capability, JobBoard},
+        states::Available,
+        tasks::{
+            Action, ActionQueue, CurrentAction, CurrentTask, FailureReason, MAX_TASKS_RETRIES,
+            Reservations, Task, TaskKind, TaskStatus,
+        },
+    },
+};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+const SEED: u64 = 42;
+
+fn chest(name: &str, x: i32, y: i32) -> ScenarioStructure {
+    ScenarioStructure::new(name, StructureKind::Chest, IVec2::new(x, y))
+}
+
+fn get_items(kind: ItemId, quantity: u32) -> Task {
+    Task::new(TaskKind::GetItems { kind, quantity }, Vec::new())
+}
+
+#[test]
+fn unit_moves_to_target_tile() {
+    let mut scenario = Scenario::new(SEED)
+        .unit(ScenarioUnit::new("unit", IVec2::new(0, 0)).with_speed(2))
+        .app();
+    scenario.give_task(
+        "unit",
+        Task::new(
+            TaskKind::Action(Action::MoveTo(IVec2::new(4, 3))),
+            Vec::new(),
+        ),
+    );
+
+    assert!(scenario.run_until(200, |scenario| scenario.task_status("unit").is_none()));
+    assert_eq!(scenario.rounded_tile_pos("unit"), IVec2::new(4, 3));
+    assert!(scenario.failures().is_empty());
+}
+
+#[test]
+fn unit_takes_items_from_closest_provider() {
+    let mut scenario = Scenario::new(SEED)
+        .structure(
+            chest("close", 3, 0)
+                .with_items([(ItemId::ROCK, 10)])
+                .with_logistics(LogisticsChest::provider()),
+        )
+        .structure(
+            chest("far", -8, 0)
+                .with_items([(ItemId::ROCK, 10)])
+                .with_logistics(LogisticsChest::provider()),
+        )
+        .unit(ScenarioUnit::new("unit", IVec2::new(0, 0)).with_speed(2))
+        .app();
+    scenario.give_task("unit", get_items(ItemId::ROCK, 5));
+
+    scenario.run_ticks(1);
+    assert!(scenario.task_status("unit").is_some());
+    assert!(scenario.run_until(300, |scenario| scenario.task_status("unit").is_none()));
+    assert_eq!(scenario.count("unit", ItemId::ROCK), 5);
+    assert_eq!(scenario.count("close", ItemId::ROCK), 5);
+    assert_eq!(scenario.count("far", ItemId::ROCK), 10);
+}
+
+#[test]
+fn logistics_fills_requester_chest() {
+    let mut scenario = Scenario::new(SEED)
+        .structure(
+            chest("provider", 4, 0)
+                .with_items([(ItemId::ROCK, 100)])
+                .with_logistics(LogisticsChest::provider()),
+        )
+        .structure(
+            chest("requester", -4, 0)
+                .with_logistics(LogisticsChest::requester([(ItemId::ROCK, 25)])),
+        )
+        .unit(ScenarioUnit::new("unit 1", IVec2::new(0, 1)).with_speed(2))
+        .unit(ScenarioUnit::new("unit 2", IVec2::new(0, -1)).with_speed(2))
+        .app();
+
+    assert!(scenario.run_until(3000, |scenario| {
+        scenario.count("requester", ItemId::ROCK) == 25
+    }));
+    // leaves time to the units to finish what they started
+    scenario.run_ticks(300);
+    assert_eq!(scenario.count("requester", ItemId::ROCK), 25);
+    assert_eq!(scenario.count("provider", ItemId::ROCK), 75);
+    assert!(scenario.failures().is_empty());
+}
+
+#[test]
+fn get_items_without_provider_fails_after_retries() {
+    let mut scenario = Scenario::new(SEED)
+        .structure(chest("not a provider", 3, 0).with_items([(ItemId::ROCK, 10)]))
+        .unit(ScenarioUnit::new("unit", IVec2::new(0, 0)))
+        .app();
+    scenario.give_task("unit", get_items(ItemId::ROCK, 5));
+
+    scenario.run_ticks(1);
+    assert_eq!(scenario.task_status("unit"), Some(TaskStatus::Pending));
+    assert!(scenario.run_until(1000, |scenario| !scenario.failures().is_empty()));
+    assert_eq!(scenario.failures()[0].reason, FailureReason::NoProvider);
+    assert_eq!(scenario.failures()[0].unit, scenario.entity("unit"));
+    assert!(scenario.task_status("unit").is_none());
+    assert_eq!(scenario.count("not a provider", ItemId::ROCK), 10);
+}
+
+#[test]
+fn failed_job_is_posted_again_on_the_board() {
+    let mut scenario = Scenario::new(SEED)
+        .structure(chest("chest", 3, 0).with_items([(ItemId::ROCK, 10)]))
+        .unit(ScenarioUnit::new("unit", IVec2::new(0, 0)).with_speed(2))
+        .app();
+    scenario.post_job(get_items(ItemId::ROCK, 5), 0, None);
+
+    assert!(scenario.run_until(1000, |scenario| !scenario.failures().is_empty()));
+    // the chest only provides the rocks once the unit used its retries
+    let chest_ent = scenario.entity("chest");
+    scenario
+        .app
+        .world_mut()
+        .entity_mut(chest_ent)
+        .insert(LogisticsChest::provider());
+    assert!(scenario.run_until(1000, |scenario| {
+        scenario.count("unit", ItemId::ROCK) == 5
+    }));
+}
+
+#[test]
+fn failed_job_is_dropped_once_posted_again_max_tasks_retries_times() {
+    let mut scenario = Scenario::new(SEED)
+        .structure(chest("not a provider", 3, 0).with_items([(ItemId::ROCK, 10)]))
+        .unit(ScenarioUnit::new("unit", IVec2::new(0, 0)).with_speed(2))
+        .app();
+    scenario.post_job(get_items(ItemId::ROCK, 5), 0, None);
+
+    let attempts = MAX_TASKS_RETRIES as usize + 1;
+    assert!(scenario.run_until(5000, |scenario| scenario.failures().len() == attempts));
+    scenario.run_ticks(1000);
+    assert_eq!(scenario.failures().len(), attempts);
+    assert!(scenario.app.world().resource::<JobBoard>().jobs.is_empty());
+    let unit = scenario.entity("unit");
+    assert!(scenario.app.world().get::<AssignedJob>(unit).is_none());
+}
+
+#[test]
+fn default_scenario_spawns_its_world() {
+    let scenario = Scenario::load(Path::new(DEFAULT_SCENARIO_PATH)).unwrap();
+    let mut scenario = scenario.app();
+
+    let mut unit_query = scenario.app.world_mut().query::<&Unit>();
+    assert_eq!(unit_query.iter(scenario.app.world()).count(), 101);
+    assert_eq!(scenario.count("provider chest 3", ItemId::ROCK), 1000);
+    assert_eq!(scenario.count("requester chest", ItemId::ROCK), 1);
+    assert_eq!(scenario.rounded_tile_pos("AFK Player"), IVec2::new(5, 0));
+
+    // the other units become Available and take the logistics jobs, not the AFK Player
+    scenario.run_ticks(60);
+    let afk_player = scenario.entity("AFK Player");
+    let world = scenario.app.world();
+    assert!(world.get::<Available>(afk_player).is_none());
+    assert!(world.get::<AssignedJob>(afk_player).is_none());
+}
+
+#[test]
+fn unavailable_unit_never_takes_jobs() {
+    let mut scenario = Scenario::new(SEED)
+        .unit(ScenarioUnit::new("unit", IVec2::new(0, 0)).unavailable())
+        .app();
+    scenario.post_job(
+        Task::new(
+            TaskKind::Action(Action::MoveTo(IVec2::new(3, 0))),
+            Vec::new(),
+        ),
+        0,
+        None,
+    );
+    scenario.run_ticks(30);
+
+    let unit = scenario.entity("unit");
+    assert!(scenario.app.world().get::<Available>(unit).is_none());
+    assert_eq!(scenario.app.world().resource::<JobBoard>().jobs.len(), 1);
+    assert_eq!(scenario.rounded_tile_pos("unit"), IVec2::new(0, 0));
+}
+
+// speeds of the units of the default scenario, by spawn order
+fn default_scenario_unit_speeds(seed: u64) -> Vec<u32> {
+    let mut scenario = Scenario::load(Path::new(DEFAULT_SCENARIO_PATH)).unwrap();
+    scenario.seed = Some(seed);
+    let mut scenario = scenario.app();
+    let world = scenario.app.world_mut();
+    let mut units: Vec<(Entity, u32)> = world
+        .query::<(Entity, &TileMovement)>()
+        .iter(world)
+        .map(|(unit_ent, tile_movement)| (unit_ent, tile_movement.ticks_per_tile()))
+        .collect();
+    units.sort();
+    units.into_iter().map(|(_, speed)| speed).collect()
+}
+
+#[test]
+fn random_unit_speeds_come_from_the_seed() {
+    assert_eq!(
+        default_scenario_unit_speeds(SEED),
+        default_scenario_unit_speeds(SEED)
+    );
+    assert_ne!(
+        default_scenario_unit_speeds(SEED),
+        default_scenario_unit_speeds(SEED + 1)
+    );
+}
+
+#[test]
+fn starting_tasks_are_done_in_order() {
+    let mut scenario = Scenario::new(SEED)
+        .structure(chest("from", 2, 2).with_items([(ItemId::ROCK, 10)]))
+        .structure(chest("to", -2, 2))
+        .unit(
+            ScenarioUnit::new("unit", IVec2::new(0, 0))
+                .with_speed(2)
+                .with_tasks([
+                    ScenarioTask::MoveTo(IVec2::new(2, 1)),
+                    ScenarioTask::Take {
+                        kind: ItemId::ROCK,
+                        quantity: 4,
+                        from: "from".into(),
+                    },
+                    ScenarioTask::MoveTo(IVec2::new(-2, 1)),
+                    ScenarioTask::Drop {
+                        kind: ItemId::ROCK,
+                        quantity: 4,
+                        to: "to".into(),
+                    },
+                    ScenarioTask::MoveTo(IVec2::new(0, -3)),
+                ]),
+        )
+        .app();
+
+    assert!(scenario.run_until(500, |scenario| {
+        scenario.rounded_tile_pos("unit") == IVec2::new(0, -3)
+    }));
+    assert_eq!(scenario.count("from", ItemId::ROCK), 6);
+    assert_eq!(scenario.count("to", ItemId::ROCK), 4);
+    assert_eq!(scenario.count("unit", ItemId::ROCK), 0);
+}
+
+#[test]
+fn delivery_is_split_in_trips_the_unit_can_carry() {
+    let mut scenario = Scenario::new(SEED)
+        .structure(
+            chest("provider", 3, 0)
+                .with_items([(ItemId::ROCK, 100)])
+                .with_logistics(LogisticsChest::provider()),
+        )
+        .structure(chest("target", -3, 0))
+        // 4 rocks
+        .unit(
+            ScenarioUnit::new("unit", IVec2::new(0, 0))
+                .with_speed(2)
+                .with_capacity(InventoryCapacity::Weight(8.0)),
+        )
+        .app();
+    let target = scenario.entity("target");
+    scenario.give_task(
+        "unit",
+        Task::sequence(vec![
+            get_items(ItemId::ROCK, 10),
+            Task::new(
+                TaskKind::DeliverItems {
+                    kind: ItemId::ROCK,
+                    quantity: 10,
+                    to: target,
+                },
+                Vec::new(),
+            ),
+        ]),
+    );
+
+    let mut max_carried = 0;
+    for _ in 0..2000 {
+        scenario.run_ticks(1);
+        max_carried = max_carried.max(scenario.count("unit", ItemId::ROCK));
+        if scenario.task_status("unit").is_none() {
+            break;
+        }
+    }
+    assert!(scenario.task_status("unit").is_none());
+    assert_eq!(max_carried, 4);
+    assert_eq!(scenario.count("target", ItemId::ROCK), 10);
+    assert_eq!(scenario.count("provider", ItemId::ROCK), 90);
+    assert_eq!(scenario.count("unit", ItemId::ROCK), 0);
+    assert!(scenario.failures().is_empty());
+}
+
+#[test]
+fn get_items_bigger_than_the_inventory_fails() {
+    let mut scenario = Scenario::new(SEED)
+        .structure(
+            chest("provider", 3, 0)
+                .with_items([(ItemId::ROCK, 100)])
+                .with_logistics(LogisticsChest::provider()),
+        )
+        .unit(
+            ScenarioUnit::new("unit", IVec2::new(0, 0))
+                .with_capacity(InventoryCapacity::Weight(8.0)),
+        )
+        .app();
+    scenario.give_task("unit", get_items(ItemId::ROCK, 10));
+
+    assert!(scenario.run_until(1000, |scenario| !scenario.failures().is_empty()));
+    assert_eq!(scenario.failures()[0].reason, FailureReason::InventoryFull);
+    assert_eq!(scenario.count("provider", ItemId::ROCK), 100);
+}
+
+#[test]
+fn logistics_stops_when_the_requester_is_full() {
+    let mut scenario = Scenario::new(SEED)
+        .structure(
+            chest("provider", 4, 0)
+                .with_items([(ItemId::ROCK, 200)])
+                .with_logistics(LogisticsChest::provider()),
+        )
+        // one stack of 50 rocks
+        .structure(
+            chest("requester", -4, 0)
+                .with_capacity(InventoryCapacity::Slots(1))
+                .with_logistics(LogisticsChest::requester([(ItemId::ROCK, 80)])),
+        )
+        .unit(ScenarioUnit::new("unit 1", IVec2::new(0, 1)).with_speed(1))
+        .unit(ScenarioUnit::new("unit 2", IVec2::new(0, -1)).with_speed(1))
+        .app();
+
+    assert!(scenario.run_until(4000, |scenario| {
+        scenario.count("requester", ItemId::ROCK) == 50
+    }));
+    scenario.run_ticks(300);
+    assert_eq!(scenario.count("requester", ItemId::ROCK), 50);
+    assert_eq!(scenario.count("provider", ItemId::ROCK), 150);
+    assert!(scenario.failures().is_empty());
+}
+
+#[test]
+fn unique_item_is_moved_between_chests() {
+    let mut scenario = Scenario::new(SEED)
+        .structure(chest("armory", 2, 2).with_unique_items([UniqueItemKind::IronSword]))
+        .structure(chest("barracks", -2, 2))
+        .unit(ScenarioUnit::new("unit", IVec2::new(0, 0)).with_speed(2))
+        .app();
+    let sword = scenario.unique_items("armory", UniqueItemKind::IronSword)[0];
+    let (armory, barracks) = (scenario.entity("armory"), scenario.entity("barracks"));
+    scenario.give_task(
+        "unit",
+        Task::sequence(vec![
+            Task::new(
+                TaskKind::Action(Action::MoveTo(IVec2::new(2, 1))),
+                Vec::new(),
+            ),
+            Task::new(
+                TaskKind::Action(Action::TakeUnique {
+                    item: sword,
+                    from: armory,
+                }),
+                Vec::new(),
+            ),
+            Task::new(
+                TaskKind::Action(Action::MoveTo(IVec2::new(-2, 1))),
+                Vec::new(),
+            ),
+            Task::new(
+                TaskKind::Action(Action::DropUnique {
+                    item: sword,
+                    to: barracks,
+                }),
+                Vec::new(),
+            ),
+        ]),
+    );
+
+    assert!(scenario.run_until(500, |scenario| scenario.task_status("unit").is_none()));
+    assert!(scenario.failures().is_empty());
+    assert!(
+        scenario
+            .unique_items("armory", UniqueItemKind::IronSword)
+            .is_empty()
+    );
+    assert_eq!(
+        scenario.unique_items("barracks", UniqueItemKind::IronSword),
+        vec![sword]
+    );
+    assert_eq!(
+        scenario.transfers(),
+        [
+            ItemsTransferred {
+                from: armory,
+                to: scenario.entity("unit"),
+                item: TransferItem::Unique(sword),
+            },
+            ItemsTransferred {
+                from: scenario.entity("unit"),
+                to: barracks,
+                item: TransferItem::Unique(sword),
+            },
+        ]
+    );
+}
+
+#[test]
+fn tool_breaks_when_its_durability_runs_out() {
+    let mut scenario = Scenario::new(SEED)
+        .structure(ScenarioStructure::new(
+            "crafter",
+            StructureKind::Crafter,
+            IVec2::new(2, 0),
+        ))
+        .unit(
+            ScenarioUnit::new("unit", IVec2::new(-2, 0))
+                .with_speed(2)
+                .with_items([(ItemId::ROCK, 6)])
+                .with_unique_items([UniqueItemKind::Hammer]),
+        )
+        .app();
+    let hammer = scenario.unique_items("unit", UniqueItemKind::Hammer)[0];
+    // two uses left
+    scenario
+        .app
+        .world_mut()
+        .get_mut::<Durability>(hammer)
+        .unwrap()
+        .current = 2;
+    let crafter = scenario.entity("crafter");
+    let craft_bricks = |quantity| {
+        Task::sequence(vec![
+            Task::new(
+                TaskKind::Action(Action::MoveTo(IVec2::new(1, 0))),
+                Vec::new(),
+            ),
+            Task::new(
+                TaskKind::Action(Action::Craft {
+                    recipe: CraftRecipeId::Brick,
+                    quantity,
+                    with: crafter,
+                }),
+                Vec::new(),
+            ),
+        ])
+    };
+    scenario.give_task("unit", craft_bricks(2));
+
+    assert!(scenario.run_until(500, |scenario| scenario.task_status("unit").is_none()));
+    assert!(scenario.failures().is_empty());
+    assert_eq!(scenario.count("unit", ItemId::new("brick")), 2);
+    assert!(
+        scenario
+            .unique_items("unit", UniqueItemKind::Hammer)
+            .is_empty()
+    );
+    assert!(scenario.app.world().get_entity(hammer).is_err());
+
+    // no hammer anymore
+    scenario.give_task("unit", craft_bricks(1));
+    assert!(scenario.run_until(1000, |scenario| !scenario.failures().is_empty()));
+    assert_eq!(scenario.failures()[0].reason, FailureReason::NotEnoughItems);
+    assert_eq!(scenario.count("unit", ItemId::ROCK), 2);
+}
+
+#[test]
+fn craft_stops_when_its_tool_breaks() {
+    let mut scenario = Scenario::new(SEED)
+        .structure(ScenarioStructure::new(
+            "crafter",
+            StructureKind::Crafter,
+            IVec2::new(1, 0),
+        ))
+        .unit(
+            ScenarioUnit::new("unit", IVec2::new(0, 0))
+                .with_items([(ItemId::ROCK, 6)])
+                .with_unique_items([UniqueItemKind::Hammer]),
+        )
+        .app();
+    let hammer = scenario.unique_items("unit", UniqueItemKind::Hammer)[0];
+    // two uses left for three bricks
+    scenario
+        .app
+        .world_mut()
+        .get_mut::<Durability>(hammer)
+        .unwrap()
+        .current = 2;
+    let crafter = scenario.entity("crafter");
+    scenario.give_task(
+        "unit",
+        Task::new(
+            TaskKind::Action(Action::Craft {
+                recipe: CraftRecipeId::Brick,
+                quantity: 3,
+                with: crafter,
+            }),
+            Vec::new(),
+        ),
+    );
+
+    assert!(scenario.run_until(500, |scenario| !scenario.failures().is_empty()));
+    assert_eq!(scenario.failures()[0].reason, FailureReason::ToolBroken);
+    assert_eq!(scenario.count("unit", ItemId::new("brick")), 2);
+    assert_eq!(scenario.count("unit", ItemId::ROCK), 2);
+    assert!(scenario.app.world().get_entity(hammer).is_err());
+}
+
+#[test]
+fn craft_waits_for_room_to_give_back_its_inputs() {
+    let mut scenario = Scenario::new(SEED)
+        .structure(ScenarioStructure::new(
+            "crafter",
+            StructureKind::Crafter,
+            IVec2::new(1, 0),
+        ))
+        .unit(
+            ScenarioUnit::new("unit", IVec2::new(0, 0))
+                .with_capacity(InventoryCapacity::Slots(1))
+                .with_items([(ItemId::ROCK, 10)]),
+        )
+        .app();
+    let (unit, crafter) = (scenario.entity("unit"), scenario.entity("crafter"));
+    scenario.give_task(
+        "unit",
+        Task::new(
+            TaskKind::Action(Action::Craft {
+                recipe: CraftRecipeId::Chest,
+                quantity: 1,
+                with: crafter,
+            }),
+            Vec::new(),
+        ),
+    );
+    assert!(scenario.run_until(10, |scenario| { scenario.count("unit", ItemId::ROCK) == 0 }));
+    // the only slot is taken before the chest is crafted
+    let brick = ItemId::new("brick");
+    scenario
+        .app
+        .world_mut()
+        .get_mut::<Inventory>(unit)
+        .unwrap()
+        .stackable_items
+        .insert(brick, 1);
+
+    assert!(scenario.run_until(1500, |scenario| !scenario.failures().is_empty()));
+    assert_eq!(scenario.failures()[0].reason, FailureReason::InventoryFull);
+    assert_eq!(scenario.count("unit", ItemId::CHEST), 0);
+    assert!(
+        scenario
+            .app
+            .world()
+            .get::<Crafter>(crafter)
+            .unwrap()
+            .progress
+            .is_some()
+    );
+
+    scenario
+        .app
+        .world_mut()
+        .get_mut::<Inventory>(unit)
+        .unwrap()
+        .stackable_items
+        .remove(&brick);
+    scenario.run_ticks(1);
+    assert_eq!(scenario.count("unit", ItemId::ROCK), 10);
+    assert!(
+        scenario
+            .app
+            .world()
+            .get::<Crafter>(crafter)
+            .unwrap()
+            .progress
+            .is_none()
+    );
+}
+
+#[test]
+fn drop_moves_only_what_fits() {
+    let mut scenario = Scenario::new(SEED)
+        // one stack of 50 rocks
+        .structure(chest("small", 2, 0).with_capacity(InventoryCapacity::Slots(1)))
+        .unit(
+            ScenarioUnit::new("unit", IVec2::new(-2, 0))
+                .with_speed(2)
+                .with_capacity(InventoryCapacity::Unlimited)
+                .with_items([(ItemId::ROCK, 70)])
+                .with_tasks([
+                    ScenarioTask::MoveTo(IVec2::new(1, 0)),
+                    ScenarioTask::Drop {
+                        kind: ItemId::ROCK,
+                        quantity: 70,
+                        to: "small".into(),
+                    },
+                ]),
+        )
+        .app();
+
+    assert!(scenario.run_until(300, |scenario| !scenario.transfers().is_empty()));
+    assert_eq!(
+        scenario.transfers()[0].item,
+        TransferItem::Stackable {
+            kind: ItemId::ROCK,
+            quantity: 50,
+        }
+    );
+    assert_eq!(scenario.count("small", ItemId::ROCK), 50);
+    assert_eq!(scenario.count("unit", ItemId::ROCK), 20);
+
+    // the remaining 20 rocks don't fit, even after the retries
+    assert!(scenario.run_until(1500, |scenario| !scenario.failures().is_empty()));
+    assert_eq!(scenario.failures()[0].reason, FailureReason::InventoryFull);
+    assert_eq!(scenario.transfers().len(), 1);
+    assert_eq!(scenario.count("unit", ItemId::ROCK), 20);
+}
+
+#[test]
+fn drop_finishes_once_the_chest_has_room() {
+    let mut scenario = Scenario::new(SEED)
+        .structure(chest("small", 2, 0).with_capacity(InventoryCapacity::Slots(1)))
+        .unit(
+            ScenarioUnit::new("unit", IVec2::new(-2, 0))
+                .with_speed(2)
+                .with_capacity(InventoryCapacity::Unlimited)
+                .with_items([(ItemId::ROCK, 70)])
+                .with_tasks([
+                    ScenarioTask::MoveTo(IVec2::new(1, 0)),
+                    ScenarioTask::Drop {
+                        kind: ItemId::ROCK,
+                        quantity: 70,
+                        to: "small".into(),
+                    },
+                ]),
+        )
+        .app();
+    let small = scenario.entity("small");
+
+    assert!(scenario.run_until(300, |scenario| !scenario.transfers().is_empty()));
+    // the chest is emptied before the unit drops the rest
+    scenario
+        .app
+        .world_mut()
+        .get_mut::<Inventory>(small)
+        .unwrap()
+        .stackable_items
+        .clear();
+    scenario.run_ticks(1);
+
+    assert_eq!(scenario.count("small", ItemId::ROCK), 20);
+    assert_eq!(scenario.count("unit", ItemId::ROCK), 0);
+    assert!(scenario.failures().is_empty());
+}
+
+fn structure_at(scenario: &ScenarioApp, rounded_tile_pos: IVec2) -> Option<Entity> {
+    scenario
+        .app
+        .world()
+        .resource::<StructureManager>()
+        .structures
+        .get(&rounded_tile_pos)
+        .copied()
+}
+
+fn blueprint_at(scenario: &ScenarioApp, rounded_tile_pos: IVec2) -> Option<Entity> {
+    scenario
+        .app
+        .world()
+        .resource::<BlueprintManager>()
+        .blueprints
+        .get(&rounded_tile_pos)
+        .copied()
+}
+
+#[test]
+fn build_commands_place_blueprints_and_deconstruct_structures() {
+    let mut scenario = Scenario::new(SEED)
+        .structure(chest("chest", 3, 0).with_unique_items([UniqueItemKind::IronSword]))
+        .unit(
+            ScenarioUnit::new("unit", IVec2::new(0, 0)).with_capabilities(Capabilities::default()),
+        )
+        .app();
+    let sword = scenario.unique_items("chest", UniqueItemKind::IronSword)[0];
+
+    let world = scenario.app.world_mut();
+    world.send_event(BuildCommand::Place {
+        kind: StructureKind::Wall,
+        rounded_tile_pos: IVec2::new(-2, 0),
+    });
+    // the unit is on this tile
+    world.send_event(BuildCommand::Place {
+        kind: StructureKind::Wall,
+        rounded_tile_pos: IVec2::new(0, 0),
+    });
+    // already a chest there
+    world.send_event(BuildCommand::Place {
+        kind: StructureKind::Crafter,
+        rounded_tile_pos: IVec2::new(3, 0),
+    });
+    scenario.run_ticks(1);
+
+    // the wall waits for a builder
+    assert!(blueprint_at(&scenario, IVec2::new(-2, 0)).is_some());
+    assert!(structure_at(&scenario, IVec2::new(-2, 0)).is_none());
+    assert!(blueprint_at(&scenario, IVec2::new(0, 0)).is_none());
+    assert!(blueprint_at(&scenario, IVec2::new(3, 0)).is_none());
+    assert_eq!(
+        structure_at(&scenario, IVec2::new(3, 0)),
+        Some(scenario.entity("chest"))
+    );
+    assert_eq!(scenario.app.world().resource::<JobBoard>().jobs.len(), 1);
+
+    let world = scenario.app.world_mut();
+    world.send_event(BuildCommand::Deconstruct {
+        rounded_tile_pos: IVec2::new(3, 0),
+    });
+    world.send_event(BuildCommand::Deconstruct {
+        rounded_tile_pos: IVec2::new(-2, 0),
+    });
+    scenario.run_ticks(1);
+    assert!(blueprint_at(&scenario, IVec2::new(-2, 0)).is_none());
+    // the sword is still in the chest
+    assert_eq!(
+        structure_at(&scenario, IVec2::new(3, 0)),
+        Some(scenario.entity("chest"))
+    );
+
+    let chest = scenario.entity("chest");
+    let world = scenario.app.world_mut();
+    world
+        .get_mut::<Inventory>(chest)
+        .unwrap()
+        .remove_unique_item(sword);
+    world.send_event(BuildCommand::Deconstruct {
+        rounded_tile_pos: IVec2::new(3, 0),
+    });
+    scenario.run_ticks(1);
+    assert!(structure_at(&scenario, IVec2::new(3, 0)).is_none());
+    assert!(scenario.app.world().get_entity(chest).is_err());
+    assert!(scenario.app.world().get_entity(sword).is_ok());
+}
+
+#[test]
+fn blueprint_needs_a_free_tile_next_to_it() {
+    let mut scenario = Scenario::new(SEED);
+    // only (5, 0) is left around (6, 0)
+    for (index, neighbor) in [
+        IVec2::new(5, 1),
+        IVec2::new(5, -1),
+        IVec2::new(6, 1),
+        IVec2::new(6, -1),
+        IVec2::new(7, 1),
+        IVec2::new(7, 0),
+        IVec2::new(7, -1),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        scenario = scenario.structure(ScenarioStructure::new(
+            &format!("wall {}", index),
+            StructureKind::Wall,
+            neighbor,
+        ));
+    }
+    let mut scenario = scenario
+        .unit(ScenarioUnit::new("unit", IVec2::new(0, 0)))
+        .app();
+
+    let world = scenario.app.world_mut();
+    world.send_event(BuildCommand::Place {
+        kind: StructureKind::Wall,
+        rounded_tile_pos: IVec2::new(5, 0),
+    });
+    world.send_event(BuildCommand::Place {
+        kind: StructureKind::Wall,
+        rounded_tile_pos: IVec2::new(6, 0),
+    });
+    scenario.run_ticks(1);
+    assert!(blueprint_at(&scenario, IVec2::new(5, 0)).is_some());
+    // the builder could only stand on the other blueprint
+    assert!(blueprint_at(&scenario, IVec2::new(6, 0)).is_none());
+    assert_eq!(scenario.app.world().resource::<JobBoard>().jobs.len(), 1);
+}
+
+#[test]
+fn units_build_blueprints_with_materials() {
+    let mut scenario = Scenario::new(SEED)
+        .structure(
+            chest("provider", 3, 2)
+                .with_items([(ItemId::ROCK, 10)])
+                .with_logistics(LogisticsChest::provider()),
+        )
+        .unit(ScenarioUnit::new("unit", IVec2::new(0, 0)).with_speed(2))
+        .app();
+    scenario.app.world_mut().send_event(BuildCommand::Place {
+        kind: StructureKind::Wall,
+        rounded_tile_pos: IVec2::new(-3, 0),
+    });
+
+    assert!(scenario.run_until(1000, |scenario| {
+        structure_at(scenario, IVec2::new(-3, 0)).is_some()
+    }));
+    let wall = structure_at(&scenario, IVec2::new(-3, 0)).unwrap();
+    assert!(scenario.app.world().get::<Wall>(wall).is_some());
+    assert!(blueprint_at(&scenario, IVec2::new(-3, 0)).is_none());
+    assert_eq!(scenario.count("provider", ItemId::ROCK), 8);
+    assert_eq!(scenario.count("unit", ItemId::ROCK), 0);
+    assert_ne!(scenario.rounded_tile_pos("unit"), IVec2::new(-3, 0));
+    assert!(scenario.failures().is_empty());
+}
+
+#[test]
+fn deconstructed_blueprint_cancels_its_job_and_gives_back_its_materials() {
+    let mut scenario = Scenario::new(SEED)
+        .structure(
+            chest("provider", 3, 2)
+                .with_items([(ItemId::ROCK, 10)])
+                .with_logistics(LogisticsChest::provider()),
+        )
+        .unit(ScenarioUnit::new("unit", IVec2::new(0, 0)).with_speed(2))
+        .app();
+    scenario.app.world_mut().send_event(BuildCommand::Place {
+        kind: StructureKind::Wall,
+        rounded_tile_pos: IVec2::new(-3, 0),
+    });
+    scenario.run_ticks(1);
+    let blueprint = blueprint_at(&scenario, IVec2::new(-3, 0)).unwrap();
+
+    assert!(scenario.run_until(1000, |scenario| {
+        scenario
+            .app
+            .world()
+            .get::<Blueprint>(blueprint)
+            .is_some_and(|blueprint| blueprint.materials_delivered)
+    }));
+    scenario
+        .app
+        .world_mut()
+        .send_event(BuildCommand::Deconstruct {
+            rounded_tile_pos: IVec2::new(-3, 0),
+        });
+    scenario.run_ticks(2);
+
+    assert!(blueprint_at(&scenario, IVec2::new(-3, 0)).is_none());
+    assert!(scenario.task_status("unit").is_none());
+    assert!(scenario.app.world().resource::<JobBoard>().jobs.is_empty());
+    assert_eq!(scenario.count("provider", ItemId::ROCK), 10);
+    assert_eq!(scenario.count("unit", ItemId::ROCK), 0);
+}
+
+#[test]
+fn blueprint_without_job_gets_a_new_one() {
+    let mut scenario = Scenario::new(SEED)
+        .unit(
+            ScenarioUnit::new("unit", IVec2::new(0, 0)).with_capabilities(Capabilities::default()),
+        )
+        .app();
+    scenario.app.world_mut().send_event(BuildCommand::Place {
+        kind: StructureKind::Wall,
+        rounded_tile_pos: IVec2::new(-3, 0),
+    });
+    scenario.run_ticks(1);
+    assert_eq!(scenario.app.world().resource::<JobBoard>().jobs.len(), 1);
+
+    // as if it was dropped after failing too many times
+    scenario
+        .app
+        .world_mut()
+        .resource_mut::<JobBoard>()
+        .jobs
+        .clear();
+    scenario.run_ticks(1);
+    assert_eq!(scenario.app.world().resource::<JobBoard>().jobs.len(), 1);
+}
+
+#[test]
+fn orders_only_apply_to_selected_units() {
+    let mut scenario = Scenario::new(SEED)
+        .structure(chest("from", 3, 2).with_items([(ItemId::ROCK, 6)]))
+        .structure(chest("to", -3, 2))
+        .unit(ScenarioUnit::new("selected", IVec2::new(0, 0)).with_speed(2))
+        .unit(ScenarioUnit::new("idle", IVec2::new(0, -2)).with_speed(2))
+        .app();
+    let selected = scenario.entity("selected");
+    scenario
+        .app
+        .world_mut()
+        .entity_mut(selected)
+        .insert(Selected);
+
+    let (from, to) = (scenario.entity("from"), scenario.entity("to"));
+    scenario
+        .app
+        .world_mut()
+        .send_event(SelectionOrder(Order::Fetch { from }));
+    scenario.run_ticks(1);
+    assert!(scenario.run_until(500, |scenario| scenario.task_status("selected").is_none()));
+    assert_eq!(scenario.count("selected", ItemId::ROCK), 6);
+    assert_eq!(scenario.count("from", ItemId::ROCK), 0);
+
+    scenario
+        .app
+        .world_mut()
+        .send_event(SelectionOrder(Order::Deliver { to }));
+    scenario.run_ticks(1);
+    assert!(scenario.run_until(500, |scenario| scenario.task_status("selected").is_none()));
+    assert_eq!(scenario.count("to", ItemId::ROCK), 6);
+
+    scenario
+        .app
+        .world_mut()
+        .send_event(SelectionOrder(Order::MoveTo(IVec2::new(4, -3))));
+    scenario.run_ticks(1);
+    assert!(scenario.run_until(500, |scenario| scenario.task_status("selected").is_none()));
+    assert_eq!(scenario.rounded_tile_pos("selected"), IVec2::new(4, -3));
+    assert_eq!(scenario.rounded_tile_pos("idle"), IVec2::new(0, -2));
+    assert!(scenario.failures().is_empty());
+}
+
+fn generated_tiles(scenario: &ScenarioApp) -> HashSet<IVec2> {
+    let world = scenario.app.world();
+    world
+        .resource::<StructureManager>()
+        .structures
+        .iter()
+        .filter(|(_, structure_ent)| world.get::<Generated>(**structure_ent).is_some())
+        .map(|(rounded_tile_pos, _)| *rounded_tile_pos)
+        .collect()
+}
+
+fn teleport(scenario: &mut ScenarioApp, name: &str, rounded_tile_pos: IVec2) {
+    let unit = scenario.entity(name);
+    scenario
+        .app
+        .world_mut()
+        .get_mut::<Transform>(unit)
+        .unwrap()
+        .translation = rounded_tile_pos_to_world(rounded_tile_pos).extend(0.0);
+}
+
+#[test]
+fn reloaded_chunks_keep_the_changes_of_the_player() {
+    let mut scenario = Scenario::new(SEED)
+        .structure(chest("chest", 3, 0))
+        .unit(ScenarioUnit::new("unit", IVec2::new(0, 0)))
+        .app();
+    let chest_ent = scenario.entity("chest");
+    scenario.run_ticks(1);
+    let generated = generated_tiles(&scenario);
+    let removed = *generated
+        .iter()
+        .min_by_key(|tile| (tile.x, tile.y))
+        .unwrap();
+
+    // nothing changed
+    teleport(&mut scenario, "unit", IVec2::new(10_000, 0));
+    scenario.run_ticks(1);
+    assert!(generated_tiles(&scenario).is_disjoint(&generated));
+    teleport(&mut scenario, "unit", IVec2::new(0, 0));
+    scenario.run_ticks(1);
+    assert_eq!(generated_tiles(&scenario), generated);
+    assert_eq!(structure_at(&scenario, IVec2::new(3, 0)), Some(chest_ent));
+
+    scenario
+        .app
+        .world_mut()
+        .send_event(BuildCommand::Deconstruct {
+            rounded_tile_pos: removed,
+        });
+    scenario.run_ticks(1);
+    assert!(structure_at(&scenario, removed).is_none());
+
+    teleport(&mut scenario, "unit", IVec2::new(10_000, 0));
+    scenario.run_ticks(1);
+    teleport(&mut scenario, "unit", IVec2::new(0, 0));
+    scenario.run_ticks(1);
+    let mut expected = generated.clone();
+    expected.remove(&removed);
+    assert_eq!(generated_tiles(&scenario), expected);
+    assert!(structure_at(&scenario, removed).is_none());
+    assert_eq!(structure_at(&scenario, IVec2::new(3, 0)), Some(chest_ent));
+}
+
+// texture of every tile of the chunk and its generated structures (true for a wall), after
+// spawning the chunks in this order
+fn spawn_chunks(order: &[IVec2], rounded_chunk_pos: IVec2) -> (Vec<u32>, Vec<(IVec2, bool)>) {
+    let mut scenario = Scenario::new(SEED).app();
+    let world = scenario.app.world_mut();
+    let order = order.to_vec();
+    let chunks = world
+        .run_system_once(
+            move |mut commands: Commands,
+                  asset_server: Res<AssetServer>,
+                  mut structure_manager: ResMut<StructureManager>,
+                  world_seed: Res<WorldSeed>| {
+                order
+                    .iter()
+                    .map(|&chunk_pos| {
+                        let chunk = spawn_chunk(
+                            &mut commands,
+                            &asset_server,
+                            &mut structure_manager,
+                            &world_seed,
+                            chunk_pos,
+                        );
+                        (chunk_pos, chunk)
+                    })
+                    .collect::<HashMap<IVec2, Entity>>()
+            },
+        )
+        .unwrap();
+
+    let tiles = world
+        .get::<TileStorage>(chunks[&rounded_chunk_pos])
+        .unwrap()
+        .iter()
+        .map(|tile| world.get::<TileTextureIndex>(tile.unwrap()).unwrap().0)
+        .collect();
+    let mut structures: Vec<(IVec2, bool)> = world
+        .resource::<StructureManager>()
+        .structures
+        .iter()
+        .filter(|(rounded_tile_pos, structure_ent)| {
+            rounded_tile_pos_to_rounded_chunk(**rounded_tile_pos) == rounded_chunk_pos
+                && world.get::<Generated>(**structure_ent).is_some()
+        })
+        .map(|(rounded_tile_pos, structure_ent)| {
+            (
+                *rounded_tile_pos,
+                world.get::<Wall>(*structure_ent).is_some(),
+            )
+        })
+        .collect();
+    structures.sort_by_key(|(tile, _)| (tile.x, tile.y));
+    (tiles, structures)
+}
+
+#[test]
+fn chunk_is_generated_the_same_whatever_the_load_order() {
+    let chunk = IVec2::new(0, 0);
+    let direct = spawn_chunks(&[chunk], chunk);
+    assert!(direct.1.iter().any(|(_, wall)| *wall));
+    assert!(direct.1.iter().any(|(_, wall)| !*wall));
+
+    let neighbours_first = [IVec2::X, IVec2::NEG_Y, IVec2::NEG_X, IVec2::Y, IVec2::ZERO];
+    let diagonals_first = [
+        IVec2::ONE,
+        IVec2::NEG_X,
+        IVec2::NEG_ONE,
+        IVec2::new(1, -1),
+        IVec2::Y,
+        IVec2::X,
+        IVec2::ZERO,
+        IVec2::NEG_Y,
+        IVec2::new(-1, 1),
+    ];
+    for order in [&neighbours_first[..], &diagonals_first[..]] {
+        let order: Vec<IVec2> = order.iter().map(|offset| chunk + *offset).collect();
+        assert_eq!(spawn_chunks(&order, chunk), direct);
+    }
+}
+
+fn save(scenario: &mut ScenarioApp) -> String {
+    let save = scenario
+        .app
+        .world_mut()
+        .run_system_once(save_system)
+        .unwrap();
+    save_to_string(&save).unwrap()
+}
+
+fn load(scenario: &mut ScenarioApp, text: &str) {
+    let save = save_from_str(text).unwrap();
+    scenario
+        .app
+        .world_mut()
+        .run_system_once_with(load_system, save)
+        .unwrap();
+}
+
+/// the ScenarioEntities aren't updated by a load
+fn unit_named(scenario: &mut ScenarioApp, name: &str) -> Entity {
+    let world = scenario.app.world_mut();
+    let mut unit_query = world.query::<(Entity, &Unit)>();
+    unit_query
+        .iter(world)
+        .find(|(_, unit)| unit.name == name)
+        .map(|(unit_ent, _)| unit_ent)
+        .unwrap()
+}
+
+fn unit_actions(scenario: &ScenarioApp, unit_ent: Entity) -> Vec<Action> {
+    let world = scenario.app.world();
+    let current_action = world.get::<CurrentAction>(unit_ent).unwrap();
+    let action_queue = world.get::<ActionQueue>(unit_ent).unwrap();
+    current_action
+        .action
+        .iter()
+        .chain(action_queue.0.iter())
+        .copied()
+        .collect()
+}
+
+#[test]
+fn loaded_save_points_at_the_new_entities() {
+    let not_building = Capabilities(HashSet::from([Capability::Hauling, Capability::Crafting]));
+    let mut scenario = Scenario::new(SEED)
+        .structure(
+            chest("from", 6, 0)
+                .with_items([(ItemId::ROCK, 20)])
+                .with_logistics(LogisticsChest::provider()),
+        )
+        .structure(ScenarioStructure::new(
+            "crafter",
+            StructureKind::Crafter,
+            IVec2::new(-3, 3),
+        ))
+        .unit(
+            ScenarioUnit::new("worker", IVec2::new(0, 0))
+                .with_speed(4)
+                .with_unique_items([UniqueItemKind::IronSword])
+                .with_capabilities(not_building.clone()),
+        )
+        .unit(
+            ScenarioUnit::new("crafting", IVec2::new(-2, 3))
+                .with_items([(ItemId::ROCK, 10)])
+                .with_capabilities(not_building),
+        )
+        .app();
+    let crafter = scenario.entity("crafter");
+    scenario.give_task("worker", get_items(ItemId::ROCK, 5));
+    scenario.give_task(
+        "crafting",
+        Task::new(
+            TaskKind::Action(Action::Craft {
+                recipe: CraftRecipeId::Chest,
+                quantity: 1,
+                with: crafter,
+            }),
+            Vec::new(),
+        ),
+    );
+    // nobody can build it, so its job stays on the board
+    scenario.app.world_mut().send_event(BuildCommand::Place {
+        kind: StructureKind::Wall,
+        rounded_tile_pos: IVec2::new(0, -5),
+    });
+    assert!(scenario.run_until(20, |scenario| {
+        !scenario
+            .app
+            .world()
+            .resource::<Reservations>()
+            .reserved
+            .is_empty()
+            && scenario
+                .app
+                .world()
+                .get::<Crafter>(crafter)
+                .unwrap()
+                .progress
+                .is_some()
+    }));
+    let text = save(&mut scenario);
+    let old_entities = ["worker", "crafting", "from", "crafter"].map(|name| scenario.entity(name));
+
+    let mut loaded = Scenario::new(SEED).app();
+    load(&mut loaded, &text);
+    let worker = unit_named(&mut loaded, "worker");
+    let crafting = unit_named(&mut loaded, "crafting");
+    let from = structure_at(&loaded, IVec2::new(6, 0)).unwrap();
+    let crafter = structure_at(&loaded, IVec2::new(-3, 3)).unwrap();
+    let blueprint = blueprint_at(&loaded, IVec2::new(0, -5)).unwrap();
+    for entity in [worker, crafting, from, crafter] {
+        assert!(!old_entities.contains(&entity));
+    }
+    let world = loaded.app.world();
+
+    let reserved = &world.resource::<Reservations>().reserved;
+    assert_eq!(reserved.len(), 1);
+    assert_eq!(
+        reserved[&from].keys().copied().collect::<Vec<Entity>>(),
+        vec![worker]
+    );
+    assert!(
+        unit_actions(&loaded, worker)
+            .iter()
+            .any(|action| matches!(action, Action::Take { from: to_take, .. } if *to_take == from))
+    );
+    assert_eq!(
+        world
+            .get::<Crafter>(crafter)
+            .unwrap()
+            .progress
+            .as_ref()
+            .unwrap()
+            .owner,
+        crafting
+    );
+    let craft_task = world
+        .get::<CurrentTask>(crafting)
+        .unwrap()
+        .task
+        .as_ref()
+        .unwrap();
+    assert!(matches!(
+        craft_task.kind,
+        TaskKind::Action(Action::Craft { with, .. }) if with == crafter
+    ));
+    let unique_items = &world.get::<Inventory>(worker).unwrap().unique_items;
+    assert_eq!(unique_items.len(), 1);
+    assert_eq!(
+        world.get::<UniqueItemKind>(unique_items[0]),
+        Some(&UniqueItemKind::IronSword)
+    );
+    let job_board = world.resource::<JobBoard>();
+    assert_eq!(job_board.jobs.len(), 1);
+    assert!(job_board.jobs[0].task.iter().any(|task| matches!(
+        task.kind,
+        TaskKind::Action(Action::Build { blueprint: to_build }) if to_build == blueprint
+    )));
+
+    // and the loaded game goes on
+    let rocks = |loaded: &ScenarioApp, entity: Entity| {
+        loaded
+            .app
+            .world()
+            .get::<Inventory>(entity)
+            .unwrap()
+            .count(&ItemId::ROCK)
+    };
+    assert!(loaded.run_until(600, |loaded| rocks(loaded, worker) == 5));
+    assert_eq!(rocks(&loaded, from), 15);
+}
+
+#[test]
+fn load_despawns_the_structures_of_unloaded_chunks() {
+    let mut scenario = Scenario::new(SEED)
+        .structure(chest("chest", 3, 0).with_items([(ItemId::ROCK, 10)]))
+        .unit(ScenarioUnit::new("unit", IVec2::new(0, 0)))
+        .app();
+    let text = save(&mut scenario);
+    let old_chest = scenario.entity("chest");
+
+    // the chest is kept out of its unloaded chunk
+    teleport(&mut scenario, "unit", IVec2::new(10_000, 0));
+    scenario.run_ticks(1);
+    assert!(scenario.app.world().get_entity(old_chest).is_ok());
+    load(&mut scenario, &text);
+    scenario.run_ticks(1);
+
+    let world = scenario.app.world_mut();
+    assert!(world.get_entity(old_chest).is_err());
+    let chests: Vec<Entity> = world
+        .query_filtered::<Entity, With<Chest>>()
+        .iter(world)
+        .collect();
+    assert_eq!(chests.len(), 1);
+    assert_eq!(structure_at(&scenario, IVec2::new(3, 0)), Some(chests[0]));
+    let inventory = scenario.app.world().get::<Inventory>(chests[0]).unwrap();
+    assert_eq!(inventory.count(&ItemId::ROCK), 10);
+}
+
+#[test]
+fn unit_walks_around_a_wall_crossing_several_chunks() {
+    // wall on x = 40 from y = -100 to 100 ; the target is in the water from x = 80, so a road
+    // is laid up to it
+    let scenario = (77..=80).fold(
+        Scenario::new(SEED).unit(ScenarioUnit::new("unit", IVec2::new(0, 0)).with_speed(1)),
+        |scenario, x| scenario.ground(IVec2::new(x, 0), TileKind::Road),
+    );
+    let mut scenario = (-100..=100)
+        .fold(scenario, |scenario, y| {
+            scenario.structure(ScenarioStructure::new(
+                &format!("wall {}", y),
+                StructureKind::Wall,
+                IVec2::new(40, y),
+            ))
+        })
+        .app();
+    scenario.give_task(
+        "unit",
+        Task::new(
+            TaskKind::Action(Action::MoveTo(IVec2::new(80, 0))),
+            Vec::new(),
+        ),
+    );
+
+    assert!(scenario.run_until(1500, |scenario| scenario.task_status("unit").is_none()));
+    assert_eq!(scenario.rounded_tile_pos("unit"), IVec2::new(80, 0));
+    assert!(scenario.failures().is_empty());
+}
+
+#[test]
+fn units_going_to_the_same_tile_share_a_flow_field() {
+    let target = IVec2::new(30, 5);
+    let mut scenario = (0..10).fold(Scenario::new(SEED), |scenario, i| {
+        scenario
+            .unit(ScenarioUnit::new(&format!("unit {}", i), IVec2::new(-i, i % 3)).with_speed(1))
+    });
+    // a wall between the units and the target
+    scenario = (-8..=8).fold(scenario, |scenario, y| {
+        scenario.structure(ScenarioStructure::new(
+            &format!("wall {}", y),
+            StructureKind::Wall,
+            IVec2::new(15, y),
+        ))
+    });
+    let mut scenario = scenario.app();
+    for i in 0..10 {
+        scenario.give_task(
+            &format!("unit {}", i),
+            Task::new(TaskKind::Action(Action::MoveTo(target)), Vec::new()),
+        );
+    }
+
+    scenario.run_ticks(3);
+    assert!(
+        scenario
+            .app
+            .world()
+            .resource::<FlowFields>()
+            .fields
+            .contains_key(&target)
+    );
+    assert!(scenario.run_until(1000, |scenario| {
+        (0..10).all(|i| scenario.task_status(&format!("unit {}", i)).is_none())
+    }));
+    for i in 0..10 {
+        let unit_tile_pos = scenario.rounded_tile_pos(&format!("unit {}", i));
+        assert!((unit_tile_pos - target).abs().max_element() <= 1);
+    }
+    assert!(scenario.failures().is_empty());
+    // nobody follows it anymore
+    scenario.run_ticks(1);
+    assert!(
+        scenario
+            .app
+            .world()
+            .resource::<FlowFields>()
+            .fields
+            .is_empty()
+    );
+}
+
+#[test]
+fn path_computations_respect_the_budget_per_tick() {
+    let mut scenario = (0..3)
+        .fold(Scenario::new(SEED), |scenario, i| {
+            scenario
+                .unit(ScenarioUnit::new(&format!("unit {}", i), IVec2::new(0, i * 2)).with_speed(1))
+        })
+        .app();
+    scenario
+        .app
+        .world_mut()
+        .resource_mut::<PathfindingSettings>()
+        .max_path_computations_per_tick = 1;
+    for i in 0..3 {
+        scenario.give_task(
+            &format!("unit {}", i),
+            Task::new(
+                TaskKind::Action(Action::MoveTo(IVec2::new(20, i * 2))),
+                Vec::new(),
+            ),
+        );
+    }
+
+    let mut max_waiting = 0;
+    for _ in 0..10 {
+        scenario.run_ticks(1);
+        let world = scenario.app.world_mut();
+        let computations = world.query::<&PathComputation>().iter(world).count();
+        assert!(computations <= 1);
+        max_waiting = max_waiting.max(world.resource::<PathRequests>().queue.len());
+    }
+    assert_eq!(max_waiting, 2);
+    assert!(scenario.run_until(500, |scenario| {
+        (0..3).all(|i| scenario.task_status(&format!("unit {}", i)).is_none())
+    }));
+    for i in 0..3 {
+        assert_eq!(
+            scenario.rounded_tile_pos(&format!("unit {}", i)),
+            IVec2::new(20, i * 2)
+        );
+    }
+}
+
+#[test]
+fn path_is_repaired_around_a_new_wall() {
+    let mut scenario = Scenario::new(SEED)
+        .unit(ScenarioUnit::new("unit", IVec2::new(0, 0)).with_speed(2))
+        .app();
+    scenario.give_task(
+        "unit",
+        Task::new(
+            TaskKind::Action(Action::MoveTo(IVec2::new(20, 0))),
+            Vec::new(),
+        ),
+    );
+    scenario.run_ticks(10);
+    let unit = scenario.entity("unit");
+    let path = &scenario
+        .app
+        .world()
+        .get::<PathfindingAgent>(unit)
+        .unwrap()
+        .path;
+    assert!(path.contains(&IVec2::new(10, 0)));
+
+    // wall across the path, from y = -3 to 3
+    let world = scenario.app.world_mut();
+    for y in -3..=3 {
+        let wall = world.spawn((Structure, Wall)).id();
+        world
+            .resource_mut::<StructureManager>()
+            .insert(IVec2::new(10, y), wall);
+    }
+    scenario.run_ticks(1);
+    let world = scenario.app.world();
+    let path = &world.get::<PathfindingAgent>(unit).unwrap().path;
+    assert!(!path.is_empty());
+    assert!(path.iter().all(|rounded_tile_pos| {
+        !world
+            .resource::<StructureManager>()
+            .structures
+            .contains_key(rounded_tile_pos)
+    }));
+
+    assert!(scenario.run_until(300, |scenario| scenario.task_status("unit").is_none()));
+    assert_eq!(scenario.rounded_tile_pos("unit"), IVec2::new(20, 0));
+    assert!(scenario.failures().is_empty());
+}
+
+// ticks to walk from (0, 0) to (12, 0), and the tiles it went through
+fn walk_with_ground(ground: &[(IVec2, TileKind)]) -> (u32, Vec<IVec2>) {
+    let mut scenario = ground
+        .iter()
+        .fold(
+            Scenario::new(SEED).unit(ScenarioUnit::new("unit", IVec2::new(0, 0)).with_speed(4)),
+            |scenario, (rounded_tile_pos, tile_kind)| {
+                scenario.ground(*rounded_tile_pos, *tile_kind)
+            },
+        )
+        .app();
+    scenario.give_task(
+        "unit",
+        Task::new(
+            TaskKind::Action(Action::MoveTo(IVec2::new(12, 0))),
+            Vec::new(),
+        ),
+    );
+
+    let mut visited = Vec::new();
+    for _ in 0..500 {
+        if scenario.task_status("unit").is_none() {
+            break;
+        }
+        scenario.run_ticks(1);
+        visited.push(scenario.rounded_tile_pos("unit"));
+    }
+    assert!(scenario.task_status("unit").is_none());
+    assert_eq!(scenario.rounded_tile_pos("unit"), IVec2::new(12, 0));
+    assert!(scenario.failures().is_empty());
+    (scenario.ticks, visited)
+}
+
+#[test]
+fn units_prefer_roads_and_walk_faster_on_them() {
+    let (grass_ticks, _) = walk_with_ground(&[]);
+    // road two tiles beside the straight line
+    let road: Vec<(IVec2, TileKind)> = (0..=12)
+        .map(|x| (IVec2::new(x, 2), TileKind::Road))
+        .collect();
+    let (road_ticks, visited) = walk_with_ground(&road);
+
+    assert!(visited.contains(&IVec2::new(6, 2)));
+    assert!(road_ticks < grass_ticks);
+}
+
+#[test]
+fn units_go_around_water() {
+    // lake across the straight line, from y = -3 to 3
+    let lake: Vec<(IVec2, TileKind)> = (-3..=3)
+        .flat_map(|y| [5, 6].map(|x| (IVec2::new(x, y), TileKind::Water)))
+        .collect();
+    let (_, visited) = walk_with_ground(&lake);
+
+    assert!(visited.iter().all(|rounded_tile_pos| {
+        !lake
+            .iter()
+            .any(|(water_pos, _)| water_pos == rounded_tile_pos)
+    }));
+}